@@ -0,0 +1,91 @@
+//! Timings of sorting and formatting large manifests with criterion, run
+//! them with `cargo bench`.
+//!
+//! Sorting a table sorts its entries in one go, a grouped table is drained
+//! into a list that is sorted and put back, for the flat 2,500 entries that
+//! is under 2ms of the check. The time
+//! went into reporting the keys that moved, which compared every key with
+//! the ones before it and counted the lines up to it from the start of the
+//! file. Finding the key it belongs before among the keys that rise above
+//! all before them and a table of line starts took the flat 2,500 entries
+//! from 74ms to 18ms and the grouped ones from 4.5s to 20ms, on the machine
+//! they were first measured on. Looking up the headers of the input in a map
+//! instead of a list took the 2,500 dependency tables from 26ms to 16ms.
+//!
+//! Most of what is left is toml_edit: an unsorted manifest is parsed twice,
+//! once to sort it and once more for the lines of the keys that moved, and
+//! then rendered.
+
+use std::hint::black_box;
+
+use cargo_sort_fix::{Config, check_manifest};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+/// A `[dependencies]` table with `count` entries in reverse order, with a
+/// blank line every `group` entries.
+fn manifest(count: usize, group: usize) -> String {
+    let mut out = String::from("[package]\nname = \"workspace-hack\"\nversion = \"0.1.0\"\n\n[dependencies]\n");
+    for n in (0..count).rev() {
+        if n % group == 0 && n != count - 1 {
+            out.push('\n');
+        }
+        match n % 3 {
+            0 => out.push_str(&format!("crate-{n:05} = \"1\"\n")),
+            1 => out.push_str(&format!("crate-{n:05} = {{ version = \"1\", features = [\"std\", \"alloc\"] }}\n")),
+            _ => out.push_str(&format!("crate-{n:05}.workspace = true\n")),
+        }
+    }
+    out
+}
+
+fn sort(c: &mut Criterion) {
+    let config = Config::default();
+    let grouped = Config::builder().grouped(true).build().unwrap();
+
+    let flat = manifest(2_500, usize::MAX);
+    c.bench_function("flat 2500", |b| b.iter(|| check_manifest(black_box(&flat), &config).unwrap()));
+
+    let groups = manifest(2_500, 50);
+    c.bench_function("grouped 2500", |b| b.iter(|| check_manifest(black_box(&groups), &grouped).unwrap()));
+
+    let tables: String = (0..2_500)
+        .rev()
+        .map(|n| format!("[dependencies.crate-{n:05}]\nversion = \"1\"\n\n"))
+        .collect();
+    c.bench_function("2500 dependency tables", |b| {
+        b.iter(|| check_manifest(black_box(&tables), &config).unwrap());
+    });
+
+    let small: Vec<_> = (0..1_000).map(|n| manifest(5 + n % 20, 5)).collect();
+    c.bench_function("1000 small files", |b| {
+        b.iter(|| {
+            for input in &small {
+                black_box(check_manifest(black_box(input), &config).unwrap());
+            }
+        });
+    });
+}
+
+/// The `--check` of manifests that are sorted already, the common case that
+/// `is_sorted` answers without sorting a copy.
+fn check(c: &mut Criterion) {
+    let config = Config::default();
+    let sorted = |input: &str| check_manifest(input, &config).unwrap().output;
+
+    let flat = sorted(&manifest(2_500, usize::MAX));
+    c.bench_function("check sorted flat 2500", |b| {
+        b.iter(|| assert!(check_manifest(black_box(&flat), &config).unwrap().sorted));
+    });
+
+    let small: Vec<_> = (0..1_000).map(|n| sorted(&manifest(5 + n % 20, 5))).collect();
+    c.bench_function("check 1000 sorted small files", |b| {
+        b.iter(|| {
+            for input in &small {
+                assert!(check_manifest(black_box(input), &config).unwrap().sorted);
+            }
+        });
+    });
+}
+
+criterion_group!(benches, sort, check);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+//! A second pass over sorted and formatted toml changes nothing.
+#![no_main]
+
+use cargo_sort_fix::{Config, MATCHER, fmt_toml, try_sort_toml};
+use libfuzzer_sys::fuzz_target;
+
+fn sort_and_fmt(input: &str, config: &Config) -> Option<String> {
+    let mut doc = try_sort_toml(input, MATCHER, config.grouped, &config.table_order).ok()?;
+    fmt_toml(&mut doc, config);
+    Some(doc.to_string())
+}
+
+fuzz_target!(|input: &str| {
+    for grouped in [false, true] {
+        let config = Config {
+            grouped,
+            ..Config::default()
+        };
+        let Some(once) = sort_and_fmt(input, &config) else {
+            return;
+        };
+        let twice = sort_and_fmt(&once, &config).expect("the first pass gave invalid toml");
+        assert_eq!(once, twice, "grouped={grouped}");
+    }
+});
@@ -0,0 +1,23 @@
+//! Sorting and formatting any valid toml gives valid toml.
+#![no_main]
+
+use cargo_sort_fix::{Config, MATCHER, fmt_toml, try_sort_toml};
+use libfuzzer_sys::fuzz_target;
+use toml_edit::DocumentMut;
+
+fuzz_target!(|input: &str| {
+    for grouped in [false, true] {
+        let config = Config {
+            grouped,
+            ..Config::default()
+        };
+        let Ok(mut doc) = try_sort_toml(input, MATCHER, grouped, &config.table_order) else {
+            return;
+        };
+        fmt_toml(&mut doc, &config);
+        let output = doc.to_string();
+        if let Err(err) = output.parse::<DocumentMut>() {
+            panic!("grouped={grouped} gave invalid toml: {err}\n{output}");
+        }
+    }
+});
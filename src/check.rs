@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+
+use toml_edit::{DocumentMut, ImDocument, Item, Key, Table};
+
+use crate::{
+    error::Error,
+    fmt::{self, Config, NewlineStyle, TrailingNewline},
+    sort::{self, Matcher, key_path},
+};
+
+/// The outcome of sorting and formatting one manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Sorting moves no table, key or array item, see `unsorted_tables` and
+    /// `misplaced_keys`. How the manifest is written out does not count.
+    pub sorted: bool,
+
+    /// The output differs from the input in nothing but what sorting moved,
+    /// line endings aside. Always `true` unless `check_format` is set.
+    pub formatted: bool,
+
+    /// Every line of the input already ends the way `newline_style` asks,
+    /// always `true` unless `check_format` is set.
+    pub newlines: bool,
+
+    /// The lines of the input that do not end the way `newline_style` asks,
+    /// `0` exactly when `newlines` is `true`.
+    pub newline_mismatches: usize,
+
+    /// The line endings of `output`, the ones of the input for
+    /// [`NewlineStyle::Auto`].
+    pub newline_style: NewlineStyle,
+
+    /// The input differs from `output` only in the whitespace at the end of
+    /// the file, line endings aside, which `require_trailing_newline` can
+    /// tolerate.
+    pub eof_only: bool,
+
+    /// The dotted paths of the tables and arrays that sorting reordered, in
+    /// the order of the output.
+    pub unsorted_tables: Vec<String>,
+
+    /// The keys that sorting moved in front of a key they follow in the
+    /// input, in the order of the input.
+    pub misplaced_keys: Vec<MisplacedKey>,
+
+    /// `output` was written back to the manifest, [`check_manifest`] itself
+    /// never writes and leaves this `false`.
+    pub written: bool,
+
+    /// The sorted and formatted manifest.
+    pub output: String,
+}
+
+/// A key that sorting moves in front of another key of its table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MisplacedKey {
+    /// The dotted path of the table.
+    pub table: String,
+
+    /// The key that is out of order.
+    pub key: String,
+
+    /// The first key of the input that `key` belongs in front of.
+    pub expected_before: String,
+
+    /// The line of `key` in the input, counted from 1.
+    pub line: Option<usize>,
+}
+
+/// Sorts and formats the manifest `input` with `config` and reports what
+/// changed, without printing or writing anything.
+///
+/// ## Example
+/// ```
+/// use cargo_sort_fix::{Config, check_manifest};
+///
+/// let config = "grouped = true\ncheck_format = true".parse::<Config>().unwrap();
+/// let input = "[dependencies]\nserde = \"1\"\nanyhow = {version=\"1\"}\n";
+/// let report = check_manifest(input, &config).unwrap();
+/// assert!(!report.sorted);
+/// assert!(!report.formatted);
+/// assert_eq!(report.output, "[dependencies]\nanyhow = { version = \"1\" }\nserde = \"1\"\n");
+/// ```
+///
+/// ## Errors
+/// [`Error::Parse`] if `input` is not valid toml.
+pub fn check_manifest(input: &str, config: &Config) -> Result<CheckReport, Error> {
+    let input_doc = sort::parse(input)?;
+    Ok(check_document(input, input_doc, config))
+}
+
+/// Like [`check_manifest`] for a manifest that is already parsed, `input_doc`
+/// must be the parsed `input`.
+pub fn check_document(input: &str, input_doc: DocumentMut, config: &Config) -> CheckReport {
+    let detected = NewlineStyle::detect(input);
+    let mut config = config.clone();
+    if config.newline_style == NewlineStyle::Auto {
+        config.newline_style = detected;
+    }
+
+    let (heading, heading_key) = config.sort_patterns();
+    let matcher = Matcher {
+        heading: &heading,
+        heading_key: &heading_key,
+        tables: Some(&config.tables),
+        unknown_tables: config.unknown_tables,
+        group_comments: config.group_comments,
+        feature_values: config.sort_feature_values,
+        array_of_tables: &config.sort_array_of_tables,
+    };
+    let order = config.table_order_for(input_doc.contains_key("workspace"));
+    let (mut sorted_doc, (unsorted_tables, misplaced_keys)) = if sort::is_sorted(&input_doc, matcher, config.grouped, order) {
+        // the common case, which needs no sorted copy to compare against
+        (input_doc, (vec![], vec![]))
+    } else {
+        let mut sorted_doc = input_doc.clone();
+        sort::sort_document(&mut sorted_doc, matcher, config.grouped, order);
+        let reordered = reordered(input, &input_doc, &sorted_doc);
+        (sorted_doc, reordered)
+    };
+
+    // if no-format is not found apply formatting
+    let formats = !config.no_format || config.check_format;
+    let mut output = sorted_doc.to_string();
+    let mut formatted = true;
+    if formats {
+        fmt::fmt_toml(&mut sorted_doc, &config);
+        let after_fmt = sorted_doc.to_string();
+        formatted = output == after_fmt;
+        output = after_fmt;
+    }
+
+    let normalized = input.replace("\r\n", "\n");
+    let eof_only = {
+        let output = output.replace("\r\n", "\n");
+        output != normalized && output.trim_end() == normalized.trim_end()
+    };
+    let tolerated = eof_only && config.require_trailing_newline != TrailingNewline::Require;
+    if eof_only && config.require_trailing_newline == TrailingNewline::Ignore {
+        // keep the end of the input, the line endings are still converted
+        output.truncate(output.trim_end().len());
+        output.push_str(&input[input.trim_end().len()..]);
+    }
+
+    output = config.newline_style.convert(&output);
+    // a file mixing both is converted to the one most of its lines use
+    let (lf, crlf) = fmt::count_line_endings(input);
+    // formatting only counts against the manifest with `check_format`
+    let newline_mismatches = match config.newline_style {
+        _ if !config.check_format => 0,
+        NewlineStyle::Crlf => lf,
+        _ => crlf,
+    };
+
+    let sorted = unsorted_tables.is_empty() && misplaced_keys.is_empty();
+    let formatted = if !config.check_format || tolerated {
+        true
+    } else if sorted {
+        // a change that moves nothing is formatting, however it came about
+        normalized == output.replace("\r\n", "\n")
+    } else {
+        formatted
+    };
+
+    CheckReport {
+        sorted,
+        formatted,
+        // a forced line ending is checked as part of the formatting
+        newlines: newline_mismatches == 0,
+        newline_mismatches,
+        eof_only,
+        newline_style: config.newline_style,
+        unsorted_tables,
+        misplaced_keys,
+        written: false,
+        output,
+    }
+}
+
+/// A table with a header, as far as its order is concerned.
+#[derive(Debug)]
+struct Header {
+    position: usize,
+    path: String,
+    /// The keys holding values, a dotted key with all its parts, tables are
+    /// headers of their own.
+    keys: Vec<String>,
+    /// Where each of `keys` starts in the input, for a parsed document that
+    /// kept its spans.
+    key_starts: Vec<Option<usize>>,
+    /// The items of the array values.
+    arrays: Vec<(String, Vec<String>)>,
+}
+
+/// Collects the headers below `table` at the dotted `path`.
+fn headers(table: &Table, path: &str, found: &mut Vec<Header>) {
+    let mut header = Header {
+        position: table.position().unwrap_or_default(),
+        path: path.to_owned(),
+        keys: vec![],
+        key_starts: vec![],
+        arrays: vec![],
+    };
+    entries(table, path, "", &mut header, found);
+    if !table.is_implicit() && !path.is_empty() {
+        found.push(header);
+    }
+}
+
+/// Adds the keys of `table` to `header`, the ones of a dotted key after its
+/// `prefix`, and collects the headers below it.
+fn entries(table: &Table, path: &str, prefix: &str, header: &mut Header, found: &mut Vec<Header>) {
+    let join = |parent: &str, key: &str| {
+        let key = key_path([key]);
+        if parent.is_empty() { key } else { format!("{parent}.{key}") }
+    };
+    for (key, item) in table.iter() {
+        match item {
+            // `b.workspace = true` is a key of the table it is written in
+            Item::Table(dotted) if dotted.is_dotted() => entries(dotted, &join(path, key), &join(prefix, key), header, found),
+            Item::Table(table) => headers(table, &join(path, key), found),
+            Item::ArrayOfTables(tables) => tables.iter().for_each(|table| headers(table, &join(path, key), found)),
+            Item::Value(value) => {
+                let key_start = table.key(key).and_then(Key::span).map(|span| span.start);
+                let key = join(prefix, key);
+                if let Some(array) = value.as_array() {
+                    let items = array.iter().map(|v| v.to_string().trim().to_owned()).collect();
+                    header.arrays.push((key.clone(), items));
+                }
+                header.keys.push(key);
+                header.key_starts.push(key_start);
+            }
+            Item::None => {}
+        }
+    }
+}
+
+/// Returns the dotted paths of the tables and arrays whose order differs
+/// between `input_doc` and `sorted`, and the keys that moved.
+///
+/// `input` is parsed once more for the lines of the keys, a `DocumentMut`
+/// does not keep them.
+fn reordered(input: &str, input_doc: &DocumentMut, sorted: &DocumentMut) -> (Vec<String>, Vec<MisplacedKey>) {
+    let collect = |table: &Table| {
+        let mut found = vec![];
+        headers(table, "", &mut found);
+        found.sort_by_key(|h| h.position);
+        found
+    };
+    let spanned = ImDocument::parse(input).ok();
+    let before = collect(spanned.as_ref().map_or(input_doc.as_table(), |doc| doc.as_table()));
+    let after = collect(sorted.as_table());
+    let newlines: Vec<usize> = input.match_indices('\n').map(|(idx, _)| idx).collect();
+    let line = |start: usize| newlines.partition_point(|&newline| newline < start) + 1;
+    // the entries of an array of tables share a path, the n-th one of the
+    // output is compared with the n-th one of the input
+    let mut by_path: HashMap<&str, Vec<&Header>> = HashMap::new();
+    for header in &before {
+        by_path.entry(header.path.as_str()).or_default().push(header);
+    }
+
+    let mut paths: Vec<String> = vec![];
+    let mut keys = vec![];
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (idx, header) in after.iter().enumerate() {
+        let nth = seen.entry(header.path.as_str()).or_default();
+        let Some(old) = by_path.get(header.path.as_str()).and_then(|entries| entries.get(*nth)) else {
+            continue;
+        };
+        *nth += 1;
+        let moved = before.get(idx).is_none_or(|h| h.path != header.path);
+        if moved || old.keys != header.keys {
+            paths.push(header.path.clone());
+        }
+        let rank: HashMap<&str, usize> = header.keys.iter().enumerate().map(|(rank, key)| (key.as_str(), rank)).collect();
+        // the keys of the input that come after every key before them in the
+        // output, by rising rank, the first key a key belongs before is one
+        let mut maxima: Vec<(usize, &String)> = vec![];
+        for (idx, key) in old.keys.iter().enumerate() {
+            let Some(&rank_of_key) = rank.get(key.as_str()) else { continue };
+            if let Some((_, first)) = maxima.get(maxima.partition_point(|(rank, _)| *rank < rank_of_key)) {
+                keys.push(MisplacedKey {
+                    table: header.path.clone(),
+                    key: key.clone(),
+                    expected_before: (*first).clone(),
+                    line: old.key_starts[idx].map(line),
+                });
+            }
+            if maxima.last().is_none_or(|(rank, _)| *rank < rank_of_key) {
+                maxima.push((rank_of_key, key));
+            }
+        }
+        for (key, items) in &header.arrays {
+            if old.arrays.iter().any(|(k, old_items)| k == key && old_items != items) {
+                paths.push(format!("{}.{key}", header.path));
+            }
+        }
+    }
+    paths.dedup();
+    (paths, keys)
+}
+
+#[cfg(test)]
+mod test {
+    use toml_edit::DocumentMut;
+
+    use super::{MisplacedKey, check_document, check_manifest};
+    use crate::fmt::{Config, NewlineStyle, TrailingNewline};
+
+    #[test]
+    fn reports() {
+        let config = Config {
+            table_order: vec![],
+            check_format: true,
+            ..Config::default()
+        };
+        let sorted = "[package]\nname = \"a\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        let report = check_manifest(sorted, &config).unwrap();
+        assert!(report.sorted && report.formatted && report.newlines && !report.written);
+        assert_eq!(report.newline_style, NewlineStyle::Lf);
+        assert!(report.unsorted_tables.is_empty());
+        assert_eq!(report.output, sorted);
+
+        let input = "[workspace]\nmembers = [\"b\", \"a\"]\n\n[dependencies]\nb = \"1\"\na = \"1\"\n\n[dev-dependencies]\nx = \"1\"\n";
+        let report = check_manifest(input, &config).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.unsorted_tables, ["workspace.members", "dependencies"]);
+
+        let unformatted = sorted.replace("b = \"1\"", "b = {version = \"1\"}");
+        let report = check_manifest(&unformatted, &config).unwrap();
+        assert!(report.sorted && !report.formatted);
+        assert!(report.unsorted_tables.is_empty());
+        assert_eq!(report.output, sorted.replace("b = \"1\"", "b = { version = \"1\" }"));
+        // formatting is fixed but not reported without `check_format`
+        let unchecked = Config {
+            check_format: false,
+            ..config.clone()
+        };
+        let report = check_manifest(&unformatted, &unchecked).unwrap();
+        assert!(report.sorted && report.formatted);
+        assert_eq!(report.output, sorted.replace("b = \"1\"", "b = { version = \"1\" }"));
+
+        let crlf = Config {
+            newline_style: NewlineStyle::Crlf,
+            ..config.clone()
+        };
+        let report = check_manifest(sorted, &crlf).unwrap();
+        assert!(report.sorted && report.formatted && !report.newlines);
+        assert_eq!(report.output, sorted.replace('\n', "\r\n"));
+        let report = check_manifest(
+            sorted,
+            &Config {
+                check_format: false,
+                ..crlf
+            },
+        )
+        .unwrap();
+        assert!(report.newlines && report.newline_mismatches == 0);
+        assert_eq!(report.output, sorted.replace('\n', "\r\n"));
+
+        assert!(check_manifest("[dependencies\n", &config).is_err());
+
+        let parsed = check_document(input, input.parse::<DocumentMut>().unwrap(), &config);
+        assert_eq!(parsed, check_manifest(input, &config).unwrap());
+    }
+
+    #[test]
+    fn moved_tables() {
+        let config = Config::default();
+        let input = "[dependencies]\na = \"1\"\n\n[package]\nname = \"a\"\n";
+        let report = check_manifest(input, &config).unwrap();
+        assert_eq!(report.unsorted_tables, ["package", "dependencies"]);
+    }
+
+    #[test]
+    fn misplaced_keys() {
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\ntokio = \"1\"\nserde = \"1\"\nanyhow = \"1\"\nzip = \"1\"\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        let misplaced = |key: &str, expected_before: &str, line| MisplacedKey {
+            table: "dependencies".to_owned(),
+            key: key.to_owned(),
+            expected_before: expected_before.to_owned(),
+            line: Some(line),
+        };
+        assert_eq!(
+            report.misplaced_keys,
+            [misplaced("serde", "tokio", 6), misplaced("anyhow", "tokio", 7)]
+        );
+        // a key belongs before the first key it comes after that sorts after it
+        let input = "[dependencies]\nb = \"1\"\nd = \"1\"\na = \"1\"\nc = \"1\"\ne = \"1\"\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        assert_eq!(report.misplaced_keys, [misplaced("a", "b", 4), misplaced("c", "d", 5)]);
+        assert!(
+            check_manifest(&report.output, &Config::default())
+                .unwrap()
+                .misplaced_keys
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn quoted_paths() {
+        let input = "[package]\nname = \"a\"\n\n[target.'cfg(unix)'.dependencies]\nz = \"1\"\n\"a.b\" = \"1\"\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        assert_eq!(report.unsorted_tables, ["target.\"cfg(unix)\".dependencies"]);
+        assert_eq!(report.misplaced_keys[0].table, "target.\"cfg(unix)\".dependencies");
+        assert_eq!(
+            (
+                report.misplaced_keys[0].key.as_str(),
+                report.misplaced_keys[0].expected_before.as_str()
+            ),
+            ("\"a.b\"", "z")
+        );
+    }
+
+    #[test]
+    fn dotted_keys() {
+        let misplaced = |table: &str, key: &str, expected_before: &str, line| MisplacedKey {
+            table: table.to_owned(),
+            key: key.to_owned(),
+            expected_before: expected_before.to_owned(),
+            line: Some(line),
+        };
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\nb.workspace = true\na.workspace = true\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.unsorted_tables, ["dependencies"]);
+        assert_eq!(report.misplaced_keys, [misplaced("dependencies", "a.workspace", "b.workspace", 6)]);
+        assert!(check_manifest(&report.output, &Config::default()).unwrap().sorted);
+
+        let input = "[package]\nname = \"a\"\n\n[lints]\nrust.x = \"warn\"\nclippy.y = \"warn\"\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.misplaced_keys, [misplaced("lints", "clippy.y", "rust.x", 6)]);
+        assert_eq!(
+            report.output,
+            input.replace("rust.x = \"warn\"\nclippy.y", "clippy.y = \"warn\"\nrust.x")
+        );
+    }
+
+    #[test]
+    fn sort_tables() {
+        let input = "[package]\nname = \"a\"\n\n[package.metadata.cross.x]\nz = 1\na = 1\n\n[dependencies]\nz = \"1\"\na = \"1\"\n";
+        let check = |config: Config| check_manifest(input, &config).unwrap().output;
+        let cross = input.replace("z = 1\na = 1", "a = 1\nz = 1");
+        let dependencies = input.replace("z = \"1\"\na = \"1\"", "a = \"1\"\nz = \"1\"");
+
+        assert_eq!(check(Config::default()), dependencies);
+        let extended = Config::builder().extend_sort_tables(["package.metadata.cross.*"]);
+        assert_eq!(
+            check(extended.build().unwrap()),
+            cross.replace("z = \"1\"\na = \"1\"", "a = \"1\"\nz = \"1\"")
+        );
+        let replaced = Config::builder().sort_tables(["package.metadata.cross.*"]);
+        assert_eq!(check(replaced.build().unwrap()), cross);
+        assert_eq!(check(Config::builder().sort_tables(Vec::<String>::new()).build().unwrap()), input);
+    }
+
+    #[test]
+    fn feature_values() {
+        let input = "[package]\nname = \"a\"\n\n[features]\nstd = [\"serde/std\", \"dep:serde\"]\n";
+        assert!(check_manifest(input, &Config::default()).unwrap().sorted);
+
+        let config = Config::builder().sort_feature_values(true).build().unwrap();
+        let report = check_manifest(input, &config).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.unsorted_tables, ["features.std"]);
+        assert_eq!(
+            report.output,
+            input.replace("\"serde/std\", \"dep:serde\"", "\"dep:serde\", \"serde/std\"")
+        );
+    }
+
+    #[test]
+    fn array_of_tables() {
+        let config = Config::builder().sort_array_of_tables(["example"]).build().unwrap();
+        let sorted = "[package]\nname = \"a\"\n\n[[example]]\nname = \"a\"\npath = \"a.rs\"\n\n[[example]]\nname = \"b\"\n";
+        let report = check_manifest(sorted, &config).unwrap();
+        assert!(report.sorted && report.unsorted_tables.is_empty() && report.misplaced_keys.is_empty());
+
+        let unsorted = "[package]\nname = \"a\"\n\n[[example]]\nname = \"b\"\n\n[[example]]\nname = \"a\"\npath = \"a.rs\"\n";
+        assert!(check_manifest(unsorted, &Config::default()).unwrap().sorted);
+        let report = check_manifest(unsorted, &config).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.unsorted_tables, ["example"]);
+        assert_eq!(report.output, sorted);
+    }
+
+    #[test]
+    fn stylistic_changes() {
+        let config = Config {
+            table_order: vec![],
+            check_format: true,
+            ..Config::default()
+        };
+        let no_format = Config {
+            no_format: true,
+            check_format: false,
+            ..config.clone()
+        };
+        let sorted = "[package]\nname = \"a\"\n\n[dependencies]\nserde = \"1\"\ntokio = \"1\"\n";
+
+        // the key is compared by name, not by how it is written
+        let quoted = sorted.replace("serde = ", "\"serde\" = ");
+        let report = check_manifest(&quoted, &config).unwrap();
+        assert!(report.sorted && report.formatted && report.misplaced_keys.is_empty());
+        let report = check_manifest(&quoted.replace("\"serde\" = ", "'serde'   ="), &config).unwrap();
+        assert!(report.sorted && !report.formatted);
+        assert!(
+            check_manifest(&quoted.replace("\"serde\" = ", "'serde'   ="), &no_format)
+                .unwrap()
+                .formatted
+        );
+        let unsorted = "[dependencies]\n\"tokio\" = \"1\"\nserde = \"1\"\n";
+        let report = check_manifest(unsorted, &config).unwrap();
+        assert!(!report.sorted);
+        assert_eq!(report.misplaced_keys[0].key, "serde");
+
+        let trailing_space = sorted.replace("\"1\"\n", "\"1\"  \n");
+        assert!(check_manifest(&trailing_space, &config).unwrap().sorted);
+        assert!(check_manifest(&trailing_space, &no_format).unwrap().sorted);
+
+        // the newline sorting adds at the end is formatting too
+        for config in [&config, &no_format] {
+            let report = check_manifest(sorted.trim_end(), config).unwrap();
+            assert!(report.sorted && report.eof_only, "{config:?}");
+            assert_eq!(report.formatted, config.no_format, "{config:?}");
+        }
+    }
+
+    #[test]
+    fn trailing_newline_policy() {
+        let sorted = "[package]\nname = \"a\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        let check = |input: &str, require_trailing_newline| {
+            let config = Config {
+                require_trailing_newline,
+                check_format: true,
+                ..Config::default()
+            };
+            check_manifest(input, &config).unwrap()
+        };
+        for input in [sorted.trim_end(), &format!("{sorted}\n# end")] {
+            let report = check(input, TrailingNewline::Require);
+            assert!(report.eof_only && report.sorted && !report.formatted, "{input:?}");
+            assert_eq!(report.output, format!("{input}\n"));
+
+            let report = check(input, TrailingNewline::Optional);
+            assert!(report.eof_only && report.sorted && report.formatted, "{input:?}");
+            assert_eq!(report.output, format!("{input}\n"));
+
+            let report = check(input, TrailingNewline::Ignore);
+            assert!(report.eof_only && report.sorted && report.formatted, "{input:?}");
+            assert_eq!(report.output, input);
+        }
+
+        // a real change is reported whatever the policy
+        let unsorted = sorted.replace("a = \"1\"\nb = \"1\"\n", "b = \"1\"\na = \"1\"");
+        let report = check(&unsorted, TrailingNewline::Ignore);
+        assert!(!report.eof_only && !report.sorted);
+        assert_eq!(report.output, sorted);
+        assert!(!check(sorted, TrailingNewline::Require).eof_only);
+
+        let crlf = Config {
+            require_trailing_newline: TrailingNewline::Ignore,
+            newline_style: NewlineStyle::Crlf,
+            check_format: true,
+            ..Config::default()
+        };
+        let report = check_manifest(sorted.trim_end(), &crlf).unwrap();
+        assert!(report.sorted && !report.newlines);
+        assert_eq!(report.output, sorted.trim_end().replace('\n', "\r\n"));
+    }
+
+    #[test]
+    fn mixed_line_endings() {
+        let config = Config {
+            check_format: true,
+            ..Config::default()
+        };
+        let sorted = "[package]\r\nname = \"a\"\r\ndescription = \"\"\"\none\r\ntwo\"\"\"\r\n\r\n[dependencies]\na = \"1\"\r\n";
+        let report = check_manifest(sorted, &config).unwrap();
+        assert!(report.sorted && report.formatted && !report.newlines);
+        assert_eq!(report.newline_style, NewlineStyle::Crlf);
+        assert_eq!(report.newline_mismatches, 1);
+        // the lone `\n` of the string stays, the one after the header is converted
+        assert_eq!(report.output, sorted.replace("[dependencies]\n", "[dependencies]\r\n"));
+
+        let report = check_manifest(&report.output, &config).unwrap();
+        assert!(report.sorted && report.newlines);
+        assert!(!report.output.contains("\r\r"));
+
+        let lf = Config {
+            newline_style: NewlineStyle::Lf,
+            ..config
+        };
+        let report = check_manifest(sorted, &lf).unwrap();
+        let expected = "[package]\nname = \"a\"\ndescription = \"\"\"\none\r\ntwo\"\"\"\n\n[dependencies]\na = \"1\"\n";
+        assert_eq!(report.output, expected);
+    }
+}
@@ -0,0 +1,1654 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use toml_edit::{DocumentMut, ImDocument, Item, Table, TableLike, Value, value};
+
+use crate::{CARGO_TOML, Result, read_file};
+use cargo_sort_fix::{Config, DependencyStyle, Error, NewlineStyle, UnknownTables, parse_toml};
+
+/// The key under `[package.metadata]` and `[workspace.metadata]` that holds
+/// configuration.
+pub(crate) const METADATA_KEY: &str = "cargo-sort-ng";
+
+/// The config file names looked for in a directory, in order.
+///
+/// The `tomlfmt.toml` names are kept for compatibility.
+const CONFIG_FILES: &[&str] = &["cargo-sort-ng.toml", ".cargo-sort-ng.toml", "tomlfmt.toml", ".tomlfmt.toml"];
+
+/// The type a config value must have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Bool,
+    /// A non-negative integer.
+    Integer,
+    String,
+    StringArray,
+    Table,
+    /// One of a fixed set of strings.
+    OneOf(&'static [&'static str]),
+    /// A boolean or one of a fixed set of strings.
+    BoolOr(&'static [&'static str]),
+}
+
+impl Kind {
+    fn expected(self) -> String {
+        match self {
+            Kind::Bool => "a boolean".to_owned(),
+            Kind::Integer => "a non-negative integer".to_owned(),
+            Kind::String => "a string".to_owned(),
+            Kind::StringArray => "an array of strings".to_owned(),
+            Kind::Table => "a table".to_owned(),
+            Kind::OneOf(choices) => {
+                let choices = choices.iter().map(|c| format!("`{c}`")).collect::<Vec<_>>();
+                format!("one of {}", choices.join(", "))
+            }
+            Kind::BoolOr(choices) => {
+                let choices = choices.iter().map(|c| format!("`{c}`")).collect::<Vec<_>>();
+                format!("a boolean or {}", choices.join(", "))
+            }
+        }
+    }
+
+    /// Returns what was found instead if `item` does not have this type.
+    fn mismatch(self, item: &Item) -> Option<String> {
+        let ok = match self {
+            Kind::Bool => item.as_bool().is_some(),
+            Kind::Integer => item.as_integer().is_some_and(|i| i >= 0),
+            Kind::String => item.as_str().is_some(),
+            Kind::StringArray => item.as_array().is_some_and(|arr| arr.iter().all(|v| v.is_str())),
+            Kind::Table => item.is_table_like(),
+            Kind::OneOf(choices) => item.as_str().is_some_and(|s| choices.contains(&s)),
+            Kind::BoolOr(choices) => item.as_bool().is_some() || item.as_str().is_some_and(|s| choices.contains(&s)),
+        };
+        if ok {
+            return None;
+        }
+        Some(match item {
+            Item::Value(Value::Integer(i)) if self == Kind::Integer => format!("integer {}", i.value()),
+            Item::Value(Value::String(s)) if matches!(self, Kind::OneOf(_) | Kind::BoolOr(_)) => format!("`{}`", s.value()),
+            Item::Value(Value::Array(arr)) if self == Kind::StringArray => {
+                let other = arr
+                    .iter()
+                    .find(|v| !v.is_str())
+                    .map_or("value", |v| type_name(&Item::Value(v.clone())));
+                format!("array containing {other}")
+            }
+            item => type_name(item).to_owned(),
+        })
+    }
+}
+
+fn type_name(item: &Item) -> &'static str {
+    match item {
+        Item::None => "nothing",
+        Item::Value(Value::String(_)) => "string",
+        Item::Value(Value::Integer(_)) => "integer",
+        Item::Value(Value::Float(_)) => "float",
+        Item::Value(Value::Boolean(_)) => "boolean",
+        Item::Value(Value::Datetime(_)) => "datetime",
+        Item::Value(Value::Array(_)) => "array",
+        Item::Value(Value::InlineTable(_)) | Item::Table(_) => "table",
+        Item::ArrayOfTables(_) => "array of tables",
+    }
+}
+
+/// A key accepted in config files, `children` lists the keys accepted when
+/// the value is a table, a `*` name matches any key.
+#[derive(Debug)]
+pub(crate) struct Key {
+    pub(crate) name: &'static str,
+    pub(crate) kind: Kind,
+    /// A one line description used for the generated config file.
+    pub(crate) doc: &'static str,
+    pub(crate) children: &'static [Key],
+}
+
+const fn key(name: &'static str, kind: Kind, doc: &'static str) -> Key {
+    Key {
+        name,
+        kind,
+        doc,
+        children: &[],
+    }
+}
+
+/// Every key accepted in config files.
+pub(crate) const KEYS: &[Key] = &[
+    key("always_trailing_comma", Kind::Bool, "trailing comma in arrays"),
+    key("multiline_trailing_comma", Kind::Bool, "trailing comma when multi-line"),
+    key(
+        "max_array_line_len",
+        Kind::Integer,
+        "the maximum length in bytes of the string of an array object",
+    ),
+    key("indent_count", Kind::Integer, "number of spaces to indent"),
+    key("space_around_eq", Kind::Bool, "space around equal sign"),
+    key("compact_arrays", Kind::Bool, "remove all the spacing inside the array"),
+    key("compact_inline_tables", Kind::Bool, "remove all the spacing inside the object"),
+    key("trailing_newline", Kind::Bool, "add a trailing newline to the file"),
+    key(
+        "require_trailing_newline",
+        Kind::BoolOr(&["ignore"]),
+        "whether a difference only in the whitespace at the end of the file fails --check, `ignore` also leaves it unwritten",
+    ),
+    key(
+        "key_value_newlines",
+        Kind::Bool,
+        "is it ok to have blank lines inside of a table, needed for `grouped`",
+    ),
+    key(
+        "allowed_blank_lines",
+        Kind::Integer,
+        "the maximum amount of consecutive blank lines allowed",
+    ),
+    key(
+        "newline_style",
+        Kind::OneOf(NewlineStyle::NAMES),
+        "line endings, `auto` keeps the ones each file already uses",
+    ),
+    key(
+        "table_order",
+        Kind::StringArray,
+        "the ordering of tables in a document, unspecified tables go where `*` is or after these",
+    ),
+    key(
+        "table_order_root",
+        Kind::StringArray,
+        "the ordering of tables in the workspace root manifest, `table_order` is used when unset",
+    ),
+    key(
+        "unknown_tables",
+        Kind::OneOf(UnknownTables::NAMES),
+        "where tables missing from the order go, a sorted one keeps its `position` in the file, `keep` after the table before them, `alphabetize` or `end` in input order",
+    ),
+    key(
+        "dependency_style",
+        Kind::OneOf(DependencyStyle::NAMES),
+        "write dependencies as `inline` tables or as `table`s of their own, `preserve` keeps the form of each",
+    ),
+    key(
+        "order_inline_keys",
+        Kind::Bool,
+        "put the keys of inline dependencies in the order of `inline_key_order`",
+    ),
+    key(
+        "inline_key_order",
+        Kind::StringArray,
+        "the order of the keys of inline dependencies, the others come after these sorted by name",
+    ),
+    key(
+        "canonicalize_versions",
+        Kind::Bool,
+        "write version requirements without a redundant `^` and as `>=1.2, <2`, this changes values",
+    ),
+    key(
+        "grouped",
+        Kind::Bool,
+        "keep blank lines when sorting groups of key value pairs, same as --grouped",
+    ),
+    key(
+        "group_comments",
+        Kind::Bool,
+        "sort the keys between full line comments on their own, the comments keep their place",
+    ),
+    key(
+        "sort_feature_values",
+        Kind::Bool,
+        "sort the strings in each array of [features], comments after an element move with it",
+    ),
+    key(
+        "sort_array_of_tables",
+        Kind::StringArray,
+        "the arrays of tables like [[example]] whose entries are sorted by `name`",
+    ),
+    key(
+        "check_format",
+        Kind::Bool,
+        "also fail --check if formatting changes, same as --check-format",
+    ),
+    key("workspace", Kind::Bool, "check every crate in the workspace, same as --workspace"),
+    key("no_format", Kind::Bool, "skip formatting after sorting, same as --no-format"),
+    key(
+        "ignore",
+        Kind::StringArray,
+        "glob patterns of manifests to skip, relative to the directory of this file",
+    ),
+    key(
+        "sort_tables",
+        Kind::StringArray,
+        "dotted paths of the tables and arrays to sort, `*` as the last segment matches every table there",
+    ),
+    key(
+        "extend_sort_tables",
+        Kind::StringArray,
+        "more tables to sort, in addition to `sort_tables`",
+    ),
+    key("allow_unknown_keys", Kind::Bool, "accept keys this version does not know about"),
+    key("extends", Kind::String, "a config file to load first, relative to this file"),
+    key("profile", Kind::Table, "named settings applied with --profile, e.g. `[profile.ci]`"),
+    key(
+        "override",
+        Kind::Table,
+        "settings for the manifests matching a glob, e.g. `[override.\"crates/legacy/**\"]`",
+    ),
+    Key {
+        name: "format",
+        kind: Kind::Table,
+        doc: "formatting rules to apply, `format = false` turns off formatting like --no-format",
+        children: &[
+            key("blank_lines", Kind::Bool, "limit blank lines before headers and between keys"),
+            key("arrays", Kind::Bool, "wrap long arrays and tidy short ones"),
+            key("inline_tables", Kind::Bool, "tidy the spacing of inline tables"),
+        ],
+    },
+    Key {
+        name: "tables",
+        kind: Kind::Table,
+        doc: "settings for single tables keyed by dotted path, e.g. `[tables.\"workspace.members\"]`",
+        children: &[Key {
+            name: "*",
+            kind: Kind::Table,
+            doc: "",
+            children: &[
+                key("sort", Kind::Bool, "sort the keys of this table"),
+                key("grouped", Kind::Bool, "keep blank lines when sorting this table"),
+                key("format", Kind::Bool, "format this table or value"),
+            ],
+        }],
+    },
+];
+
+/// The top level tables of a Cargo manifest, used to warn about `[tables]`
+/// entries that can never match.
+const MANIFEST_TABLES: &[&str] = &[
+    "cargo-features",
+    "package",
+    "lib",
+    "bin",
+    "example",
+    "test",
+    "bench",
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "target",
+    "badges",
+    "features",
+    "lints",
+    "patch",
+    "replace",
+    "profile",
+    "workspace",
+];
+
+/// Returns a warning for every `[tables]` entry whose path does not start with
+/// a Cargo manifest table.
+pub(crate) fn unknown_table_warnings(config: &Config) -> Vec<String> {
+    config
+        .tables
+        .keys()
+        .filter(|path| !MANIFEST_TABLES.contains(&path.split('.').next().unwrap_or_default()))
+        .map(|path| format!("`[tables.{path}]` does not match any Cargo.toml table"))
+        .collect()
+}
+
+/// Returns every key of `config` as a toml table.
+fn config_table(config: &Config) -> Table {
+    let doc = config
+        .to_string()
+        .parse::<DocumentMut>()
+        .expect("a config is written as valid toml");
+    doc.as_table().clone()
+}
+
+/// The values the generated config file shows for the keys a [`Config`] does
+/// not hold, `true` for an example that is written commented out. A table's
+/// value is the key of its example entry.
+const UNSET_KEYS: &[(&str, &str, bool)] = &[
+    ("table_order_root", "[\"workspace\", \"package\"]", true),
+    ("allow_unknown_keys", "false", false),
+    ("extends", "\"../tomlfmt.toml\"", true),
+    ("profile", "ci", true),
+    ("override", "\"crates/legacy/**\"", true),
+    ("tables", "\"workspace.members\"", true),
+];
+
+/// Renders a config file with every option set to its default value, each
+/// preceded by a comment explaining it. The options without a default are
+/// shown commented out with an example value.
+pub(crate) fn default_config_file() -> String {
+    let default = config_table(&Config::default());
+    let (mut file, mut tables) = (String::new(), String::new());
+    for key in KEYS {
+        let unset = UNSET_KEYS.iter().find(|(name, ..)| *name == key.name);
+        match (default.get(key.name), unset) {
+            (Some(item), _) if key.kind == Kind::Table => {
+                tables.push_str(&format!("\n# {}\n[{}]\n", key.doc, key.name));
+                for child in key.children {
+                    let value = item.get(child.name).and_then(Item::as_value).map(|v| v.to_string());
+                    tables.push_str(&format!("# {}\n{} = {}\n", child.doc, child.name, value.unwrap_or_default().trim()));
+                }
+            }
+            (Some(item), _) => {
+                let value = item.as_value().map(|v| v.to_string()).unwrap_or_default();
+                file.push_str(&format!("# {}\n{} = {}\n", key.doc, key.name, value.trim()));
+            }
+            (None, Some(&(_, example, _))) if key.kind == Kind::Table => {
+                tables.push_str(&format!("\n# {}\n# [{}.{example}]\n", key.doc, key.name));
+                for child in key.children.iter().flat_map(|child| child.children) {
+                    tables.push_str(&format!("# # {}\n# {} = true\n", child.doc, child.name));
+                }
+            }
+            (None, Some(&(_, value, commented))) => {
+                let comment = if commented { "# " } else { "" };
+                file.push_str(&format!("# {}\n{comment}{} = {value}\n", key.doc, key.name));
+            }
+            (None, None) => unreachable!("`{}` has neither a default nor an example", key.name),
+        }
+    }
+    file + &tables
+}
+
+/// Where the value of a config key came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// No layer set the key.
+    Default,
+    /// The per user config file, see [`user_config_file`].
+    User(PathBuf),
+    /// A `tomlfmt.toml` file.
+    File(PathBuf),
+    /// A `[profile.<name>]` section of a config file.
+    Profile { file: PathBuf, name: String },
+    /// An `[override."<pattern>"]` section of a config file.
+    Override { file: PathBuf, pattern: String },
+    /// `[workspace.metadata.cargo-sort-ng]` in the workspace root manifest.
+    WorkspaceMetadata(PathBuf),
+    /// `[package.metadata.cargo-sort-ng]` in the manifest being processed.
+    PackageMetadata(PathBuf),
+    /// A command line flag.
+    Cli,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::User(path) | Source::File(path) => write!(f, "{}", path.display()),
+            Source::Profile { file, name } => write!(f, "{} [profile.{name}]", file.display()),
+            Source::Override { file, pattern } => write!(f, "{} [override.\"{pattern}\"]", file.display()),
+            Source::WorkspaceMetadata(path) => write!(f, "{} [workspace.metadata.{METADATA_KEY}]", path.display()),
+            Source::PackageMetadata(path) => write!(f, "{} [package.metadata.{METADATA_KEY}]", path.display()),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Config tables ordered from lowest to highest precedence.
+///
+/// A key set in a later layer overrides the same key in every earlier layer,
+/// keys no layer sets keep their default value.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Layers {
+    /// Shared with the copies made for each manifest.
+    layers: Vec<Layer>,
+    profiles: Vec<Profile>,
+    overrides: Vec<Override>,
+    warnings: Vec<String>,
+    /// The ancestor manifests looked at for a workspace root, by absolute
+    /// path, shared with the copies so each is parsed once. `None` for one
+    /// that is not a workspace root.
+    roots: Arc<Mutex<HashMap<PathBuf, Option<Arc<DocumentMut>>>>>,
+}
+
+/// A config table and where it comes from.
+type Layer = Arc<(Source, Table)>;
+
+/// A `[profile.<name>]` section, applied on top of the layer of its config
+/// file when selected with `--profile`.
+#[derive(Debug, Clone)]
+struct Profile {
+    /// The layer of the config file.
+    layer: usize,
+    name: String,
+    settings: Layer,
+}
+
+/// An `[override."<pattern>"]` section, applied on top of the layer of its
+/// config file for the manifests the pattern matches.
+#[derive(Debug, Clone)]
+struct Override {
+    /// The layer the override goes right above, that of its config file or
+    /// of the profile selected from it.
+    layer: usize,
+    /// The directory of the config file, the pattern is relative to it.
+    base: PathBuf,
+    glob: glob::Pattern,
+    settings: Layer,
+}
+
+/// A renamed config key as `(old, new, convert)`, `convert` turns a value
+/// of the old key into one of the new key.
+type Rename = (&'static str, &'static str, fn(&Item) -> Item);
+
+/// Config keys that have been renamed.
+///
+/// The old names keep working with a warning.
+pub(crate) const RENAMED_KEYS: &[Rename] = &[
+    ("trailing_comma", "always_trailing_comma", Item::clone),
+    ("crlf", "newline_style", |item| match item.as_bool() {
+        Some(true) => value(NewlineStyle::Crlf.as_str()),
+        Some(false) => value(NewlineStyle::Lf.as_str()),
+        None => item.clone(),
+    }),
+];
+
+/// Keys whose tables are merged key by key instead of being replaced, with
+/// the number of table levels merged.
+const MERGED_TABLES: &[(&str, usize)] = &[("tables", 2), ("format", 1)];
+
+/// Inserts every key of `from` into `into`, `depth` levels of tables present
+/// in both are merged instead of replaced.
+fn merge(into: &mut dyn TableLike, from: &dyn TableLike, depth: usize) {
+    for (key, item) in from.iter() {
+        if depth > 0
+            && let Some(from) = item.as_table_like()
+            && let Some(into) = into.get_mut(key).and_then(Item::as_table_like_mut)
+        {
+            merge(into, from, depth - 1);
+        } else {
+            into.insert(key, item.clone());
+        }
+    }
+}
+
+/// Rewrites the renamed keys of `table` to their new names, returning a
+/// warning for each.
+///
+/// Setting both the old and the new name to different values is an error.
+fn migrate_renamed_keys(table: &mut Table, source: &Source) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+    for &(old, new, convert) in RENAMED_KEYS {
+        let Some(item) = table.remove(old).map(|item| convert(&item)) else {
+            continue;
+        };
+        match table.get(new) {
+            Some(current) if canonical(current) != canonical(&item) => {
+                return Err(Error::Config(format!(
+                    "`{old}` and `{new}` are both set in {source} with different values, remove `{old}`"
+                )));
+            }
+            Some(_) => {}
+            None => {
+                table.insert(new, item);
+            }
+        }
+        warnings.push(format!("`{old}` in {source} is deprecated, rename it to `{new}`"));
+    }
+    Ok(warnings)
+}
+
+/// Turns the `format = <bool>` master switch into `no_format`, `format` is
+/// otherwise the table of formatting rules.
+fn expand_format_switch(table: &mut Table, source: &Source) -> Result<()> {
+    let Some(format) = table.get("format").and_then(Item::as_bool) else {
+        return Ok(());
+    };
+    if table
+        .get("no_format")
+        .and_then(Item::as_bool)
+        .is_some_and(|no_format| no_format == format)
+    {
+        return Err(Error::Config(format!("`format` and `no_format` contradict each other in {source}")));
+    }
+    table.remove("format");
+    table.insert("no_format", value(!format));
+    Ok(())
+}
+
+/// Renders `item` without its formatting so equal values compare equal.
+fn canonical(item: &Item) -> String {
+    fn plain(value: &Value) -> String {
+        match value {
+            Value::Array(arr) => format!("[{}]", arr.iter().map(plain).collect::<Vec<_>>().join(",")),
+            Value::InlineTable(table) => {
+                let mut entries: Vec<_> = table.iter().map(|(k, v)| format!("{k}={}", plain(v))).collect();
+                entries.sort();
+                format!("{{{}}}", entries.join(","))
+            }
+            _ => value.clone().decorated("", "").to_string(),
+        }
+    }
+    match item {
+        Item::Value(v) => plain(v),
+        _ => item.to_string(),
+    }
+}
+
+impl Layers {
+    /// Adds a layer that takes precedence over all layers added before it.
+    ///
+    /// Renamed keys are rewritten to their new name, see [`RENAMED_KEYS`].
+    pub(crate) fn push(&mut self, source: Source, mut table: Table) -> Result<()> {
+        self.warnings.extend(migrate_renamed_keys(&mut table, &source)?);
+        expand_format_switch(&mut table, &source)?;
+        // a value of the wrong type is left for `config` to report
+        if table.get("override").is_some_and(Item::is_table_like)
+            && let Some(overrides) = table.remove("override")
+        {
+            self.add_overrides(&source, &overrides)?;
+        }
+        if table.get("profile").is_some_and(Item::is_table_like)
+            && let Some(profiles) = table.remove("profile")
+        {
+            self.add_profiles(&source, &profiles)?;
+        }
+        self.layers.push(Arc::new((source, table)));
+        Ok(())
+    }
+
+    /// Stores the `[profile]` sections of the config file of `source`, see
+    /// [`Layers::select_profile`].
+    fn add_profiles(&mut self, source: &Source, profiles: &Item) -> Result<()> {
+        let (Source::User(file) | Source::File(file)) = source else {
+            return Err(Error::Config(format!(
+                "`profile` sections are only allowed in config files, not in {source}"
+            )));
+        };
+        for (name, item) in profiles.as_table_like().into_iter().flat_map(|p| p.iter()) {
+            let source = Source::Profile {
+                file: file.clone(),
+                name: name.to_owned(),
+            };
+            let Some(settings) = item.as_table_like() else {
+                return Err(Error::Config(format!(
+                    "{}: `profile.{name}` expects a table",
+                    location(&source, &[])
+                )));
+            };
+            let mut table = Table::new();
+            for (key, value) in settings.iter() {
+                table.insert(key, value.clone());
+            }
+            self.warnings.extend(migrate_renamed_keys(&mut table, &source)?);
+            expand_format_switch(&mut table, &source)?;
+            self.profiles.push(Profile {
+                layer: self.layers.len(),
+                name: name.to_owned(),
+                settings: Arc::new((source, table)),
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the `[profile.<name>]` section of every config file defining
+    /// it on top of that file.
+    ///
+    /// A name no config file defines is an error listing the known profiles.
+    pub(crate) fn select_profile(&mut self, name: &str) -> Result<()> {
+        let profiles = std::mem::take(&mut self.profiles);
+        let selected = profiles.iter().filter(|p| p.name == name).collect::<Vec<_>>();
+        if selected.is_empty() {
+            let mut names = profiles.iter().map(|p| format!("`{}`", p.name)).collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            return Err(Error::Config(if names.is_empty() {
+                format!("unknown profile `{name}`, no config file defines any profiles")
+            } else {
+                format!("unknown profile `{name}`, available profiles are {}", names.join(", "))
+            }));
+        }
+        for profile in selected.into_iter().rev() {
+            self.layers.insert(profile.layer + 1, Arc::clone(&profile.settings));
+            for o in &mut self.overrides {
+                if o.layer >= profile.layer {
+                    o.layer += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the `[override]` sections of the config file of `source`, they
+    /// are applied by [`Layers::for_manifest`].
+    fn add_overrides(&mut self, source: &Source, overrides: &Item) -> Result<()> {
+        let (Source::User(file) | Source::File(file)) = source else {
+            return Err(Error::Config(format!(
+                "`override` sections are only allowed in config files, not in {source}"
+            )));
+        };
+        let Some(overrides) = overrides.as_table_like() else {
+            return Ok(());
+        };
+        let base = std::path::absolute(file.parent().unwrap_or(Path::new("")))?;
+        for (pattern, item) in overrides.iter() {
+            let source = Source::Override {
+                file: file.clone(),
+                pattern: pattern.to_owned(),
+            };
+            let Some(settings) = item.as_table_like() else {
+                return Err(Error::Config(format!(
+                    "{}: `override.{pattern}` expects a table",
+                    location(&source, &[])
+                )));
+            };
+            let mut table = Table::new();
+            for (key, value) in settings.iter() {
+                table.insert(key, value.clone());
+            }
+            self.warnings.extend(migrate_renamed_keys(&mut table, &source)?);
+            expand_format_switch(&mut table, &source)?;
+            let glob = glob::Pattern::new(pattern).map_err(|e| Error::Config(format!("invalid override pattern `{pattern}`: {e}")))?;
+            self.overrides.push(Override {
+                layer: self.layers.len(),
+                base: base.clone(),
+                glob,
+                settings: Arc::new((source, table)),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns a copy with the metadata layers of a manifest and the command
+    /// line layer added on top.
+    ///
+    /// The copy shares the config tables instead of cloning them and only
+    /// keeps the warnings of the layers added here.
+    pub(crate) fn for_manifest(&self, path: &Path, doc: Option<&DocumentMut>, cli: Table) -> Result<Self> {
+        let mut layers = self.clone();
+        layers.warnings.clear();
+        // The first matching override of each config file applies, later
+        // files are higher up so start at the end to keep the indices valid.
+        let manifest = std::path::absolute(path)?;
+        let mut applied: Vec<&Override> = vec![];
+        for o in &self.overrides {
+            if glob_matches(&o.glob, &o.base, &manifest) && !applied.iter().any(|a| a.layer == o.layer) {
+                applied.push(o);
+            }
+        }
+        for o in applied.into_iter().rev() {
+            layers.layers.insert(o.layer + 1, Arc::clone(&o.settings));
+        }
+        layers.overrides.clear();
+        if let Some((root, root_doc)) = self.find_workspace_root(path, doc)
+            && let Some(table) = metadata_table(&root_doc, "workspace")
+        {
+            layers.push(Source::WorkspaceMetadata(root), table)?;
+        }
+        if let Some(table) = doc.and_then(|doc| metadata_table(doc, "package")) {
+            layers.push(Source::PackageMetadata(path.to_owned()), table)?;
+        }
+        layers.push(Source::Cli, cli)?;
+        Ok(layers)
+    }
+
+    /// Records the already parsed manifest at `path`, for the members that
+    /// look for their workspace root.
+    pub(crate) fn add_manifest(&self, path: &Path, doc: &DocumentMut) {
+        if let Ok(path) = std::path::absolute(path) {
+            let root = doc.contains_key("workspace").then(|| Arc::new(doc.clone()));
+            self.roots.lock().expect("the lock is not poisoned").insert(path, root);
+        }
+    }
+
+    /// Finds the workspace root for a manifest, the manifest itself when it
+    /// has a `[workspace]` table, otherwise the nearest ancestor `Cargo.toml`
+    /// with one.
+    fn find_workspace_root(&self, path: &Path, doc: Option<&DocumentMut>) -> Option<(PathBuf, Arc<DocumentMut>)> {
+        if let Some(doc) = doc.filter(|doc| doc.contains_key("workspace")) {
+            return Some((path.to_owned(), Arc::new(doc.clone())));
+        }
+        let dir = std::path::absolute(path).ok()?;
+        for ancestor in dir.ancestors().skip(2) {
+            let candidate = ancestor.join(CARGO_TOML);
+            let mut roots = self.roots.lock().expect("the lock is not poisoned");
+            let root = roots.entry(candidate.clone()).or_insert_with(|| {
+                let doc = read_to_string(&candidate).ok()?.parse::<DocumentMut>().ok()?;
+                doc.contains_key("workspace").then(|| Arc::new(doc))
+            });
+            if let Some(root) = root {
+                return Some((candidate, Arc::clone(root)));
+            }
+        }
+        None
+    }
+
+    /// Returns the `[override]` sections applied to the manifest.
+    pub(crate) fn applied_overrides(&self) -> impl Iterator<Item = &Source> {
+        self.layers
+            .iter()
+            .map(|layer| &layer.0)
+            .filter(|source| matches!(source, Source::Override { .. }))
+    }
+
+    /// Returns the deprecation warnings of the layers.
+    pub(crate) fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Merges all layers into a single table.
+    ///
+    /// A key set in a later layer replaces the value of the earlier layers,
+    /// arrays such as `table_order` included, they are never appended to. The
+    /// `tables` sections are merged per table and then per key, so a member
+    /// can override just `format` of a table the root sets `sort` for, and
+    /// the `format` rules are merged per rule.
+    pub(crate) fn merged(&self) -> Table {
+        let mut merged = Table::new();
+        for layer in &self.layers {
+            for (key, item) in layer.1.iter() {
+                let depth = MERGED_TABLES.iter().find(|(name, _)| *name == key).map_or(0, |&(_, depth)| depth);
+                if depth > 0
+                    && let Some(from) = item.as_table_like()
+                    && let Some(into) = merged.get_mut(key).and_then(Item::as_table_like_mut)
+                {
+                    merge(into, from, depth - 1);
+                    continue;
+                }
+                merged.insert(key, item.clone());
+            }
+        }
+        merged
+    }
+
+    /// Returns the layer that decided the value of `key`.
+    pub(crate) fn source_of(&self, key: &str) -> Source {
+        self.layers
+            .iter()
+            .rev()
+            .find(|layer| layer.1.contains_key(key))
+            .map_or(Source::Default, |layer| layer.0.clone())
+    }
+
+    /// Builds the effective config.
+    ///
+    /// Values of the wrong type are an error, as are unknown keys unless
+    /// `allow_unknown_keys = true` is set.
+    pub(crate) fn config(&self) -> Result<Config> {
+        let merged = self.merged();
+        let allow_unknown = merged.get("allow_unknown_keys").and_then(Item::as_bool).unwrap_or_default();
+        for layer in &self.layers {
+            let (source, table) = &**layer;
+            check_keys(table, KEYS, &[], source, allow_unknown)?;
+        }
+        DocumentMut::from(merged).to_string().parse::<Config>()
+    }
+
+    /// Renders the effective config, each key annotated with its source.
+    pub(crate) fn show(&self) -> Result<String> {
+        let mut shown = String::new();
+        for (key, item) in config_table(&self.config()?).iter() {
+            let value = item.as_value().map(|v| v.to_string()).unwrap_or_default();
+            shown.push_str(&format!("{key} = {} # {}\n", value.trim(), self.source_of(key)));
+        }
+        Ok(shown)
+    }
+}
+
+/// Errors on the first key of `table` that is not in `known` suggesting the
+/// closest known key, or whose value has the wrong type.
+fn check_keys(table: &Table, known: &[Key], path: &[&str], source: &Source, allow_unknown: bool) -> Result<()> {
+    let prefix = path.iter().map(|seg| format!("{seg}.")).collect::<String>();
+    for (name, item) in table.iter() {
+        let Some(key) = known.iter().find(|k| k.name == name || k.name == "*") else {
+            if allow_unknown {
+                continue;
+            }
+            let mut msg = format!("unknown config key `{prefix}{name}` in {source}");
+            // a typo of an old name is pointed at the new one
+            let renamed = RENAMED_KEYS.iter().filter(|_| path.is_empty()).map(|&(old, new, _)| (old, new));
+            if let Some(suggestion) = suggest(name, known.iter().map(|k| (k.name, k.name)).chain(renamed)) {
+                msg.push_str(&format!(", did you mean `{prefix}{suggestion}`?"));
+            }
+            return Err(Error::Config(msg));
+        };
+        let mut key_path = path.to_vec();
+        key_path.push(name);
+        if let Some(found) = key.kind.mismatch(item) {
+            let location = location(source, &key_path);
+            return Err(Error::Config(format!(
+                "{location}: `{prefix}{name}` expects {}, found {found}",
+                key.kind.expected()
+            )));
+        }
+        if !key.children.is_empty()
+            && let Some(inner) = item.as_table_like()
+        {
+            let mut nested = Table::new();
+            for (k, v) in inner.iter() {
+                nested.insert(k, v.clone());
+            }
+            check_keys(&nested, key.children, &key_path, source, allow_unknown)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `file:line` for the config key at `path` in the file of `source`,
+/// falling back to just the source when the key can not be found.
+fn location(source: &Source, path: &[&str]) -> String {
+    let (file, prefix) = match source {
+        Source::User(file) | Source::File(file) => (file, vec![]),
+        Source::Profile { file, name } => (file, vec!["profile", name.as_str()]),
+        Source::Override { file, pattern } => (file, vec!["override", pattern.as_str()]),
+        Source::WorkspaceMetadata(file) => (file, vec!["workspace", "metadata", METADATA_KEY]),
+        Source::PackageMetadata(file) => (file, vec!["package", "metadata", METADATA_KEY]),
+        Source::Default | Source::Cli => return source.to_string(),
+    };
+    let line = read_to_string(file).ok().and_then(|raw| {
+        let doc = ImDocument::parse(raw).ok()?;
+        let segs = prefix.iter().chain(path).copied().collect::<Vec<_>>();
+        let (last, parents) = segs.split_last()?;
+        let mut table: &dyn TableLike = doc.as_table();
+        for seg in parents {
+            table = table.get(seg)?.as_table_like()?;
+        }
+        let start = table.key(last)?.span()?.start;
+        Some(doc.raw()[..start].matches('\n').count() + 1)
+    });
+    match line {
+        Some(line) => format!("{}:{line}", file.display()),
+        None => file.display().to_string(),
+    }
+}
+
+/// Returns the suggestion of the `(name, suggestion)` pair of `known` whose
+/// name is closest to `name`, if any is close enough to be a likely typo.
+fn suggest<'a>(name: &str, known: impl Iterator<Item = (&'a str, &'a str)>) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).max(2);
+    known
+        .filter(|(k, _)| *k != "*")
+        .map(|(k, suggestion)| (levenshtein(name, k), suggestion))
+        .filter(|&(dist, _)| dist <= max)
+        .min_by_key(|&(dist, _)| dist)
+        .map(|(_, suggestion)| suggestion)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+/// Reads the first config file found in `dir`.
+pub(crate) fn read_config_file(dir: &Path) -> Result<Option<(PathBuf, Table)>> {
+    for name in CONFIG_FILES {
+        let path = dir.join(name);
+        if let Some(table) = read_toml_file(&path)? {
+            return Ok(Some((path, table)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads a config file, `None` if it can not be read.
+fn read_toml_file(path: &Path) -> Result<Option<Table>> {
+    let Ok(raw) = read_to_string(path) else {
+        return Ok(None);
+    };
+    let doc = parse_toml(&raw).map_err(|err| Error::from(err).with_path(path))?;
+    Ok(Some(doc.as_table().clone()))
+}
+
+/// Returns the per user config file, `cargo-sort-ng/config.toml` in the
+/// config directory of the platform.
+///
+/// That directory is `$XDG_CONFIG_HOME` or `~/.config` on Linux,
+/// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows, the
+/// same the `dirs` crate uses.
+pub(crate) fn user_config_file() -> Option<PathBuf> {
+    let env = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        env("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        env("XDG_CONFIG_HOME")
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env("HOME").map(|home| home.join(".config")))
+    };
+    Some(dir?.join(METADATA_KEY).join("config.toml"))
+}
+
+/// Reads the per user config file at `path` and the files it extends, base
+/// first.
+pub(crate) fn read_user_config(path: &Path) -> Result<Vec<(PathBuf, Table)>> {
+    match read_toml_file(path)? {
+        Some(table) => resolve_extends(path.to_owned(), table),
+        None => Ok(vec![]),
+    }
+}
+
+/// Glob patterns of manifests to skip, each matched relative to its own base
+/// directory.
+#[derive(Debug, Default)]
+pub(crate) struct Ignore {
+    patterns: Vec<(PathBuf, glob::Pattern)>,
+}
+
+impl Ignore {
+    /// Adds `patterns`, relative to `base`.
+    pub(crate) fn add(&mut self, base: &Path, patterns: &[String]) -> Result<()> {
+        let base = std::path::absolute(base)?;
+        for pattern in patterns {
+            let glob = glob::Pattern::new(pattern).map_err(|e| Error::Config(format!("invalid ignore pattern `{pattern}`: {e}")))?;
+            self.patterns.push((base.clone(), glob));
+        }
+        Ok(())
+    }
+
+    /// Returns the pattern ignoring `manifest`, if any.
+    ///
+    /// Patterns match either the manifest itself or its directory.
+    pub(crate) fn matching(&self, manifest: &Path) -> Option<&str> {
+        let manifest = std::path::absolute(manifest).ok()?;
+        self.patterns
+            .iter()
+            .find(|(base, glob)| glob_matches(glob, base, &manifest))
+            .map(|(_, glob)| glob.as_str())
+    }
+}
+
+/// Returns `true` if `glob`, relative to `base`, matches the absolute
+/// `manifest` or its directory.
+fn glob_matches(glob: &glob::Pattern, base: &Path, manifest: &Path) -> bool {
+    let Ok(rel) = manifest.strip_prefix(base) else {
+        return false;
+    };
+    let dir = rel.parent().unwrap_or(Path::new(""));
+    glob.matches_path(rel) || glob.matches_path(dir)
+}
+
+/// Returns the directory relative paths set by `source` are resolved against.
+pub(crate) fn base_dir(source: &Source, cwd: &Path) -> PathBuf {
+    match source {
+        Source::User(path)
+        | Source::File(path)
+        | Source::Profile { file: path, .. }
+        | Source::Override { file: path, .. }
+        | Source::WorkspaceMetadata(path)
+        | Source::PackageMetadata(path) => path.parent().map_or_else(|| cwd.to_owned(), |dir| cwd.join(dir)),
+        Source::Default | Source::Cli => cwd.to_owned(),
+    }
+}
+
+/// Resolves the `extends` chain of a config file.
+///
+/// Returns the files from the base of the chain to `path` itself, so pushing
+/// them in order lets every file override the ones it extends. `extends` is
+/// relative to the file containing it unless absolute.
+pub(crate) fn resolve_extends(path: PathBuf, table: Table) -> Result<Vec<(PathBuf, Table)>> {
+    let mut chain: Vec<(PathBuf, PathBuf, Table)> = vec![];
+    let (mut path, mut table) = (path, table);
+    loop {
+        let extends = match table.get("extends") {
+            None => None,
+            Some(item) => {
+                let base = item
+                    .as_str()
+                    .ok_or_else(|| Error::Config(format!("`extends` in {} must be a string", path.display())))?;
+                Some(path.parent().unwrap_or(Path::new("")).join(base))
+            }
+        };
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if chain.iter().any(|(seen, _, _)| *seen == canonical) {
+            let cycle = chain
+                .iter()
+                .map(|(_, p, _): &(PathBuf, PathBuf, Table)| p.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(Error::Config(format!(
+                "config files extend each other in a cycle: {} -> {}",
+                cycle.join(" -> "),
+                path.display()
+            )));
+        }
+        chain.push((canonical, path, table));
+
+        let Some(base) = extends else { break };
+        let raw = read_file(&base)?;
+        let doc = parse_toml(&raw).map_err(|err| Error::from(err).with_path(&base))?;
+        (path, table) = (base, doc.as_table().clone());
+    }
+    Ok(chain.into_iter().rev().map(|(_, path, table)| (path, table)).collect())
+}
+
+/// Returns the other config files next to the chosen `path`, these are
+/// ignored and should be warned about.
+pub(crate) fn shadowed_config_files(path: &Path) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    CONFIG_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|other| other != path && other.is_file())
+        .collect()
+}
+
+/// Finds the nearest config file, looking in `start` and then its ancestors.
+///
+/// The search stops at the filesystem root or after the first directory that
+/// contains `.git`, the root of the repository.
+pub(crate) fn discover_config_file(start: &Path) -> Result<Option<(PathBuf, Table)>> {
+    for dir in start.ancestors() {
+        if let Some(found) = read_config_file(dir)? {
+            return Ok(Some(found));
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Returns `[<section>.metadata.cargo-sort-ng]` of a manifest.
+fn metadata_table(doc: &DocumentMut, section: &str) -> Option<Table> {
+    match doc.get(section)?.get("metadata")?.get(METADATA_KEY)? {
+        Item::Table(table) => Some(table.clone()),
+        Item::Value(value) => value.as_inline_table().map(|t| t.clone().into_table()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use toml_edit::{DocumentMut, Table};
+
+    use super::{
+        Ignore, KEYS, Kind, Layers, Source, config_table, default_config_file, discover_config_file, read_user_config, resolve_extends,
+        shadowed_config_files,
+    };
+    use cargo_sort_fix::{Config, NewlineStyle, TableOverride};
+
+    use crate::test_utils::temp_dir;
+
+    fn table(s: &str) -> Table {
+        s.parse::<DocumentMut>().unwrap().as_table().clone()
+    }
+
+    #[test]
+    fn precedence() {
+        let manifest = r#"
+[package]
+name = "foo"
+
+[package.metadata.cargo-sort-ng]
+grouped = true
+indent_count = 8
+
+[workspace]
+
+[workspace.metadata.cargo-sort-ng]
+grouped = false
+indent_count = 2
+allowed_blank_lines = 3
+max_array_line_len = 100
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let path = Path::new("foo/Cargo.toml");
+        let file = PathBuf::from("tomlfmt.toml");
+
+        let mut base = Layers::default();
+        base.push(
+            Source::File(file.clone()),
+            table("max_array_line_len = 120\ncompact_arrays = true\n"),
+        )
+        .unwrap();
+        let layers = base.for_manifest(path, Some(&manifest), table("indent_count = 6\n")).unwrap();
+        let config = layers.config().unwrap();
+
+        assert_eq!(config.indent_count, 6);
+        assert_eq!(layers.source_of("indent_count"), Source::Cli);
+        assert!(config.grouped);
+        assert_eq!(layers.source_of("grouped"), Source::PackageMetadata(path.to_owned()));
+        assert_eq!(config.max_array_line_len, 100);
+        assert_eq!(layers.source_of("max_array_line_len"), Source::WorkspaceMetadata(path.to_owned()));
+        assert!(config.compact_arrays);
+        assert_eq!(layers.source_of("compact_arrays"), Source::File(file));
+        assert!(config.trailing_newline);
+        assert_eq!(layers.source_of("trailing_newline"), Source::Default);
+    }
+
+    #[test]
+    fn every_config_key_is_known() {
+        let config = Config {
+            table_order_root: Some(vec![]),
+            tables: [("features".to_owned(), Default::default())].into(),
+            ..Config::default()
+        };
+        for (key, _) in config_table(&config).iter() {
+            assert!(KEYS.iter().any(|k| k.name == key), "`{key}` is missing from KEYS");
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let mut layers = Layers::default();
+        layers
+            .push(Source::File(PathBuf::from("tomlfmt.toml")), table("tabel_order = []\n"))
+            .unwrap();
+        let err = layers.config().unwrap_err().to_string();
+        assert_eq!(err, "unknown config key `tabel_order` in tomlfmt.toml, did you mean `table_order`?");
+
+        let mut layers = Layers::default();
+        layers
+            .push(Source::File(PathBuf::from("tomlfmt.toml")), table("frobnicate = 1\n"))
+            .unwrap();
+        let err = layers.config().unwrap_err().to_string();
+        assert_eq!(err, "unknown config key `frobnicate` in tomlfmt.toml");
+
+        let mut renamed = Layers::default();
+        renamed
+            .push(
+                Source::File(PathBuf::from("tomlfmt.toml")),
+                table(
+                    "trailing_commas = true
+",
+                ),
+            )
+            .unwrap();
+        let err = renamed.config().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "unknown config key `trailing_commas` in tomlfmt.toml, did you mean `always_trailing_comma`?"
+        );
+
+        layers
+            .push(Source::File(PathBuf::from("other.toml")), table("allow_unknown_keys = true\n"))
+            .unwrap();
+        assert_eq!(layers.config().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn member_merge() {
+        let root = r#"
+[workspace.metadata.cargo-sort-ng]
+indent_count = 2
+table_order = ["package", "dependencies"]
+[workspace.metadata.cargo-sort-ng.tables.features]
+sort = true
+grouped = true
+[workspace.metadata.cargo-sort-ng.tables.dependencies]
+grouped = true
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let member = r#"
+[package]
+name = "member"
+[package.metadata.cargo-sort-ng]
+indent_count = 6
+table_order = ["features"]
+tables = { features = { grouped = false, format = false }, "workspace.members" = { format = false } }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let dir = temp_dir("member-merge");
+        std::fs::create_dir_all(dir.join("member")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), root.to_string()).unwrap();
+        let path = dir.join("member/Cargo.toml");
+        let flags = |sort, grouped, format| TableOverride { sort, grouped, format };
+
+        let layers = Layers::default().for_manifest(&path, Some(&member), Table::new()).unwrap();
+        let config = layers.config().unwrap();
+        assert_eq!(config.indent_count, 6);
+        assert_eq!(config.table_order, ["features"]);
+        assert_eq!(config.tables["features"], flags(Some(true), Some(false), Some(false)));
+        assert_eq!(config.tables["dependencies"], flags(None, Some(true), None));
+        assert_eq!(config.tables["workspace.members"], flags(None, None, Some(false)));
+
+        let empty = "[package]\nname = \"member\"\n[package.metadata.cargo-sort-ng]\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let layers = Layers::default().for_manifest(&path, Some(&empty), Table::new()).unwrap();
+        let config = layers.config().unwrap();
+        assert_eq!(config.indent_count, 2);
+        assert_eq!(config.table_order, ["package", "dependencies"]);
+        assert_eq!(config.tables["features"], flags(Some(true), Some(true), None));
+        assert_eq!(layers.source_of("indent_count"), Source::WorkspaceMetadata(dir.join("Cargo.toml")));
+    }
+
+    #[test]
+    fn workspace_root_is_parsed_once() {
+        let root = "[workspace]\n[workspace.metadata.cargo-sort-ng]\nindent_count = 3\n";
+        let dir = temp_dir("root-once");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), root).unwrap();
+        let member = "[package]\nname = \"a\"\n".parse::<DocumentMut>().unwrap();
+        let indent = |layers: &Layers| {
+            layers
+                .for_manifest(&dir.join("a/Cargo.toml"), Some(&member), Table::new())
+                .unwrap()
+                .config()
+                .unwrap()
+                .indent_count
+        };
+
+        let layers = Layers::default();
+        assert_eq!(indent(&layers), 3);
+        // the copies share what was parsed, the file is not read again
+        std::fs::write(dir.join("Cargo.toml"), "[package]\n").unwrap();
+        assert_eq!(indent(&layers.clone()), 3);
+        assert_eq!(indent(&Layers::default()), 4);
+
+        let layers = Layers::default();
+        layers.add_manifest(&dir.join("Cargo.toml"), &root.parse().unwrap());
+        assert_eq!(indent(&layers), 3);
+    }
+
+    #[test]
+    fn override_sections() {
+        let dir = temp_dir("overrides");
+        let file = dir.join("tomlfmt.toml");
+        let raw = r#"grouped = true
+indent_count = 6
+
+[override."crates/legacy/**"]
+grouped = false
+indent_count = 2
+
+[override."crates/**"]
+indent_count = 8
+"#;
+        std::fs::write(&file, raw).unwrap();
+        let mut base = Layers::default();
+        base.push(Source::File(file.clone()), table(raw)).unwrap();
+        let layers = |manifest: &str, cli: &str| base.for_manifest(&dir.join(manifest), None, table(cli)).unwrap();
+        let legacy = Source::Override {
+            file: file.clone(),
+            pattern: "crates/legacy/**".to_owned(),
+        };
+
+        let config = layers("crates/legacy/foo/Cargo.toml", "").config().unwrap();
+        assert!(!config.grouped);
+        assert_eq!(config.indent_count, 2);
+        let applied = layers("crates/legacy/foo/Cargo.toml", "");
+        assert_eq!(applied.applied_overrides().collect::<Vec<_>>(), [&legacy]);
+        assert_eq!(applied.source_of("grouped"), legacy);
+
+        let config = layers("crates/other/Cargo.toml", "").config().unwrap();
+        assert!(config.grouped);
+        assert_eq!(config.indent_count, 8);
+
+        let config = layers("Cargo.toml", "").config().unwrap();
+        assert_eq!(config.indent_count, 6);
+        assert_eq!(layers("Cargo.toml", "").applied_overrides().count(), 0);
+
+        assert_eq!(
+            layers("crates/legacy/foo/Cargo.toml", "indent_count = 3")
+                .config()
+                .unwrap()
+                .indent_count,
+            3
+        );
+
+        let raw = "[override.\"crates/**\"]\nindent_count = \"2\"\n";
+        std::fs::write(&file, raw).unwrap();
+        let mut base = Layers::default();
+        base.push(Source::File(file.clone()), table(raw)).unwrap();
+        let err = base
+            .for_manifest(&dir.join("crates/a/Cargo.toml"), None, Table::new())
+            .unwrap()
+            .config()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:2: `indent_count` expects a non-negative integer, found string", file.display())
+        );
+
+        let err = Layers::default().push(Source::Cli, table(raw)).unwrap_err().to_string();
+        assert_eq!(err, "`override` sections are only allowed in config files, not in command line");
+    }
+
+    #[test]
+    fn profiles() {
+        let dir = temp_dir("profiles");
+        let file = dir.join("tomlfmt.toml");
+        let raw = r#"grouped = true
+
+[profile.ci]
+check_format = true
+
+[profile.local]
+no_format = true
+grouped = false
+
+[override."crates/**"]
+grouped = true
+"#;
+        let mut base = Layers::default();
+        base.push(Source::File(file.clone()), table(raw)).unwrap();
+        let manifest = dir.join("crates/a/Cargo.toml");
+
+        let config = base.for_manifest(&manifest, None, Table::new()).unwrap().config().unwrap();
+        assert!(!config.check_format && !config.no_format);
+
+        let mut ci = base.clone();
+        ci.select_profile("ci").unwrap();
+        let layers = ci.for_manifest(&dir.join("Cargo.toml"), None, Table::new()).unwrap();
+        assert!(layers.config().unwrap().check_format);
+        let profile = Source::Profile {
+            file: file.clone(),
+            name: "ci".to_owned(),
+        };
+        assert_eq!(layers.source_of("check_format"), profile);
+
+        let mut local = base.clone();
+        local.select_profile("local").unwrap();
+        let config = local
+            .for_manifest(&dir.join("Cargo.toml"), None, Table::new())
+            .unwrap()
+            .config()
+            .unwrap();
+        assert!(config.no_format && !config.grouped);
+        // overrides go above the profile of their file
+        let config = local.for_manifest(&manifest, None, Table::new()).unwrap().config().unwrap();
+        assert!(config.no_format && config.grouped);
+
+        let err = base.clone().select_profile("strict").unwrap_err().to_string();
+        assert_eq!(err, "unknown profile `strict`, available profiles are `ci`, `local`");
+        let err = Layers::default().select_profile("ci").unwrap_err().to_string();
+        assert_eq!(err, "unknown profile `ci`, no config file defines any profiles");
+    }
+
+    #[test]
+    fn user_config_layer() {
+        let dir = temp_dir("user-config");
+        let user = dir.join("cargo-sort-ng/config.toml");
+        assert!(read_user_config(&user).unwrap().is_empty());
+
+        std::fs::create_dir_all(user.parent().unwrap()).unwrap();
+        std::fs::write(&user, "indent_count = 2\ngrouped = true\n").unwrap();
+        let mut layers = Layers::default();
+        for (path, table) in read_user_config(&user).unwrap() {
+            layers.push(Source::User(path), table).unwrap();
+        }
+        layers
+            .push(Source::File(PathBuf::from("tomlfmt.toml")), table("indent_count = 6\n"))
+            .unwrap();
+        let config = layers.config().unwrap();
+
+        assert_eq!(config.indent_count, 6);
+        assert!(config.grouped);
+        assert_eq!(layers.source_of("grouped"), Source::User(user.clone()));
+        assert!(layers.show().unwrap().contains(&format!("grouped = true # {}", user.display())));
+    }
+
+    #[test]
+    fn format_switch() {
+        let file = Source::File(PathBuf::from("tomlfmt.toml"));
+        for (format, no_format) in [(false, true), (true, false)] {
+            let mut layers = Layers::default();
+            layers.push(file.clone(), table(&format!("format = {format}\n"))).unwrap();
+            assert_eq!(layers.config().unwrap().no_format, no_format);
+            assert_eq!(layers.source_of("no_format"), file);
+        }
+
+        let mut layers = Layers::default();
+        let err = layers
+            .push(file.clone(), table("format = false\nno_format = false\n"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "`format` and `no_format` contradict each other in tomlfmt.toml");
+
+        layers
+            .push(file.clone(), table("[format]\narrays = false\nblank_lines = false\n"))
+            .unwrap();
+        layers.push(Source::Cli, table("[format]\nblank_lines = true\n")).unwrap();
+        let rules = layers.config().unwrap().format;
+        assert!(!rules.arrays && rules.blank_lines && rules.inline_tables);
+    }
+
+    #[test]
+    fn renamed_keys() {
+        let file = Source::File(PathBuf::from("tomlfmt.toml"));
+        let mut layers = Layers::default();
+        layers.push(file.clone(), table("trailing_comma = true\ncrlf = true\n")).unwrap();
+        assert_eq!(
+            layers.warnings(),
+            [
+                "`trailing_comma` in tomlfmt.toml is deprecated, rename it to `always_trailing_comma`",
+                "`crlf` in tomlfmt.toml is deprecated, rename it to `newline_style`",
+            ]
+        );
+        let config = layers.config().unwrap();
+        assert!(config.always_trailing_comma);
+        assert_eq!(layers.source_of("always_trailing_comma"), file);
+
+        let mut layers = Layers::default();
+        layers.push(file.clone(), table("crlf = true\nnewline_style = \"crlf\"\n")).unwrap();
+        assert_eq!(layers.warnings().len(), 1);
+
+        for (crlf, style) in [(true, NewlineStyle::Crlf), (false, NewlineStyle::Lf)] {
+            let mut layers = Layers::default();
+            layers.push(file.clone(), table(&format!("crlf = {crlf}\n"))).unwrap();
+            assert_eq!(layers.config().unwrap().newline_style, style);
+        }
+
+        let err = layers
+            .push(file, table("trailing_comma = true\nalways_trailing_comma = false\n"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "`trailing_comma` and `always_trailing_comma` are both set in tomlfmt.toml with different values, remove \
+             `trailing_comma`"
+        );
+    }
+
+    #[test]
+    fn discover_nearest_config() {
+        let root = temp_dir("discover");
+        let member = root.join("crates/foo");
+        std::fs::create_dir_all(&member).unwrap();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        std::fs::write(root.join("tomlfmt.toml"), "grouped = true\n").unwrap();
+
+        let (path, table) = discover_config_file(&member).unwrap().unwrap();
+        assert_eq!(path, root.join("tomlfmt.toml"));
+        assert!(table.contains_key("grouped"));
+
+        std::fs::write(root.join("crates/.tomlfmt.toml"), "indent_count = 2\n").unwrap();
+        let (path, _) = discover_config_file(&member).unwrap().unwrap();
+        assert_eq!(path, root.join("crates/.tomlfmt.toml"));
+
+        // the search stops at the repository root
+        let nested = root.join("vendor");
+        std::fs::create_dir_all(nested.join(".git")).unwrap();
+        assert!(discover_config_file(&nested).unwrap().is_none());
+    }
+
+    #[test]
+    fn extends_chain() {
+        let root = temp_dir("extends");
+        std::fs::create_dir(root.join("policy")).unwrap();
+        std::fs::write(root.join("policy/base.toml"), "indent_count = 2\ngrouped = true\n").unwrap();
+        std::fs::write(root.join("policy/mid.toml"), "extends = \"base.toml\"\nindent_count = 3\n").unwrap();
+        std::fs::write(root.join("tomlfmt.toml"), "extends = \"policy/mid.toml\"\ncompact_arrays = true\n").unwrap();
+
+        let (path, table) = discover_config_file(&root).unwrap().unwrap();
+        let chain = resolve_extends(path, table).unwrap();
+        let paths = chain.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                root.join("policy/base.toml"),
+                root.join("policy/mid.toml"),
+                root.join("tomlfmt.toml")
+            ]
+        );
+
+        let mut layers = Layers::default();
+        for (path, table) in chain {
+            layers.push(Source::File(path), table).unwrap();
+        }
+        let config = layers.config().unwrap();
+        assert_eq!(config.indent_count, 3);
+        assert!(config.grouped && config.compact_arrays);
+
+        std::fs::write(root.join("policy/base.toml"), "extends = \"../tomlfmt.toml\"\n").unwrap();
+        let (path, table) = discover_config_file(&root).unwrap().unwrap();
+        let err = resolve_extends(path, table).unwrap_err().to_string();
+        assert!(err.starts_with("config files extend each other in a cycle: "), "{err}");
+    }
+
+    #[test]
+    fn default_config_file_round_trips() {
+        let file = default_config_file();
+        let mut layers = Layers::default();
+        layers.push(Source::File(PathBuf::from("tomlfmt.toml")), table(&file)).unwrap();
+        assert_eq!(layers.config().unwrap(), Config::default());
+        for (key, _) in config_table(&Config::default()).iter() {
+            assert!(
+                file.contains(&format!("\n{key} = ")) || file.contains(&format!("\n[{key}]\n")),
+                "`{key}` is missing from the generated file"
+            );
+        }
+
+        // the keys without a default are there too, commented out
+        let lines = file.lines().collect::<Vec<_>>();
+        let has = |line: &str| lines.contains(&line) || lines.iter().any(|l| l.starts_with(&format!("{line} = ")));
+        for key in KEYS {
+            let header = format!("[{}]", key.name);
+            let commented = lines.iter().any(|l| l.starts_with(&format!("# [{}.", key.name)));
+            assert!(
+                has(key.name) || has(&format!("# {}", key.name)) || has(&header) || commented,
+                "`{}` is missing from the generated file",
+                key.name
+            );
+            for child in key.children.iter().chain(key.children.iter().flat_map(|child| child.children)) {
+                if child.name != "*" {
+                    assert!(
+                        has(child.name) || has(&format!("# {}", child.name)),
+                        "`{}.{}` is missing from the generated file",
+                        key.name,
+                        child.name
+                    );
+                }
+            }
+        }
+        assert!(lines.contains(&"allow_unknown_keys = false"));
+        assert!(lines.contains(&"# extends = \"../tomlfmt.toml\""));
+        assert!(lines.contains(&"# [tables.\"workspace.members\"]"));
+    }
+
+    #[test]
+    fn config_file_name_precedence() {
+        let root = temp_dir("names");
+        let names = ["cargo-sort-ng.toml", ".cargo-sort-ng.toml", "tomlfmt.toml", ".tomlfmt.toml"];
+        for name in names {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        for (idx, name) in names.iter().enumerate() {
+            let (path, _) = discover_config_file(&root).unwrap().unwrap();
+            assert_eq!(path, root.join(name));
+            let shadowed = shadowed_config_files(&path);
+            assert_eq!(shadowed, names[idx + 1..].iter().map(|n| root.join(n)).collect::<Vec<_>>());
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(discover_config_file(&root).unwrap().is_none());
+    }
+
+    #[test]
+    fn wrong_types() {
+        fn wrong_value(kind: Kind) -> &'static str {
+            match kind {
+                Kind::Bool => "\"yes\"",
+                Kind::Integer => "-1",
+                Kind::String => "1",
+                Kind::StringArray => "[\"package\", 1]",
+                Kind::Table => "[]",
+                Kind::OneOf(_) | Kind::BoolOr(_) => "\"nope\"",
+            }
+        }
+        let mut keys = KEYS.iter().map(|k| (k.name.to_owned(), k.kind)).collect::<Vec<_>>();
+        for child in KEYS.iter().flat_map(|k| k.children).flat_map(|k| k.children) {
+            keys.push((format!("tables.foo.{}", child.name), child.kind));
+        }
+        for (name, kind) in keys {
+            let (parent, leaf) = name.rsplit_once('.').map_or(("", name.as_str()), |(p, l)| (p, l));
+            let toml = if parent.is_empty() {
+                format!("{leaf} = {}\n", wrong_value(kind))
+            } else {
+                format!("[{parent}]\n{leaf} = {}\n", wrong_value(kind))
+            };
+            let mut layers = Layers::default();
+            layers.push(Source::Cli, table(&toml)).unwrap();
+            let err = layers.config().unwrap_err().to_string();
+            assert!(
+                err.starts_with(&format!("command line: `{name}` expects {}, found ", kind.expected())),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_type_location() {
+        let root = temp_dir("types");
+        std::fs::write(root.join("tomlfmt.toml"), "# policy\ngrouped = true\ntable_order = \"package\"\n").unwrap();
+        let (path, table) = discover_config_file(&root).unwrap().unwrap();
+        let mut layers = Layers::default();
+        layers.push(Source::File(path.clone()), table).unwrap();
+        let err = layers.config().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!("{}:3: `table_order` expects an array of strings, found string", path.display())
+        );
+
+        let manifest = "[package]\nname = \"foo\"\n\n[package.metadata.cargo-sort-ng]\nindent_count = \"4\"\n";
+        let manifest_path = root.join("Cargo.toml");
+        std::fs::write(&manifest_path, manifest).unwrap();
+        let doc = manifest.parse::<DocumentMut>().unwrap();
+        let err = Layers::default()
+            .for_manifest(&manifest_path, Some(&doc), Table::new())
+            .unwrap()
+            .config()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            format!(
+                "{}:5: `indent_count` expects a non-negative integer, found string",
+                manifest_path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        let root = temp_dir("ignore");
+        let mut ignore = Ignore::default();
+        ignore
+            .add(
+                &root,
+                &["fuzz/**".to_owned(), "testdata/**/Cargo.toml".to_owned(), "examples/*".to_owned()],
+            )
+            .unwrap();
+
+        assert_eq!(ignore.matching(&root.join("fuzz/Cargo.toml")), Some("fuzz/**"));
+        assert_eq!(
+            ignore.matching(&root.join("testdata/a/b/Cargo.toml")),
+            Some("testdata/**/Cargo.toml")
+        );
+        assert_eq!(ignore.matching(&root.join("examples/demo/Cargo.toml")), Some("examples/*"));
+        assert_eq!(ignore.matching(&root.join("crates/foo/Cargo.toml")), None);
+        assert_eq!(ignore.matching(&root.join("Cargo.toml")), None);
+
+        let err = ignore.add(&root, &["[".to_owned()]).unwrap_err().to_string();
+        assert!(err.starts_with("invalid ignore pattern `[`"), "{err}");
+    }
+}
@@ -0,0 +1,181 @@
+use std::{fmt::Write, ops::Range};
+
+use similar::{DiffTag, TextDiff};
+
+/// A run of changed lines, without the unchanged lines around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The replaced lines of the original, counted from 0.
+    pub original: Range<usize>,
+
+    /// The lines replacing them in the sorted text, counted from 0.
+    pub new: Range<usize>,
+
+    /// The replaced lines with their line endings.
+    pub original_text: String,
+
+    /// The new lines with their line endings.
+    pub new_text: String,
+}
+
+/// Returns the changes that turn `original` into `sorted`, line by line.
+pub fn diff(original: &str, sorted: &str) -> Vec<Hunk> {
+    let (old_lines, new_lines) = (lines(original), lines(sorted));
+    let mut hunks: Vec<Hunk> = vec![];
+    for op in TextDiff::from_lines(original, sorted).ops() {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match hunks.last_mut() {
+            // a delete next to an insert is one replacement
+            Some(last) if last.original.end == old.start && last.new.end == new.start => {
+                last.original.end = old.end;
+                last.new.end = new.end;
+            }
+            _ => hunks.push(Hunk {
+                original: old,
+                new,
+                original_text: String::new(),
+                new_text: String::new(),
+            }),
+        }
+    }
+    for hunk in &mut hunks {
+        hunk.original_text = old_lines[hunk.original.clone()].concat();
+        hunk.new_text = new_lines[hunk.new.clone()].concat();
+    }
+    hunks
+}
+
+/// Renders the [`diff`] of `original` and `sorted` as the hunks of a
+/// unified diff, with `context` unchanged lines around each change.
+///
+/// The `---` and `+++` file header is left to the caller, an empty string
+/// means there are no changes.
+pub fn unified_diff(original: &str, sorted: &str, context: usize) -> String {
+    let old_lines = lines(original);
+    let hunks = diff(original, sorted);
+    let mut out = String::new();
+    let mut rest = &hunks[..];
+    while let Some(first) = rest.first() {
+        // hunks whose context would touch are written as one
+        let len = 1 + rest
+            .windows(2)
+            .take_while(|pair| pair[1].original.start - pair[0].original.end <= 2 * context)
+            .count();
+        let (group, tail) = rest.split_at(len);
+        rest = tail;
+
+        let last = &group[len - 1];
+        let start = first.original.start.saturating_sub(context);
+        let end = (last.original.end + context).min(old_lines.len());
+        let new_start = first.new.start - (first.original.start - start);
+        let new_end = last.new.end + (end - last.original.end);
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            line_range(start, end - start),
+            line_range(new_start, new_end - new_start)
+        )
+        .unwrap();
+
+        let mut line = start;
+        for hunk in group {
+            old_lines[line..hunk.original.start]
+                .iter()
+                .for_each(|text| push_line(&mut out, ' ', text));
+            lines(&hunk.original_text).iter().for_each(|text| push_line(&mut out, '-', text));
+            lines(&hunk.new_text).iter().for_each(|text| push_line(&mut out, '+', text));
+            line = hunk.original.end;
+        }
+        old_lines[line..end].iter().for_each(|text| push_line(&mut out, ' ', text));
+    }
+    out
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// The `start,len` of a hunk header, counted from 1.
+fn line_range(start: usize, len: usize) -> String {
+    match len {
+        // an empty range names the line before it
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+fn push_line(out: &mut String, prefix: char, text: &str) {
+    out.push(prefix);
+    out.push_str(text);
+    if !text.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Hunk, diff, unified_diff};
+    use crate::test_utils::assert_eq;
+
+    #[test]
+    fn hunks() {
+        let original = "[dependencies]\nb = \"1\"\na = \"1\"\nc = \"1\"\n";
+        let sorted = "[dependencies]\na = \"1\"\nb = \"1\"\nc = \"1\"\n";
+        let hunks = diff(original, sorted);
+        let moved = |original, new, original_text: &str, new_text: &str| Hunk {
+            original,
+            new,
+            original_text: original_text.to_owned(),
+            new_text: new_text.to_owned(),
+        };
+        assert_eq!(hunks.len(), 2, "{hunks:?}");
+        assert_eq!(hunks[0], moved(1..1, 1..2, "", "a = \"1\"\n"));
+        assert_eq!(hunks[1], moved(2..3, 3..3, "a = \"1\"\n", ""));
+
+        assert!(diff(original, original).is_empty());
+        let replaced = diff("a = 1\nb = 2", "a = 1\nb = 3\n");
+        assert_eq!(replaced, [moved(1..2, 1..2, "b = 2", "b = 3\n")]);
+    }
+
+    #[test]
+    fn unified() {
+        let original = (0..20).map(|n| format!("k{n:02} = {n}\n")).collect::<String>();
+        let sorted = original
+            .replace("k02 = 2\n", "k02 = 0\n")
+            .replace("k05 = 5\n", "")
+            .replace("k17 = 17\n", "k17 = 17\nk18 = 0\n");
+        assert_eq(
+            unified_diff(&original, &sorted, 2),
+            "@@ -1,8 +1,7 @@
+ k00 = 0
+ k01 = 1
+-k02 = 2
++k02 = 0
+ k03 = 3
+ k04 = 4
+-k05 = 5
+ k06 = 6
+ k07 = 7
+@@ -17,4 +16,5 @@
+ k16 = 16
+ k17 = 17
++k18 = 0
+ k18 = 18
+ k19 = 19
+",
+        );
+        assert_eq(
+            unified_diff(&original, &sorted, 0),
+            "@@ -3 +3 @@\n-k02 = 2\n+k02 = 0\n@@ -6 +5,0 @@\n-k05 = 5\n@@ -18,0 +18 @@\n+k18 = 0\n",
+        );
+        assert_eq(unified_diff(&original, &original, 3), "");
+        assert_eq(
+            unified_diff("a = 1", "a = 1\n", 3),
+            "@@ -1 +1 @@\n-a = 1\n\\ No newline at end of file\n+a = 1\n",
+        );
+    }
+}
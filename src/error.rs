@@ -0,0 +1,155 @@
+use std::{
+    fmt::{Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::sort::ParseError;
+
+/// The ways sorting a manifest and the command line around it can fail.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A file could not be read or written.
+    #[error("{}{source}", Located(.path.as_deref()))]
+    Io {
+        /// The file, `None` for the standard streams.
+        path: Option<PathBuf>,
+        /// The error of the operating system.
+        source: io::Error,
+    },
+
+    /// A manifest or config file is not valid toml.
+    #[error("{}{source}", Located(.path.as_deref()))]
+    Parse {
+        /// The file, `None` for input that was passed as a string.
+        path: Option<PathBuf>,
+        /// The error of the parser with the span it points at.
+        source: ParseError,
+    },
+
+    /// The sorted manifest could not be written back, it is left as it was.
+    #[error("failed to write {}: {source}", .path.display())]
+    Write {
+        /// The manifest.
+        path: PathBuf,
+        /// The error of the operating system.
+        source: io::Error,
+    },
+
+    /// A config is not valid, the message names the offending key.
+    #[error("{0}")]
+    Config(String),
+
+    /// A run over several manifests did not pass, the errors of the single
+    /// manifests are reported on their own.
+    #[error("{}", check_summary([*.unsorted, *.invalid, *.unwritten, *.failed]))]
+    Check {
+        /// The manifests that are not sorted or not formatted.
+        unsorted: usize,
+        /// The manifests that are not valid toml.
+        invalid: usize,
+        /// The manifests that could not be written back.
+        unwritten: usize,
+        /// The manifests that failed otherwise.
+        failed: usize,
+    },
+}
+
+impl Error {
+    /// Adds the file to an error of input passed as a string.
+    pub fn with_path(self, path: impl Into<PathBuf>) -> Self {
+        match self {
+            Error::Io { path: None, source } => Error::Io {
+                path: Some(path.into()),
+                source,
+            },
+            Error::Parse { path: None, source } => Error::Parse {
+                path: Some(path.into()),
+                source,
+            },
+            err => err,
+        }
+    }
+}
+
+/// The file an error is about in front of its message, if there is one.
+struct Located<'a>(Option<&'a Path>);
+
+impl Display for Located<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(path) => write!(f, "{}: ", path.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The summary of [`Error::Check`], the counts of the unsorted, invalid,
+/// unwritten and failed manifests that are not `0`.
+fn check_summary(counts: [usize; 4]) -> String {
+    let phrases = [
+        ("is not sorted or formatted", "are not sorted or formatted"),
+        ("is not valid toml", "are not valid toml"),
+        ("could not be written", "could not be written"),
+        ("could not be checked", "could not be checked"),
+    ];
+    let mut summary = String::new();
+    for (count, (one, many)) in counts.into_iter().zip(phrases).filter(|(count, _)| *count > 0) {
+        let (sep, files) = match (summary.is_empty(), count) {
+            (true, 1) => ("", " Cargo.toml file"),
+            (true, _) => ("", " Cargo.toml files"),
+            (false, _) => (", ", ""),
+        };
+        summary.push_str(&format!("{sep}{count}{files} {}", if count == 1 { one } else { many }));
+    }
+    summary
+}
+
+impl From<ParseError> for Error {
+    fn from(source: ParseError) -> Self {
+        Error::Parse { path: None, source }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error as _, io};
+
+    use super::Error;
+
+    #[test]
+    fn messages_and_sources() {
+        let err = Error::from(io::Error::other("gone")).with_path("Cargo.toml");
+        assert_eq!(err.to_string(), "Cargo.toml: gone");
+        assert!(err.source().is_some());
+        assert_eq!(Error::from(io::Error::other("gone")).to_string(), "gone");
+        assert!(Error::Config("bad".to_owned()).source().is_none());
+    }
+
+    #[test]
+    fn check_summary() {
+        let summary = |unsorted, invalid, unwritten, failed| {
+            Error::Check {
+                unsorted,
+                invalid,
+                unwritten,
+                failed,
+            }
+            .to_string()
+        };
+        assert_eq!(summary(1, 0, 0, 0), "1 Cargo.toml file is not sorted or formatted");
+        assert_eq!(summary(0, 2, 0, 0), "2 Cargo.toml files are not valid toml");
+        assert_eq!(summary(0, 0, 1, 0), "1 Cargo.toml file could not be written");
+        assert_eq!(
+            summary(3, 1, 1, 2),
+            "3 Cargo.toml files are not sorted or formatted, 1 is not valid toml, 1 could not be written, 2 could not be checked"
+        );
+    }
+}
@@ -1,12 +1,41 @@
-use std::str::FromStr;
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    ops::Range,
+    str::FromStr,
+};
 
-use toml_edit::{DocumentMut, Item, RawString, Table, Value};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, Visitor},
+};
+use toml_edit::{Decor, DocumentMut, InlineTable, Item, RawString, Table, Value};
+
+use crate::{
+    error::Error,
+    sort::{self, UnknownTables},
+};
 
 #[cfg(target_os = "windows")]
 pub(crate) const DEF_CRLF: bool = true;
 #[cfg(not(target_os = "windows"))]
 pub(crate) const DEF_CRLF: bool = false;
 
+pub(crate) const DEF_INLINE_KEY_ORDER: &[&str] = &[
+    "version",
+    "path",
+    "git",
+    "branch",
+    "rev",
+    "tag",
+    "registry",
+    "package",
+    "default-features",
+    "features",
+    "optional",
+    "workspace",
+];
+
 pub(crate) const DEF_TABLE_ORDER: &[&str] = &[
     "package",
     "workspace",
@@ -18,20 +47,285 @@ pub(crate) const DEF_TABLE_ORDER: &[&str] = &[
     "dev-dependencies",
 ];
 
+/// Settings for a single table that override the global ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableOverride {
+    /// Sort the keys of the table, this also opts in tables that are not
+    /// sorted by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<bool>,
+
+    /// Keep blank lines when sorting groups of key value pairs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grouped: Option<bool>,
+
+    /// Format the table, or the value when the path points at a key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<bool>,
+}
+
+/// Per table overrides keyed by dotted table path, e.g. `workspace.members`.
+pub type TableOverrides = BTreeMap<String, TableOverride>;
+
+/// Formatting rules that can be turned off one by one in the `[format]`
+/// section.
+///
+/// `space_around_eq` and `trailing_newline` are rules with their own top
+/// level switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatRules {
+    /// Limit blank lines before table headers and between keys.
+    pub blank_lines: bool,
+
+    /// Wrap arrays longer than `max_array_line_len` and tidy single line
+    /// arrays.
+    pub arrays: bool,
+
+    /// Tidy the spacing of inline tables.
+    pub inline_tables: bool,
+}
+
+impl Default for FormatRules {
+    fn default() -> Self {
+        Self {
+            blank_lines: true,
+            arrays: true,
+            inline_tables: true,
+        }
+    }
+}
+
+/// The line endings of a formatted file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NewlineStyle {
+    /// Keep the line endings the file already uses, a file mixing both gets
+    /// the one most of its lines end with.
+    #[default]
+    Auto,
+    /// Write `\n`.
+    Lf,
+    /// Write `\r\n`.
+    Crlf,
+}
+
+impl NewlineStyle {
+    /// The config file spelling of every style.
+    pub const NAMES: &[&str] = &["auto", "lf", "crlf"];
+
+    /// Returns the config file spelling of the style.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NewlineStyle::Auto => "auto",
+            NewlineStyle::Lf => "lf",
+            NewlineStyle::Crlf => "crlf",
+        }
+    }
+
+    /// Returns the style most lines of `contents` end with, LF on a tie.
+    ///
+    /// The line breaks inside multi-line strings are part of the string and
+    /// are not counted.
+    pub fn detect(contents: &str) -> Self {
+        let (lf, crlf) = count_line_endings(contents);
+        if crlf > lf { NewlineStyle::Crlf } else { NewlineStyle::Lf }
+    }
+
+    /// Returns `contents` with every line ending in this style, `Auto` keeps
+    /// them as they are.
+    ///
+    /// The line breaks inside multi-line strings are left alone, changing
+    /// them would change the string.
+    pub(crate) fn convert(self, contents: &str) -> String {
+        let newline = match self {
+            NewlineStyle::Auto => return contents.to_owned(),
+            style => style.newline(),
+        };
+        let mut out = String::with_capacity(contents.len());
+        let mut last = 0;
+        for ending in line_endings(contents) {
+            out.push_str(&contents[last..ending.start]);
+            out.push_str(newline);
+            last = ending.end;
+        }
+        out.push_str(&contents[last..]);
+        out
+    }
+
+    /// Returns the line ending to write, `Auto` that of the platform.
+    pub fn newline(self) -> &'static str {
+        match self {
+            NewlineStyle::Lf => "\n",
+            NewlineStyle::Crlf => "\r\n",
+            NewlineStyle::Auto if DEF_CRLF => "\r\n",
+            NewlineStyle::Auto => "\n",
+        }
+    }
+}
+
+/// Returns the number of LF and of CRLF line endings of `contents`, outside
+/// multi-line strings.
+pub(crate) fn count_line_endings(contents: &str) -> (usize, usize) {
+    line_endings(contents)
+        .into_iter()
+        .fold((0, 0), |(lf, crlf), ending| match ending.len() {
+            1 => (lf + 1, crlf),
+            _ => (lf, crlf + 1),
+        })
+}
+
+/// Returns the byte ranges of the `\n` and `\r\n` line endings of the toml
+/// `contents`, the ones inside multi-line strings are skipped.
+///
+/// Input that is not valid toml still gets every line ending outside what
+/// looks like a string.
+fn line_endings(contents: &str) -> Vec<Range<usize>> {
+    let bytes = contents.as_bytes();
+    let mut endings = vec![];
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\n' => endings.push(idx..idx + 1),
+            b'\r' if bytes.get(idx + 1) == Some(&b'\n') => {
+                endings.push(idx..idx + 2);
+                idx += 1;
+            }
+            b'#' => {
+                // a comment runs to the end of the line
+                while idx + 1 < bytes.len() && !matches!(bytes[idx + 1], b'\n' | b'\r') {
+                    idx += 1;
+                }
+            }
+            quote @ (b'"' | b'\'') => {
+                let multi_line = bytes[idx..].starts_with(&[quote; 3]);
+                let escapes = quote == b'"';
+                idx += if multi_line { 3 } else { 1 };
+                while idx < bytes.len() {
+                    match bytes[idx] {
+                        b'\\' if escapes => idx += 1,
+                        b if b == quote && !multi_line => break,
+                        b if b == quote && bytes[idx..].starts_with(&[quote; 3]) => {
+                            // up to two more quotes before the closing ones belong to the string
+                            let run = bytes[idx..].iter().take_while(|&&b| b == quote).count();
+                            idx += run - 1;
+                            break;
+                        }
+                        // a single line string ends at the line, the toml is invalid
+                        b'\n' | b'\r' if !multi_line => {
+                            idx -= 1;
+                            break;
+                        }
+                        _ => {}
+                    }
+                    idx += 1;
+                }
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    endings
+}
+
+/// What a manifest that differs from the sorted and formatted one only in the
+/// whitespace at the end of the file counts as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "TrailingNewlineSpelling", into = "TrailingNewlineSpelling")]
+pub enum TrailingNewline {
+    /// It fails `--check` and is rewritten like any other change, written
+    /// `true`.
+    #[default]
+    Require,
+    /// It passes `--check` but is still rewritten, written `false`.
+    Optional,
+    /// It passes `--check` and is left as it is, written `"ignore"`.
+    Ignore,
+}
+
+/// A [`TrailingNewline`] the way the config file spells it, a boolean or the
+/// string `"ignore"`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, expecting = "expected a boolean or `ignore`")]
+enum TrailingNewlineSpelling {
+    Flag(bool),
+    Name(String),
+}
+
+impl TryFrom<TrailingNewlineSpelling> for TrailingNewline {
+    type Error = String;
+
+    fn try_from(spelling: TrailingNewlineSpelling) -> Result<Self, Self::Error> {
+        match spelling {
+            TrailingNewlineSpelling::Flag(true) => Ok(TrailingNewline::Require),
+            TrailingNewlineSpelling::Flag(false) => Ok(TrailingNewline::Optional),
+            TrailingNewlineSpelling::Name(name) if name == "ignore" => Ok(TrailingNewline::Ignore),
+            TrailingNewlineSpelling::Name(name) => Err(format!("unknown variant `{name}`, expected a boolean or `ignore`")),
+        }
+    }
+}
+
+impl From<TrailingNewline> for TrailingNewlineSpelling {
+    fn from(trailing: TrailingNewline) -> Self {
+        match trailing {
+            TrailingNewline::Require => TrailingNewlineSpelling::Flag(true),
+            TrailingNewline::Optional => TrailingNewlineSpelling::Flag(false),
+            TrailingNewline::Ignore => TrailingNewlineSpelling::Name("ignore".to_owned()),
+        }
+    }
+}
+
+/// The form dependency entries are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStyle {
+    /// `serde = { version = "1" }`, a table that does not fit in
+    /// `max_array_line_len` or holds a comment that has no place in an
+    /// inline table keeps its form.
+    Inline,
+    /// `[dependencies.serde]` with a key per line.
+    Table,
+    /// Every entry keeps the form it has.
+    #[default]
+    Preserve,
+}
+
+impl DependencyStyle {
+    /// The config file spelling of every style.
+    pub const NAMES: &[&str] = &["inline", "table", "preserve"];
+
+    /// Returns the config file spelling of the style.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DependencyStyle::Inline => "inline",
+            DependencyStyle::Table => "table",
+            DependencyStyle::Preserve => "preserve",
+        }
+    }
+}
+
 /// The config file for formatting toml after sorting.
 ///
-/// Use the `FromStr` to create a config from a string.
+/// Use the `FromStr` to create a config from a string, `Display` writes it
+/// back in the same format.
 ///
 /// ## Example
 /// ```
-/// let input = "trailing_comma = true\ncrlf = true";
+/// use cargo_sort_fix::{Config, NewlineStyle};
+///
+/// let input = "always_trailing_comma = true\nnewline_style = \"crlf\"";
 /// let config = input.parse::<Config>().unwrap();
-/// assert!(config.trailing_comma);
-/// assert!(config.crlf);
+/// assert!(config.always_trailing_comma);
+/// assert_eq!(config.newline_style, NewlineStyle::Crlf);
 /// ```
-#[allow(dead_code)]
-#[derive(Clone)]
-pub(crate) struct Config {
+///
+/// The `Serialize` and `Deserialize` impls use the same keys and spellings as
+/// the config file, missing keys keep their default. A deserialized config is
+/// not checked, pass it through [`ConfigBuilder::build`] for that.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
     /// Use trailing comma where possible.
     ///
     /// Defaults to `false`.
@@ -72,6 +366,12 @@ pub(crate) struct Config {
     /// Defaults to `true`.
     pub trailing_newline: bool,
 
+    /// Whether differences only in the whitespace at the end of the file,
+    /// such as a missing final newline, fail `--check` and are written.
+    ///
+    /// Defaults to [`TrailingNewline::Require`].
+    pub require_trailing_newline: TrailingNewline,
+
     /// Are newlines allowed between key value pairs in a table.
     ///
     /// This must be true for the `--grouped` flag to be used.
@@ -83,16 +383,122 @@ pub(crate) struct Config {
     /// Defaults to `1`.
     pub allowed_blank_lines: usize,
 
-    /// Use CRLF line endings
+    /// The line endings to write.
     ///
-    /// Defaults to `None`, which means use the original file's line endings
-    /// or the system's.
-    pub crlf: Option<bool>,
+    /// Defaults to [`NewlineStyle::Auto`], which keeps the line endings of
+    /// each file.
+    pub newline_style: NewlineStyle,
 
     /// The user specified ordering of tables in a document.
     ///
-    /// All unspecified tables will come after these.
+    /// A `*` entry is where the unspecified tables go, without one they come
+    /// after these as [`Config::unknown_tables`] says.
     pub table_order: Vec<String>,
+
+    /// The ordering of tables in a workspace root manifest, `table_order` is
+    /// used when unset.
+    ///
+    /// Defaults to `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table_order_root: Option<Vec<String>>,
+
+    /// Where tables missing from the table order are placed.
+    ///
+    /// Defaults to [`UnknownTables::Position`].
+    pub unknown_tables: UnknownTables,
+
+    /// The formatting rules that are applied.
+    ///
+    /// Defaults to all rules.
+    pub format: FormatRules,
+
+    /// Rewrite the entries of the dependency tables as inline tables or as
+    /// tables of their own, dotted keys are left as they are.
+    ///
+    /// Defaults to [`DependencyStyle::Preserve`].
+    pub dependency_style: DependencyStyle,
+
+    /// Put the keys of the inline tables in the dependency tables in the
+    /// order of `inline_key_order`.
+    ///
+    /// Defaults to `true`.
+    pub order_inline_keys: bool,
+
+    /// The order of the keys of an inline dependency, keys missing from it
+    /// come after these, sorted by name.
+    ///
+    /// Defaults to `version`, `path`, `git`, `branch`, `rev`, `tag`,
+    /// `registry`, `package`, `default-features`, `features`, `optional` and
+    /// `workspace`.
+    pub inline_key_order: Vec<String>,
+
+    /// Rewrite the version requirements of dependencies in their canonical
+    /// form, without a redundant `^` and with `>=1.2, <2` spacing. This
+    /// changes values, not just layout.
+    ///
+    /// Defaults to `false`.
+    pub canonicalize_versions: bool,
+
+    /// Keep blank lines when sorting groups of key value pairs.
+    ///
+    /// Defaults to `false`.
+    pub grouped: bool,
+
+    /// A full line comment starts a group of keys that is sorted on its own,
+    /// the comment keeps its place. Comments after a key stay with the key.
+    ///
+    /// Defaults to `false`.
+    pub group_comments: bool,
+
+    /// Sort the strings in each array of the `[features]` table, a comment
+    /// after an element moves with it.
+    ///
+    /// Defaults to `false`.
+    pub sort_feature_values: bool,
+
+    /// The arrays of tables, like `[[example]]`, whose entries are sorted by
+    /// their `name`, entries without one go last in input order.
+    ///
+    /// Defaults to `[]`.
+    pub sort_array_of_tables: Vec<String>,
+
+    /// Also fail `--check` if formatting changes.
+    ///
+    /// Defaults to `false`.
+    pub check_format: bool,
+
+    /// Check every crate in the workspace.
+    ///
+    /// Defaults to `false`.
+    pub workspace: bool,
+
+    /// Skip formatting after sorting.
+    ///
+    /// Defaults to `false`.
+    pub no_format: bool,
+
+    /// Glob patterns of manifests to skip, relative to the directory of the
+    /// config file setting them.
+    ///
+    /// Defaults to no patterns.
+    pub ignore: Vec<String>,
+
+    /// The tables and arrays whose keys are sorted, by dotted path, a `*` as
+    /// the last segment matches every table at that level.
+    ///
+    /// Defaults to the ones of [`MATCHER`](crate::MATCHER).
+    pub sort_tables: Vec<String>,
+
+    /// More tables to sort, in addition to `sort_tables`.
+    ///
+    /// Defaults to none.
+    pub extend_sort_tables: Vec<String>,
+
+    /// Settings overriding the ones above for single tables.
+    ///
+    /// Defaults to no overrides.
+    #[serde(skip_serializing_if = "TableOverrides::is_empty")]
+    pub tables: TableOverrides,
 }
 
 impl Default for Config {
@@ -106,48 +512,330 @@ impl Default for Config {
             compact_arrays: false,
             compact_inline_tables: false,
             trailing_newline: true,
+            require_trailing_newline: TrailingNewline::Require,
             key_value_newlines: true,
             allowed_blank_lines: 1,
-            crlf: None,
+            newline_style: NewlineStyle::Auto,
             table_order: DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect(),
+            table_order_root: None,
+            unknown_tables: UnknownTables::Position,
+            format: FormatRules::default(),
+            dependency_style: DependencyStyle::Preserve,
+            order_inline_keys: true,
+            inline_key_order: DEF_INLINE_KEY_ORDER.iter().map(|&s| s.to_owned()).collect(),
+            canonicalize_versions: false,
+            grouped: false,
+            group_comments: false,
+            sort_feature_values: false,
+            sort_array_of_tables: vec![],
+            check_format: false,
+            workspace: false,
+            no_format: false,
+            ignore: vec![],
+            sort_tables: sort::default_sort_tables(),
+            extend_sort_tables: vec![],
+            tables: TableOverrides::new(),
         }
     }
 }
 
 impl FromStr for Config {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.is_empty() {
             return Ok(Self::default());
         }
 
-        let toml = s.parse::<DocumentMut>().map_err(|_| "failed to parse as toml")?;
-        Ok(Config {
-            always_trailing_comma: toml.get("always_trailing_comma").and_then(Item::as_bool).unwrap_or_default(),
-            multiline_trailing_comma: toml.get("multiline_trailing_comma").and_then(Item::as_bool).unwrap_or(true),
-            max_array_line_len: toml.get("max_array_line_len").and_then(Item::as_integer).unwrap_or(80) as usize,
-            indent_count: toml.get("indent_count").and_then(Item::as_integer).unwrap_or(4) as usize,
-            space_around_eq: toml.get("space_around_eq").and_then(Item::as_bool).unwrap_or(true),
-            compact_arrays: toml.get("compact_arrays").and_then(Item::as_bool).unwrap_or_default(),
-            compact_inline_tables: toml.get("compact_inline_tables").and_then(Item::as_bool).unwrap_or_default(),
-            trailing_newline: toml.get("trailing_newline").and_then(Item::as_bool).unwrap_or(true),
-            key_value_newlines: toml.get("key_value_newlines").and_then(Item::as_bool).unwrap_or(true),
-            allowed_blank_lines: toml.get("allowed_blank_lines").and_then(Item::as_integer).unwrap_or(1) as usize,
-            crlf: toml.get("crlf").and_then(Item::as_bool),
-            table_order: toml
-                .get("table_order")
-                .and_then(Item::as_array)
-                .map_or(DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect(), |arr| {
-                    arr.into_iter().filter_map(|v| v.as_str()).map(|s| s.to_owned()).collect()
-                }),
-        })
+        let toml = sort::parse(s)?;
+        ConfigBuilder::from(Self::from_table(toml.as_table()).map_err(Error::Config)?).build()
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", DocumentMut::from(self.to_table()))
+    }
+}
+
+/// Keys that are read by the config loading itself and not stored in
+/// [`Config`].
+pub(crate) const LOADER_KEYS: &[&str] = &["allow_unknown_keys", "extends"];
+
+/// Returns the keys of the config file, the field names the derived
+/// `Deserialize` of [`Config`] asks the deserializer for.
+fn config_keys() -> &'static [&'static str] {
+    /// Records the fields of the struct it is asked to deserialize.
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Fields<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("only the fields of a struct are recorded"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            self.deserialize_any(visitor)
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &[&str] = &[];
+    let _ = Config::deserialize(Fields(&mut fields));
+    fields
+}
+
+impl Config {
+    /// Reads a config from the keys of a toml table, missing keys get their
+    /// [`Default`] value.
+    ///
+    /// Values of the wrong type are an error, as are unknown keys unless
+    /// `allow_unknown_keys = true` is set in `toml`. Whether the options fit
+    /// together is left to [`ConfigBuilder::build`].
+    pub(crate) fn from_table(toml: &Table) -> Result<Self, String> {
+        let allow_unknown = toml.get("allow_unknown_keys").and_then(Item::as_bool).unwrap_or_default();
+        let keys = config_keys();
+        let mut toml = toml.clone();
+        toml.retain(|key, _| !LOADER_KEYS.contains(&key) && (!allow_unknown || keys.contains(&key)));
+        // without spans the error is the message and a line naming the key
+        toml_edit::de::from_document(DocumentMut::from(toml)).map_err(|err| err.to_string().trim_end().replace('\n', " "))
+    }
+
+    /// Writes every key of the config into a toml table, the inverse of
+    /// [`Config::from_table`].
+    pub(crate) fn to_table(&self) -> Table {
+        let doc = toml_edit::ser::to_document(self).expect("a config is written as a toml table");
+        doc.as_table().clone()
+    }
+
+    /// Returns the table order for a manifest, `root` is `true` for the
+    /// manifest containing `[workspace]`.
+    pub fn table_order_for(&self, root: bool) -> &[String] {
+        match &self.table_order_root {
+            Some(order) if root => order,
+            _ => &self.table_order,
+        }
+    }
+
+    /// Returns the `heading` and `heading_key` of the [`Matcher`](crate::Matcher)
+    /// sorting [`Config::sort_tables`] and [`Config::extend_sort_tables`].
+    pub(crate) fn sort_patterns(&self) -> (Vec<&str>, Vec<(&str, &str)>) {
+        let mut heading = vec![];
+        let mut heading_key = vec![];
+        for pattern in self.sort_tables.iter().chain(&self.extend_sort_tables) {
+            match pattern.split_once('.') {
+                Some(pair) => heading_key.push(pair),
+                None => heading.push(pattern.as_str()),
+            }
+        }
+        (heading, heading_key)
+    }
+
+    /// Errors on the first entry of `sort_tables` or `extend_sort_tables`
+    /// that is not a dotted path of bare keys.
+    fn check_sort_tables(&self) -> Result<(), String> {
+        let bare = |segment: &str| !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        for (key, patterns) in [("sort_tables", &self.sort_tables), ("extend_sort_tables", &self.extend_sort_tables)] {
+            for pattern in patterns {
+                let segments = pattern.split('.').collect::<Vec<_>>();
+                let (last, parents) = segments.split_last().expect("split returns a segment");
+                let reason = if !parents.iter().all(|s| bare(s)) || !(bare(last) || *last == "*") {
+                    "is not a dotted path of bare keys, only the last segment may be `*`"
+                } else if parents.is_empty() && *last == "*" {
+                    "needs a table before the `*`"
+                } else {
+                    continue;
+                };
+                return Err(format!("`{key}` entry `{pattern}` {reason}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors on the first table a table order lists twice, `*` included.
+    fn check_table_orders(&self) -> Result<(), String> {
+        for (key, order) in [
+            ("table_order", Some(&self.table_order)),
+            ("table_order_root", self.table_order_root.as_ref()),
+        ] {
+            let order = order.map_or(&[][..], Vec::as_slice);
+            if let Some((_, table)) = order.iter().enumerate().find(|(idx, table)| order[..*idx].contains(table)) {
+                return Err(format!("`{key}` lists `{table}` twice"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if formatting is turned off for the table or key at
+    /// the dotted `path`.
+    fn formats(&self, path: &str) -> bool {
+        self.tables.get(path).and_then(|t| t.format).unwrap_or(true)
+    }
+
+    /// Returns a builder starting from the [`Default`] config.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Builds a [`Config`], checking that the options fit together.
+///
+/// Start from the defaults with [`Config::builder`], or from a config read
+/// from a file with `ConfigBuilder::from(config)`.
+///
+/// ## Example
+/// ```
+/// use cargo_sort_fix::{Config, NewlineStyle};
+///
+/// let config = Config::builder()
+///     .grouped(true)
+///     .table_order(["package", "dependencies"])
+///     .newline_style(NewlineStyle::Lf)
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.table_order, ["package", "dependencies"]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+/// Defines a setter for every field of [`Config`] taking the field type.
+macro_rules! setters {
+    ($($field:ident: $ty:ty),* $(,)?) => {$(
+        #[doc = concat!("Sets [`Config::", stringify!($field), "`].")]
+        pub fn $field(mut self, $field: $ty) -> Self {
+            self.config.$field = $field;
+            self
+        }
+    )*};
+}
+
+impl ConfigBuilder {
+    setters!(
+        always_trailing_comma: bool,
+        multiline_trailing_comma: bool,
+        max_array_line_len: usize,
+        indent_count: usize,
+        space_around_eq: bool,
+        compact_arrays: bool,
+        compact_inline_tables: bool,
+        trailing_newline: bool,
+        require_trailing_newline: TrailingNewline,
+        key_value_newlines: bool,
+        allowed_blank_lines: usize,
+        newline_style: NewlineStyle,
+        unknown_tables: UnknownTables,
+        format: FormatRules,
+        dependency_style: DependencyStyle,
+        order_inline_keys: bool,
+        canonicalize_versions: bool,
+        grouped: bool,
+        group_comments: bool,
+        sort_feature_values: bool,
+        check_format: bool,
+        workspace: bool,
+        no_format: bool,
+        tables: TableOverrides,
+    );
+
+    /// Sets [`Config::sort_array_of_tables`].
+    pub fn sort_array_of_tables<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.config.sort_array_of_tables = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets [`Config::inline_key_order`].
+    pub fn inline_key_order<S: Into<String>>(mut self, order: impl IntoIterator<Item = S>) -> Self {
+        self.config.inline_key_order = order.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets [`Config::table_order`].
+    pub fn table_order<S: Into<String>>(mut self, order: impl IntoIterator<Item = S>) -> Self {
+        self.config.table_order = order.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets [`Config::table_order_root`].
+    pub fn table_order_root<S: Into<String>>(mut self, order: impl IntoIterator<Item = S>) -> Self {
+        self.config.table_order_root = Some(order.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets [`Config::ignore`].
+    pub fn ignore<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.config.ignore = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets [`Config::sort_tables`].
+    pub fn sort_tables<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.config.sort_tables = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets [`Config::extend_sort_tables`].
+    pub fn extend_sort_tables<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.config.extend_sort_tables = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds the override of the table at the dotted `path` to
+    /// [`Config::tables`].
+    pub fn table(mut self, path: impl Into<String>, settings: TableOverride) -> Self {
+        self.config.tables.insert(path.into(), settings);
+        self
+    }
+
+    /// Returns the config, or an error naming the options that contradict
+    /// each other. Every config is checked here, the ones read from a file
+    /// too.
+    ///
+    /// ## Errors
+    /// [`Error::Config`] if `grouped` is set without `key_value_newlines`,
+    /// `always_trailing_comma` without `multiline_trailing_comma`, a table
+    /// order lists a table twice or a `sort_tables` entry is not a table path.
+    pub fn build(self) -> Result<Config, Error> {
+        let config = self.config;
+        config.check_sort_tables().map_err(Error::Config)?;
+        if config.grouped && !config.key_value_newlines {
+            return Err(Error::Config(
+                "`grouped` needs `key_value_newlines` to keep the blank lines between groups".to_owned(),
+            ));
+        }
+        if config.always_trailing_comma && !config.multiline_trailing_comma {
+            return Err(Error::Config(
+                "`always_trailing_comma` contradicts `multiline_trailing_comma = false`".to_owned(),
+            ));
+        }
+        config.check_table_orders().map_err(Error::Config)?;
+        Ok(config)
+    }
+}
+
+impl From<Config> for ConfigBuilder {
+    fn from(config: Config) -> Self {
+        Self { config }
     }
 }
 
 fn fmt_value(value: &mut Value, config: &Config) {
     // Note: Here `newline_pattern` is useless since the table will use `\n` insternally always.
-    let newline_pattern = if config.crlf.unwrap_or(DEF_CRLF) { "\r\n" } else { "\n" };
+    let newline_pattern = config.newline_style.newline();
     match value {
+        Value::Array(_) if !config.format.arrays => {}
+        Value::InlineTable(_) if !config.format.inline_tables => {}
         Value::Array(arr) => {
             if arr.to_string().len() > config.max_array_line_len {
                 let old_trailing_comma = arr.trailing_comma();
@@ -243,34 +931,35 @@ fn fmt_value(value: &mut Value, config: &Config) {
     }
 }
 
-fn fmt_table(table: &mut Table, config: &Config) {
+fn fmt_table(table: &mut Table, config: &Config, path: &str) {
     // Note: Here `newline_pattern` is useless since the table will use `\n` insternally always.
-    let newline_pattern = if config.crlf.unwrap_or(DEF_CRLF) { "\r\n" } else { "\n" };
+    let newline_pattern = config.newline_style.newline();
 
     // Checks the header decor for blank lines
+    if config.format.blank_lines {
+        let current_decor = table.decor().prefix().and_then(RawString::as_str).unwrap_or("");
+        let mut new_decor = String::with_capacity(current_decor.len());
 
-    let current_decor = table.decor().prefix().and_then(RawString::as_str).unwrap_or("");
-    let mut new_decor = String::with_capacity(current_decor.len());
+        let mut num_consecutive_blank_lines = 0;
 
-    let mut num_consecutive_blank_lines = 0;
-
-    for line in current_decor.lines() {
-        if line.starts_with("#") {
-            new_decor.push_str(line);
-            new_decor.push_str(newline_pattern);
-            num_consecutive_blank_lines = 0;
-            continue;
-        }
+        for line in current_decor.lines() {
+            if line.starts_with("#") {
+                new_decor.push_str(line);
+                new_decor.push_str(newline_pattern);
+                num_consecutive_blank_lines = 0;
+                continue;
+            }
 
-        num_consecutive_blank_lines += 1;
+            num_consecutive_blank_lines += 1;
 
-        if num_consecutive_blank_lines <= config.allowed_blank_lines {
-            new_decor.push_str(line);
-            new_decor.push_str(newline_pattern);
+            if num_consecutive_blank_lines <= config.allowed_blank_lines {
+                new_decor.push_str(line);
+                new_decor.push_str(newline_pattern);
+            }
         }
-    }
 
-    table.decor_mut().set_prefix(new_decor);
+        table.decor_mut().set_prefix(new_decor);
+    }
 
     let keys: Vec<_> = table.iter().map(|(k, _)| k.to_owned()).collect();
     for key in keys {
@@ -284,7 +973,9 @@ fn fmt_table(table: &mut Table, config: &Config) {
         let blank_lines = prefix.lines().filter(|l| !l.starts_with('#')).count();
 
         // Check each item in the table for blank lines
-        if config.key_value_newlines {
+        if !config.format.blank_lines {
+            // the blank lines rule is turned off
+        } else if config.key_value_newlines {
             if config.allowed_blank_lines < blank_lines {
                 dec.set_prefix(prefix.replacen(newline_pattern, "", blank_lines - config.allowed_blank_lines));
             }
@@ -302,10 +993,14 @@ fn fmt_table(table: &mut Table, config: &Config) {
             dec.set_suffix(format!("{}{}", dec.suffix().and_then(RawString::as_str).unwrap_or(""), ' '));
         }
 
+        let key_path = format!("{path}.{key}");
+        if !config.formats(&key_path) {
+            continue;
+        }
         match table.get_mut(&key).unwrap() {
             Item::Table(table) => {
                 // stuff
-                fmt_table(table, config);
+                fmt_table(table, config, &key_path);
             }
             Item::Value(val) => {
                 fmt_value(val, config);
@@ -316,17 +1011,263 @@ fn fmt_table(table: &mut Table, config: &Config) {
     }
 }
 
+/// The tables of a manifest whose entries `dependency_style` rewrites, at the
+/// top level, below `[workspace]` and below `[target.<cfg>]`.
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Returns every dependency table of `toml` with its dotted path.
+fn dependency_tables(toml: &mut DocumentMut) -> Vec<(String, &mut Table)> {
+    let mut tables = vec![];
+    for (key, item) in toml.as_table_mut().iter_mut() {
+        let Some(table) = item.as_table_mut() else { continue };
+        match key.get() {
+            "workspace" => {
+                if let Some(deps) = table.get_mut("dependencies").and_then(Item::as_table_mut) {
+                    tables.push(("workspace.dependencies".to_owned(), deps));
+                }
+            }
+            "target" => {
+                for (cfg, target) in table.iter_mut() {
+                    let Some(target) = target.as_table_mut() else { continue };
+                    for (name, deps) in target.iter_mut() {
+                        if let Some(deps) = deps.as_table_mut()
+                            && DEPENDENCY_TABLES.contains(&name.get())
+                        {
+                            tables.push((format!("target.{}.{}", cfg.get(), name.get()), deps));
+                        }
+                    }
+                }
+            }
+            name if DEPENDENCY_TABLES.contains(&name) => tables.push((name.to_owned(), table)),
+            _ => {}
+        }
+    }
+    tables
+}
+
+/// Rewrites the entries of every dependency table of `toml` in the form
+/// `config.dependency_style` asks for.
+fn style_dependencies(toml: &mut DocumentMut, config: &Config) {
+    for (path, table) in dependency_tables(toml) {
+        if !config.formats(&path) {
+            continue;
+        }
+        let (mut inlined, mut moved_out) = (false, false);
+        for (mut key, item) in table.iter_mut() {
+            if !config.formats(&format!("{path}.{}", key.get())) {
+                continue;
+            }
+            match (config.dependency_style, &mut *item) {
+                (DependencyStyle::Table, Item::Value(Value::InlineTable(inline))) if !inline.is_dotted() => {
+                    let mut dep = inline_to_table(inline, key.leaf_decor().prefix());
+                    fmt_table(&mut dep, config, &format!("{path}.{}", key.get()));
+                    *item = Item::Table(dep);
+                    key.leaf_decor_mut().clear();
+                    moved_out = true;
+                }
+                (DependencyStyle::Inline, Item::Table(dep)) => {
+                    let Some((mut inline, comments)) = table_to_inline(dep) else {
+                        continue;
+                    };
+                    let eq = if config.space_around_eq { " " } else { "" };
+                    let after = inline.decor().suffix().cloned();
+                    inline.decor_mut().set_suffix("");
+                    if format!("{}{eq}={inline}", key.get()).len() > config.max_array_line_len {
+                        continue;
+                    }
+                    inline.decor_mut().set_suffix(after.unwrap_or_default());
+                    *item = Item::Value(Value::InlineTable(inline));
+                    key.leaf_decor_mut().set_prefix(comments);
+                    key.leaf_decor_mut().set_suffix(eq);
+                    inlined = true;
+                }
+                _ => {}
+            }
+        }
+        // a table that only held `[dependencies.name]` tables gets a header,
+        // one left without keys or comments loses it
+        if inlined && table.is_implicit() {
+            table.set_implicit(false);
+            table.decor_mut().set_prefix("\n");
+        } else if moved_out && table.iter().all(|(_, item)| item.is_table()) && comment_lines(&decor_text(table.decor())).is_empty() {
+            table.set_implicit(true);
+        }
+    }
+}
+
+/// Rewrites the version requirements in the dependency tables of `toml` the
+/// way [`canonical_requirement`] does, strings elsewhere are left alone.
+fn canonicalize_versions(toml: &mut DocumentMut, config: &Config) {
+    for (path, table) in dependency_tables(toml) {
+        if !config.formats(&path) {
+            continue;
+        }
+        for (key, item) in table.iter_mut() {
+            if !config.formats(&format!("{path}.{}", key.get())) {
+                continue;
+            }
+            // `dep = "1"`, `dep = { version = "1" }` and `[dependencies.dep]`
+            let version = if item.is_str() {
+                item.as_value_mut()
+            } else if let Some(inline) = item.as_inline_table_mut() {
+                inline.get_mut("version")
+            } else {
+                item.as_table_mut()
+                    .and_then(|table| table.get_mut("version"))
+                    .and_then(Item::as_value_mut)
+            };
+            let Some(version) = version else { continue };
+            if let Some(canonical) = version.as_str().and_then(canonical_requirement)
+                && version.as_str() != Some(canonical.as_str())
+            {
+                let decor = version.decor().clone();
+                *version = Value::from(canonical);
+                *version.decor_mut() = decor;
+            }
+        }
+    }
+}
+
+/// Returns the version requirement `req` without the `^` that a bare version
+/// means anyway, with no space between an operator and its version and one
+/// after each comma. The versions themselves are left as they are.
+///
+/// Returns `None` if `req` does not look like a version requirement.
+fn canonical_requirement(req: &str) -> Option<String> {
+    let mut comparators = vec![];
+    for comparator in req.split(',') {
+        let comparator = comparator.trim();
+        let op = ["<=", ">=", "<", ">", "=", "~", "^"]
+            .into_iter()
+            .find(|op| comparator.starts_with(op))
+            .unwrap_or_default();
+        let version = comparator[op.len()..].trim_start();
+        let looks_like_version = version.starts_with(|c: char| c.is_ascii_digit() || c == '*')
+            && version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '*'));
+        if !looks_like_version {
+            return None;
+        }
+        let op = if op == "^" { "" } else { op };
+        comparators.push(format!("{op}{version}"));
+    }
+    Some(comparators.join(", "))
+}
+
+/// Puts the keys of the inline tables in the dependency tables of `toml` in
+/// the order of `config.inline_key_order`.
+fn order_inline_keys(toml: &mut DocumentMut, config: &Config) {
+    let rank = |key: &str| {
+        let listed = config.inline_key_order.iter().position(|k| k == key);
+        (listed.unwrap_or(config.inline_key_order.len()), key.to_owned())
+    };
+    for (path, table) in dependency_tables(toml) {
+        if !config.formats(&path) {
+            continue;
+        }
+        for (key, item) in table.iter_mut() {
+            if !config.formats(&format!("{path}.{}", key.get())) {
+                continue;
+            }
+            if let Some(inline) = item.as_inline_table_mut()
+                && !inline.is_dotted()
+            {
+                inline.sort_values_by(|a, _, b, _| rank(a).cmp(&rank(b)));
+                inline.fmt();
+            }
+        }
+    }
+}
+
+/// Returns the prefix and the suffix of `decor` on lines of their own.
+fn decor_text(decor: &Decor) -> String {
+    let text = |raw: Option<&RawString>| raw.and_then(RawString::as_str).unwrap_or_default().to_owned();
+    format!("{}\n{}", text(decor.prefix()), text(decor.suffix()))
+}
+
+/// Returns the lines of `text` that are comments, each ending in `\n`.
+fn comment_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Returns the table form of the inline table of a dependency, the
+/// comments above the key go above the header and one after the inline
+/// table after it.
+fn inline_to_table(inline: &InlineTable, key_prefix: Option<&RawString>) -> Table {
+    let mut table = Table::new();
+    for (key, value) in inline.iter() {
+        let mut value = value.clone();
+        value.decor_mut().clear();
+        table.insert(key, Item::Value(value));
+    }
+    let comments = comment_lines(key_prefix.and_then(RawString::as_str).unwrap_or_default());
+    table.decor_mut().set_prefix(format!("\n{comments}"));
+    let after = inline.decor().suffix().and_then(RawString::as_str).unwrap_or_default().trim();
+    if after.starts_with('#') {
+        table.decor_mut().set_suffix(format!(" {after}"));
+    }
+    table
+}
+
+/// Returns the inline form of the table of a dependency and the comments
+/// of the table, which go above the key in the order they are written. A
+/// comment after the header goes after the inline table.
+///
+/// Returns `None` if the table holds more tables or a value that spans
+/// lines or holds comments of its own.
+fn table_to_inline(table: &Table) -> Option<(InlineTable, String)> {
+    if table.is_implicit() {
+        return None;
+    }
+    let header = table.decor();
+    let mut comments = comment_lines(header.prefix().and_then(RawString::as_str).unwrap_or_default());
+    let mut inline = InlineTable::new();
+    for (key, item) in table.iter() {
+        let value = item.as_value()?;
+        let key_decor = table.key(key).map(|key| decor_text(key.leaf_decor())).unwrap_or_default();
+        comments.push_str(&comment_lines(&key_decor));
+        let mut value = value.clone();
+        comments.push_str(&comment_lines(&decor_text(value.decor())));
+        value.decor_mut().clear();
+        if let Value::Array(arr) = &mut value {
+            if arr.to_string().contains('#') {
+                return None;
+            }
+            arr.fmt();
+        }
+        if value.to_string().contains('\n') {
+            return None;
+        }
+        inline.insert(key, value);
+    }
+    InlineTable::fmt(&mut inline);
+    inline.decor_mut().set_prefix(" ");
+    let after = header.suffix().and_then(RawString::as_str).unwrap_or_default().trim();
+    if after.starts_with('#') {
+        inline.decor_mut().set_suffix(format!(" {after}"));
+    }
+    Some((inline, comments))
+}
+
 /// Formats a toml `DocumentMut` according to `tomlfmt.toml`.
 pub(crate) fn fmt_toml(toml: &mut DocumentMut, config: &Config) {
-    for (_key, item) in toml.as_table_mut().iter_mut() {
+    for (key, item) in toml.as_table_mut().iter_mut() {
+        if !config.formats(key.get()) {
+            continue;
+        }
         match item {
             Item::ArrayOfTables(table) => {
                 for tab in table.iter_mut() {
-                    fmt_table(tab, config);
+                    fmt_table(tab, config, key.get());
                 }
             }
             Item::Table(table) => {
-                fmt_table(table, config);
+                fmt_table(table, config, key.get());
             }
             Item::Value(val) => {
                 fmt_value(val, config);
@@ -334,12 +1275,24 @@ pub(crate) fn fmt_toml(toml: &mut DocumentMut, config: &Config) {
             Item::None => {}
         }
     }
+    if config.canonicalize_versions {
+        canonicalize_versions(toml, config);
+    }
+    if config.dependency_style != DependencyStyle::Preserve {
+        style_dependencies(toml, config);
+    }
+    if config.order_inline_keys {
+        order_inline_keys(toml, config);
+    }
 
     // TODO:
     // This is TERRIBLE!! Convert the Document to a string only to check it ends with a
     // newline
+    // the trailing comments and whitespace are written after the decor of the
+    // document, so the newline goes after them
     if config.trailing_newline && !toml.to_string().ends_with('\n') {
-        toml.decor_mut().set_suffix("\n");
+        let trailing = toml.trailing().as_str().unwrap_or_default().to_owned();
+        toml.set_trailing(trailing + "\n");
     }
 }
 
@@ -362,8 +1315,12 @@ impl ValueExt for Value {
 mod test {
     use std::fs;
 
-    use super::{Config, DocumentMut, fmt_toml};
+    use super::{
+        Config, ConfigBuilder, DEF_INLINE_KEY_ORDER, DEF_TABLE_ORDER, DependencyStyle, DocumentMut, FormatRules, NewlineStyle, Table,
+        TableOverride, TableOverrides, TrailingNewline, UnknownTables, fmt_toml,
+    };
     use crate::test_utils::assert_eq;
+    use toml_edit::{Item, Value};
 
     #[test]
     fn toml_fmt_check() {
@@ -382,6 +1339,27 @@ mod test {
         assert_eq(input, toml);
     }
 
+    #[test]
+    fn line_endings() {
+        let mixed = "[package]\r\nname = \"a\"\r\n# note\n[dependencies]\r\n";
+        assert_eq!(super::count_line_endings(mixed), (1, 3));
+        assert_eq!(NewlineStyle::detect(mixed), NewlineStyle::Crlf);
+        assert_eq!(NewlineStyle::detect("a = 1\r\nb = 2\nc = 3\n"), NewlineStyle::Lf);
+        assert_eq!(NewlineStyle::detect("a = 1"), NewlineStyle::Lf);
+        assert_eq!(NewlineStyle::Lf.convert(mixed), mixed.replace("\r\n", "\n"));
+        assert_eq!(NewlineStyle::Crlf.convert(mixed), mixed.replace("# note\n", "# note\r\n"));
+        assert_eq!(NewlineStyle::Auto.convert(mixed), mixed);
+
+        // the line breaks of multi-line strings are part of the value
+        let strings = "a = \"\"\"\none\r\ntwo\n\"\"\"\"\nb = '''\nx\r\n'''\nc = \"\\\"'''\" # \"\"\"\nd = '\"\"\"'\n";
+        assert_eq!(super::count_line_endings(strings), (4, 0));
+        let crlf = "a = \"\"\"\none\r\ntwo\n\"\"\"\"\r\nb = '''\nx\r\n'''\r\nc = \"\\\"'''\" # \"\"\"\r\nd = '\"\"\"'\r\n";
+        assert_eq!(NewlineStyle::Crlf.convert(strings), crlf);
+        assert_eq!(NewlineStyle::Lf.convert(crlf), strings);
+        let continued = "a = \"\"\"one \\\r\n  two\"\"\"\r\n";
+        assert_eq!(NewlineStyle::Lf.convert(continued), "a = \"\"\"one \\\r\n  two\"\"\"\n");
+    }
+
     #[cfg(target_os = "windows")]
     #[test]
     fn fmt_crlf_correct() {
@@ -396,6 +1374,22 @@ mod test {
         similar_asserts::assert_eq!(expected, toml.to_string());
     }
 
+    #[test]
+    fn eof_without_newline() {
+        for name in ["eof-bare-key", "eof-comment", "eof-whitespace"] {
+            let input = fs::read_to_string(format!("fixtures/valid/{name}.toml")).unwrap();
+            assert!(!input.ends_with('\n'), "{name}");
+            let mut toml = crate::sort::sort_toml(&input, crate::MATCHER, false, &[]).unwrap();
+            fmt_toml(&mut toml, &Config::default());
+            assert_eq(input + "\n", toml);
+        }
+
+        let input = "[dependencies]\nb = \"1\" # bee\na = \"1\" # last";
+        let mut toml = crate::sort::sort_toml(input, crate::MATCHER, false, &[]).unwrap();
+        fmt_toml(&mut toml, &Config::default());
+        assert_eq("[dependencies]\na = \"1\" # last\nb = \"1\" # bee\n", toml);
+    }
+
     #[test]
     fn array() {
         let input = fs::read_to_string("examp/clippy.toml").unwrap();
@@ -484,4 +1478,574 @@ integration = [
         fmt_toml(&mut toml, &cfg);
         similar_asserts::assert_eq!(expected2, toml.to_string());
     }
+
+    #[test]
+    fn table_override_skips_formatting() {
+        let input = r#"[workspace]
+members = [    "a",   "b"   ]
+
+[features]
+default = [    "a"   ]
+"#;
+        let config = "[tables.\"workspace.members\"]\nformat = false\n".parse::<Config>().unwrap();
+        let mut toml = input.parse::<DocumentMut>().unwrap();
+        fmt_toml(&mut toml, &config);
+        similar_asserts::assert_eq!(
+            r#"[workspace]
+members = [    "a",   "b"   ]
+
+[features]
+default = ["a"]
+"#,
+            toml.to_string()
+        );
+    }
+
+    /// Returns a config with every field set to a value other than its
+    /// default.
+    fn populated() -> Config {
+        Config {
+            always_trailing_comma: true,
+            multiline_trailing_comma: false,
+            max_array_line_len: 100,
+            indent_count: 2,
+            space_around_eq: false,
+            compact_arrays: true,
+            compact_inline_tables: true,
+            trailing_newline: false,
+            require_trailing_newline: TrailingNewline::Ignore,
+            key_value_newlines: false,
+            allowed_blank_lines: 0,
+            newline_style: NewlineStyle::Crlf,
+            table_order: vec!["package".to_owned(), "features".to_owned()],
+            table_order_root: Some(vec!["workspace".to_owned()]),
+            unknown_tables: UnknownTables::Keep,
+            format: FormatRules {
+                blank_lines: false,
+                arrays: true,
+                inline_tables: false,
+            },
+            dependency_style: DependencyStyle::Table,
+            order_inline_keys: false,
+            inline_key_order: vec!["path".to_owned(), "version".to_owned()],
+            canonicalize_versions: true,
+            grouped: true,
+            group_comments: true,
+            sort_feature_values: true,
+            sort_array_of_tables: vec!["example".to_owned()],
+            check_format: true,
+            workspace: true,
+            no_format: true,
+            ignore: vec!["fuzz/**".to_owned()],
+            sort_tables: vec!["dependencies".to_owned()],
+            extend_sort_tables: vec!["package.metadata.cross.*".to_owned()],
+            tables: [(
+                "workspace.members".to_owned(),
+                TableOverride {
+                    sort: Some(true),
+                    grouped: None,
+                    format: Some(false),
+                },
+            )]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        assert_eq!(Config::from_table(&Table::new()).unwrap(), Config::default());
+        assert_eq!("".parse::<Config>().unwrap(), Config::default());
+        assert_eq!(Config::from_table(&Config::default().to_table()).unwrap(), Config::default());
+
+        let config = populated();
+        assert_eq!(Config::from_table(&config.to_table()).unwrap(), config);
+        // a config file is checked too, which rejects the options contradicting each other
+        let valid = Config {
+            multiline_trailing_comma: true,
+            key_value_newlines: true,
+            ..config.clone()
+        };
+        assert_eq!(valid.to_table().to_string().parse::<Config>().unwrap(), valid);
+
+        let input = r#"
+always_trailing_comma = true
+multiline_trailing_comma = false
+max_array_line_len = 100
+indent_count = 2
+space_around_eq = false
+compact_arrays = true
+compact_inline_tables = true
+trailing_newline = false
+require_trailing_newline = "ignore"
+key_value_newlines = false
+allowed_blank_lines = 0
+newline_style = "crlf"
+table_order = ["package", "features"]
+table_order_root = ["workspace"]
+unknown_tables = "keep"
+format = { blank_lines = false, inline_tables = false }
+dependency_style = "table"
+order_inline_keys = false
+inline_key_order = ["path", "version"]
+canonicalize_versions = true
+grouped = true
+group_comments = true
+sort_feature_values = true
+sort_array_of_tables = ["example"]
+check_format = true
+workspace = true
+no_format = true
+ignore = ["fuzz/**"]
+sort_tables = ["dependencies"]
+extend_sort_tables = ["package.metadata.cross.*"]
+[tables."workspace.members"]
+sort = true
+format = false
+"#;
+        assert_eq!(
+            Config::from_table(input.parse::<DocumentMut>().unwrap().as_table()).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn serde_round_trips() {
+        let config = Config {
+            multiline_trailing_comma: true,
+            key_value_newlines: true,
+            ..populated()
+        };
+        let toml = toml_edit::ser::to_string(&config).unwrap();
+        assert_eq!(toml_edit::de::from_str::<Config>(&toml).unwrap(), config);
+        // the same keys and spellings as the config file
+        assert_eq!(toml.parse::<Config>().unwrap(), config);
+        assert_eq!(toml, config.to_string());
+        assert!(toml.contains("\nnewline_style = \"crlf\"\n"), "{toml}");
+        assert!(toml.contains("\nrequire_trailing_newline = \"ignore\"\n"), "{toml}");
+        assert!(
+            toml.contains("\ntables = { \"workspace.members\" = { sort = true, format = false } }\n"),
+            "{toml}"
+        );
+
+        // unset keys are left out, missing keys keep their default
+        let toml = toml_edit::ser::to_string(&Config::default()).unwrap();
+        assert!(
+            !toml
+                .lines()
+                .any(|line| line.starts_with("table_order_root ") || line.starts_with("tables ")),
+            "{toml}"
+        );
+        let config = toml_edit::de::from_str::<Config>("grouped = true\nformat = { arrays = false }").unwrap();
+        assert!(config.grouped && !config.format.arrays && config.format.blank_lines);
+        let err = toml_edit::de::from_str::<Config>("groupd = true").unwrap_err();
+        assert!(err.message().starts_with("unknown field `groupd`"), "{err}");
+    }
+
+    #[test]
+    fn format_rules() {
+        let input = "[package]\n\n\n\nname = \"x\"\nlist = [    \"a\",   \"b\"   ]\ninline = {   a = 1   }\n";
+        let format = |config: &str| {
+            let mut toml = input.parse::<DocumentMut>().unwrap();
+            fmt_toml(&mut toml, &config.parse::<Config>().unwrap());
+            toml.to_string()
+        };
+
+        assert_eq!(format(""), "[package]\n\nname = \"x\"\nlist = [\"a\", \"b\"]\ninline = { a = 1 }\n");
+        assert_eq!(
+            format("[format]\nblank_lines = false"),
+            "[package]\n\n\n\nname = \"x\"\nlist = [\"a\", \"b\"]\ninline = { a = 1 }\n"
+        );
+        assert_eq!(
+            format("[format]\narrays = false"),
+            "[package]\n\nname = \"x\"\nlist = [    \"a\",   \"b\"   ]\ninline = { a = 1 }\n"
+        );
+        assert_eq!(
+            format("[format]\ninline_tables = false"),
+            "[package]\n\nname = \"x\"\nlist = [\"a\", \"b\"]\ninline = {   a = 1   }\n"
+        );
+    }
+
+    /// The data of `doc` without its layout, tables and inline tables alike.
+    fn data(doc: &DocumentMut) -> String {
+        fn plain(value: &Value) -> String {
+            match value {
+                Value::Array(arr) => format!("[{}]", arr.iter().map(plain).collect::<Vec<_>>().join(", ")),
+                Value::InlineTable(table) => {
+                    let mut pairs = table
+                        .iter()
+                        .map(|(key, value)| format!("{key} = {}", plain(value)))
+                        .collect::<Vec<_>>();
+                    pairs.sort();
+                    format!("{{ {} }}", pairs.join(", "))
+                }
+                value => value.clone().decorated("", "").to_string(),
+            }
+        }
+        plain(&Item::Table(doc.as_table().clone()).into_value().unwrap())
+    }
+
+    #[test]
+    fn dependency_styles() {
+        let input = fs::read_to_string("examp/dependency_styles.toml").unwrap();
+        let format = |input: &str, dependency_style| {
+            let config = Config {
+                dependency_style,
+                ..Config::default()
+            };
+            let mut toml = input.parse::<DocumentMut>().unwrap();
+            fmt_toml(&mut toml, &config);
+            toml.to_string()
+        };
+        for style in [DependencyStyle::Inline, DependencyStyle::Table, DependencyStyle::Preserve] {
+            let once = format(&input, style);
+            assert_eq!(data(&once.parse().unwrap()), data(&input.parse().unwrap()), "{style:?}");
+            assert_eq!(format(&once, style), once, "{style:?}");
+        }
+
+        let tables = format(&input, DependencyStyle::Table);
+        assert!(tables.contains("\n# the runtime\n[dependencies.tokio] # pinned by the lock file\nversion = \"1\"\n"));
+        assert!(tables.contains("[target.'cfg(unix)'.dependencies.libc]\n") && !tables.contains("[target.'cfg(unix)'.dependencies]\n"));
+        assert!(tables.contains("\nlog.workspace = true\n"));
+
+        let inline = format(&tables, DependencyStyle::Inline);
+        assert!(
+            inline.contains("\n# the runtime\ntokio = { version = \"1\", features = [\"rt\", \"macros\"] } # pinned by the lock file\n")
+        );
+        assert!(inline.contains("\n# with derive\n# for the config structs\nserde = { version = \"1\", features = [\"derive\"] }\n"));
+        assert!(inline.contains("\n[dev-dependencies]\nsimilar = { version = \"2\" }\n"));
+        // too long for `max_array_line_len`
+        assert!(inline.contains("\n[dependencies.long]\n"));
+        // a comment an inline table has no place for keeps the table
+        let commented = "[dependencies.a]\nfeatures = [\n    \"x\", # why\n    \"y\",\n]\n";
+        let config = Config {
+            dependency_style: DependencyStyle::Inline,
+            format: FormatRules {
+                arrays: false,
+                ..FormatRules::default()
+            },
+            ..Config::default()
+        };
+        let mut toml = commented.parse::<DocumentMut>().unwrap();
+        fmt_toml(&mut toml, &config);
+        assert_eq(commented, toml);
+    }
+
+    #[test]
+    fn inline_key_order() {
+        let input = r#"[dependencies]
+a = {features=["x"],   version = "1", zed = 1, optional = true, alpha = 2}
+b = { path = "b", version = "1" }
+c.features = ["x"]
+c.version = "1"
+
+[package.metadata]
+d = { features = ["x"], version = "1" }
+"#;
+        let format = |config: Config| {
+            let mut toml = input.parse::<DocumentMut>().unwrap();
+            fmt_toml(&mut toml, &config);
+            toml.to_string()
+        };
+        assert_eq(
+            r#"[dependencies]
+a = { version = "1", features = ["x"], optional = true, alpha = 2, zed = 1 }
+b = { version = "1", path = "b" }
+c.features = ["x"]
+c.version = "1"
+
+[package.metadata]
+d = { features = ["x"], version = "1" }
+"#,
+            format(Config::default()),
+        );
+        assert_eq(
+            input.replace(
+                "{features=[\"x\"],   version = \"1\", zed = 1, optional = true, alpha = 2}",
+                "{ features = [\"x\"], version = \"1\", zed = 1, optional = true, alpha = 2 }",
+            ),
+            format(Config {
+                order_inline_keys: false,
+                ..Config::default()
+            }),
+        );
+        let config = Config::builder().inline_key_order(["path"]).build().unwrap();
+        assert!(format(config).contains(
+            "\na = { alpha = 2, features = [\"x\"], optional = true, version = \"1\", zed = 1 }\nb = { path = \"b\", version = \"1\" }\n"
+        ));
+    }
+
+    #[test]
+    fn canonical_versions() {
+        let input = r#"[package]
+version = "^1.0.0"
+
+[dependencies]
+a = "^1.2.3"
+b = { version = ">= 1.2 , <2", features = ["x"] } # keep
+c = "= 1.2"
+d = "~ 1.2"
+e = "1.*"
+f = "*"
+g = "^1.0.0-alpha.1+build.5"
+h = { git = "https://example.com/h", branch = "^1" }
+i = "latest"
+
+[dependencies.j]
+version = "^0.3"
+
+[target.'cfg(unix)'.dev-dependencies]
+k = { version = "^1.0.0-rc.1, <1.1" }
+
+[workspace.dependencies]
+l = "^ 2"
+
+[package.metadata]
+m = "^1"
+"#;
+        let format = |config: Config| {
+            let mut toml = input.parse::<DocumentMut>().unwrap();
+            fmt_toml(&mut toml, &config);
+            toml.to_string()
+        };
+        assert_eq(
+            r#"[package]
+version = "^1.0.0"
+
+[dependencies]
+a = "1.2.3"
+b = { version = ">=1.2, <2", features = ["x"] } # keep
+c = "=1.2"
+d = "~1.2"
+e = "1.*"
+f = "*"
+g = "1.0.0-alpha.1+build.5"
+h = { git = "https://example.com/h", branch = "^1" }
+i = "latest"
+
+[dependencies.j]
+version = "0.3"
+
+[target.'cfg(unix)'.dev-dependencies]
+k = { version = "1.0.0-rc.1, <1.1" }
+
+[workspace.dependencies]
+l = "2"
+
+[package.metadata]
+m = "^1"
+"#,
+            format(Config {
+                canonicalize_versions: true,
+                ..Config::default()
+            }),
+        );
+        assert_eq(input, format(Config::default()));
+    }
+
+    #[test]
+    fn root_table_order() {
+        let config = "table_order = [\"package\"]".parse::<Config>().unwrap();
+        assert_eq!(config.table_order_for(true), ["package"]);
+        assert_eq!(config.table_order_for(false), ["package"]);
+
+        let config = "table_order = [\"package\"]\ntable_order_root = [\"workspace\"]"
+            .parse::<Config>()
+            .unwrap();
+        assert_eq!(config.table_order_for(true), ["workspace"]);
+        assert_eq!(config.table_order_for(false), ["package"]);
+    }
+
+    #[test]
+    fn strict_parsing() {
+        assert_eq!(
+            "indent_count = -1".parse::<Config>().unwrap_err().to_string(),
+            "invalid value: integer `-1`, expected usize in `indent_count`"
+        );
+        assert_eq!(
+            "table_order = [\"package\", 1]".parse::<Config>().unwrap_err().to_string(),
+            "invalid type: integer `1`, expected a string in `table_order`"
+        );
+        assert_eq!(
+            "table_order = [\"package\", \"*\", \"lints\", \"*\"]"
+                .parse::<Config>()
+                .unwrap_err()
+                .to_string(),
+            "`table_order` lists `*` twice"
+        );
+        assert_eq!(
+            "[tables.features]\nsort = 1".parse::<Config>().unwrap_err().to_string(),
+            "invalid type: integer `1`, expected a boolean in `tables.features.sort`"
+        );
+        assert_eq!(
+            "require_trailing_newline = \"always\"".parse::<Config>().unwrap_err().to_string(),
+            "unknown variant `always`, expected a boolean or `ignore` in `require_trailing_newline`"
+        );
+        let err = "indent = 2".parse::<Config>().unwrap_err().to_string();
+        assert!(
+            err.starts_with("unknown field `indent`, expected one of `always_trailing_comma`"),
+            "{err}"
+        );
+        let err = "[format]\narrow = false".parse::<Config>().unwrap_err().to_string();
+        assert!(err.starts_with("unknown field `arrow`"), "{err}");
+
+        let config = "allow_unknown_keys = true\nindent = 2\nindent_count = 2".parse::<Config>().unwrap();
+        assert_eq!(config.indent_count, 2);
+    }
+
+    #[test]
+    fn defaults() {
+        // the documented default of every field
+        let defaults = Config {
+            always_trailing_comma: false,
+            multiline_trailing_comma: true,
+            max_array_line_len: 80,
+            indent_count: 4,
+            space_around_eq: true,
+            compact_arrays: false,
+            compact_inline_tables: false,
+            trailing_newline: true,
+            require_trailing_newline: TrailingNewline::Require,
+            key_value_newlines: true,
+            allowed_blank_lines: 1,
+            newline_style: NewlineStyle::Auto,
+            table_order: DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect(),
+            table_order_root: None,
+            unknown_tables: UnknownTables::Position,
+            format: FormatRules {
+                blank_lines: true,
+                arrays: true,
+                inline_tables: true,
+            },
+            dependency_style: DependencyStyle::Preserve,
+            order_inline_keys: true,
+            inline_key_order: DEF_INLINE_KEY_ORDER.iter().map(|&s| s.to_owned()).collect(),
+            canonicalize_versions: false,
+            grouped: false,
+            group_comments: false,
+            sort_feature_values: false,
+            sort_array_of_tables: vec![],
+            check_format: false,
+            workspace: false,
+            no_format: false,
+            ignore: vec![],
+            sort_tables: [
+                "dependencies",
+                "dev-dependencies",
+                "build-dependencies",
+                "lints",
+                "workspace.members",
+                "workspace.exclude",
+                "workspace.dependencies",
+                "workspace.dev-dependencies",
+                "workspace.build-dependencies",
+                "workspace.lints",
+                "workspace.lints.*",
+                "workspace.metadata",
+                "lints.*",
+            ]
+            .map(str::to_owned)
+            .into(),
+            extend_sort_tables: vec![],
+            tables: TableOverrides::new(),
+        };
+        assert_eq!(Config::default(), defaults);
+        assert_eq!(Config::builder().build().unwrap(), defaults);
+        assert_eq!("".parse::<Config>().unwrap(), defaults);
+    }
+
+    #[test]
+    fn builder() {
+        let config = Config::builder()
+            .grouped(true)
+            .table_order(["package", "dependencies"])
+            .table_order_root(["workspace"])
+            .newline_style(NewlineStyle::Lf)
+            .ignore(["fuzz/**"])
+            .table(
+                "features",
+                TableOverride {
+                    sort: Some(true),
+                    ..TableOverride::default()
+                },
+            )
+            .build()
+            .unwrap();
+        assert!(config.grouped);
+        assert_eq!(config.table_order, ["package", "dependencies"]);
+        assert_eq!(config.table_order_for(true), ["workspace"]);
+        assert_eq!(config.newline_style, NewlineStyle::Lf);
+        assert_eq!(config.ignore, ["fuzz/**"]);
+        assert_eq!(config.tables["features"].sort, Some(true));
+
+        // a config read from a file seeds the builder
+        let from_file = "indent_count = 2".parse::<Config>().unwrap();
+        let config = ConfigBuilder::from(from_file).compact_arrays(true).build().unwrap();
+        assert_eq!((config.indent_count, config.compact_arrays), (2, true));
+
+        let err = |builder: ConfigBuilder| builder.build().unwrap_err().to_string();
+        assert_eq!(
+            err(Config::builder().grouped(true).key_value_newlines(false)),
+            "`grouped` needs `key_value_newlines` to keep the blank lines between groups"
+        );
+        assert_eq!(
+            err(Config::builder().always_trailing_comma(true).multiline_trailing_comma(false)),
+            "`always_trailing_comma` contradicts `multiline_trailing_comma = false`"
+        );
+        assert_eq!(
+            err(Config::builder().table_order_root(["package", "workspace", "package"])),
+            "`table_order_root` lists `package` twice"
+        );
+        assert_eq!(
+            err(Config::builder().extend_sort_tables(["package.metadata.cross*"])),
+            "`extend_sort_tables` entry `package.metadata.cross*` is not a dotted path of bare keys, only the last segment may be `*`"
+        );
+
+        // a config file is checked the same way
+        let err = "grouped = true\nkey_value_newlines = false".parse::<Config>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`grouped` needs `key_value_newlines` to keep the blank lines between groups"
+        );
+        let err = "always_trailing_comma = true\nmultiline_trailing_comma = false"
+            .parse::<Config>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`always_trailing_comma` contradicts `multiline_trailing_comma = false`"
+        );
+    }
+
+    #[test]
+    fn sort_table_patterns() {
+        let config = Config::builder()
+            .sort_tables(["dependencies", "workspace.lints.*"])
+            .extend_sort_tables(["package.metadata.cross.*"])
+            .build()
+            .unwrap();
+        let (heading, heading_key) = config.sort_patterns();
+        assert_eq!(heading, ["dependencies"]);
+        assert_eq!(heading_key, [("workspace", "lints.*"), ("package", "metadata.cross.*")]);
+
+        for (pattern, reason) in [
+            ("*", "needs a table before the `*`"),
+            ("", "is not a dotted path of bare keys, only the last segment may be `*`"),
+            (
+                "package..metadata",
+                "is not a dotted path of bare keys, only the last segment may be `*`",
+            ),
+            (
+                "target.*.dependencies",
+                "is not a dotted path of bare keys, only the last segment may be `*`",
+            ),
+            (
+                "package.\"docs.rs\"",
+                "is not a dotted path of bare keys, only the last segment may be `*`",
+            ),
+        ] {
+            let err = format!("sort_tables = [\"dependencies\", {pattern:?}]")
+                .parse::<Config>()
+                .unwrap_err();
+            assert_eq!(err.to_string(), format!("`sort_tables` entry `{pattern}` {reason}"));
+        }
+    }
 }
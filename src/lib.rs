@@ -0,0 +1,145 @@
+//! Sorts the dependency tables of Cargo manifests and formats them.
+//!
+//! This is the library behind `cargo sort-fix`, the binary adds the command
+//! line, the config files and the workspace handling on top of it.
+//!
+//! [`check_manifest`] does what the binary does for one manifest in one call,
+//! the functions below are the single steps it is made of.
+//!
+//! ## Example
+//! ```
+//! use cargo_sort_fix::{Config, MATCHER, fmt_toml, sort_toml};
+//!
+//! let config = Config::default();
+//! let input = "[dependencies]\nserde = \"1\"\nanyhow = \"1\"\n";
+//! let mut doc = sort_toml(input, MATCHER, config.grouped, &config.table_order);
+//! fmt_toml(&mut doc, &config);
+//! assert_eq!(doc.to_string(), "[dependencies]\nanyhow = \"1\"\nserde = \"1\"\n");
+//! ```
+#![warn(missing_docs)]
+
+use std::fmt::{Display, Formatter};
+
+use toml_edit::DocumentMut;
+
+mod check;
+mod diff;
+mod error;
+mod fmt;
+#[cfg(test)]
+mod properties;
+mod sort;
+#[cfg(test)]
+mod test_utils;
+
+pub use crate::{
+    check::{CheckReport, MisplacedKey, check_document, check_manifest},
+    diff::{Hunk, diff, unified_diff},
+    error::Error,
+    fmt::{Config, ConfigBuilder, DependencyStyle, FormatRules, NewlineStyle, TableOverride, TableOverrides, TrailingNewline},
+    sort::{MATCHER, Matcher, ParseError, UnknownTables},
+};
+
+/// A sorted toml document, `Display` writes it out.
+#[derive(Debug, Clone)]
+pub struct Document(DocumentMut);
+
+impl Display for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<DocumentMut> for Document {
+    fn from(doc: DocumentMut) -> Self {
+        Self(doc)
+    }
+}
+
+impl From<Document> for DocumentMut {
+    fn from(doc: Document) -> Self {
+        doc.0
+    }
+}
+
+/// Sorts the tables selected by `matcher` in the toml `input`, the top level
+/// tables are put in the order of `ordering`.
+///
+/// `group` keeps the blank line separated groups of keys and sorts each on its
+/// own.
+///
+/// ## Errors
+/// [`Error::Parse`] if `input` is not valid toml.
+pub fn try_sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering: &[String]) -> Result<Document, Error> {
+    Ok(Document(sort::sort_toml(input, matcher, group, ordering)?))
+}
+
+/// Like [`try_sort_toml`] for input that is known to be valid toml.
+///
+/// ## Panics
+/// If `input` is not valid toml.
+pub fn sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering: &[String]) -> Document {
+    try_sort_toml(input, matcher, group, ordering).unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Sorts the already parsed `doc` in place, like [`try_sort_toml`] without
+/// parsing the input again.
+pub fn sort_document(doc: &mut DocumentMut, matcher: Matcher<'_>, group: bool, ordering: &[String]) {
+    sort::sort_document(doc, matcher, group, ordering);
+}
+
+/// Parses the toml `input`, for [`sort_document`] and [`check_document`].
+///
+/// ## Errors
+/// If `input` is not valid toml, a table header that defines a table again
+/// is reported with the lines of both definitions.
+pub fn parse_toml(input: &str) -> Result<DocumentMut, ParseError> {
+    sort::parse(input)
+}
+
+/// Formats `doc` with the rules enabled in `config`.
+pub fn fmt_toml(doc: &mut Document, config: &Config) {
+    fmt::fmt_toml(&mut doc.0, config);
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use toml_edit::DocumentMut;
+
+    use super::{Config, MATCHER, fmt_toml, try_sort_toml};
+
+    /// Sorts and formats `input` the way the fuzz targets do.
+    fn sort_and_fmt(input: &str, config: &Config) -> String {
+        let mut doc = try_sort_toml(input, MATCHER, config.grouped, &config.table_order).unwrap();
+        fmt_toml(&mut doc, config);
+        doc.to_string()
+    }
+
+    /// The properties of `fuzz/fuzz_targets` for the inputs that once broke
+    /// them and for the files the corpus is seeded from.
+    #[test]
+    fn fuzz_regressions() {
+        for dir in ["regressions", "examp", "fixtures/valid"] {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_none_or(|ext| ext != "toml") {
+                    continue;
+                }
+                let input = fs::read_to_string(&path).unwrap();
+                for grouped in [false, true] {
+                    let config = Config {
+                        grouped,
+                        ..Config::default()
+                    };
+                    let once = sort_and_fmt(&input, &config);
+                    if let Err(err) = once.parse::<DocumentMut>() {
+                        panic!("{} grouped={grouped}: {err}", path.display());
+                    }
+                    assert_eq!(once, sort_and_fmt(&once, &config), "{} grouped={grouped}", path.display());
+                }
+            }
+        }
+    }
+}
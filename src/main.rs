@@ -1,22 +1,58 @@
-use std::{fmt::Display, fs::read_to_string, io::Write, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+};
 
-use clap::{crate_authors, crate_name, crate_version};
-use fmt::Config;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use toml_edit::{DocumentMut, Item};
+use cargo_sort_fix::{CheckReport, Error, NewlineStyle, ParseError, check_document, parse_toml, unified_diff};
+use clap::{crate_authors, crate_name, crate_version, error::ErrorKind, parser::ValueSource};
+use config::Layers;
+use output::{Output, Status, write_red};
+use toml_edit::{Array, DocumentMut, Item, Table, value};
 
-mod fmt;
-mod sort;
+mod config;
+mod output;
+mod progress;
 #[cfg(test)]
 mod test_utils;
 
 const CARGO_TOML: &str = "Cargo.toml";
 
 const EXTRA_HELP: &str = r#"
-NOTE: formatting is applied after the check for sorting so sorted but unformatted toml will not cause a failure.
+NOTE: sorted but unformatted toml only fails --check together with --check-format, and the
+--format-severity warn option turns that failure into a warning.
+
+Exit codes:
+  0  Every manifest is sorted, or has been sorted and written
+  1  --check found a manifest that is not sorted
+  2  --check --check-format found manifests that are sorted but not formatted, and nothing else
+  3  A file could not be read, written or parsed, or the command line or a config file is not valid
 "#;
 
-type Result<T, E = Box<dyn std::error::Error + Send + Sync + 'static>> = std::result::Result<T, E>;
+/// The options a manifest read from stdin cannot be used with.
+const STDIN_CONFLICTS: [&str; 7] = [
+    "check",
+    "dry_run",
+    "workspace",
+    "recursive",
+    "porcelain",
+    "show_config",
+    "init_config",
+];
+
+/// The exit code of a `--check` run whose manifests only need formatting.
+const UNFORMATTED_EXIT_CODE: i32 = 2;
+
+/// The exit code of a file that could not be read, written or parsed, or an
+/// invalid command line or config.
+const ERROR_EXIT_CODE: i32 = 3;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[macro_export]
 macro_rules! version_0 {
@@ -74,120 +110,570 @@ pub struct Cli {
     #[arg(long, requires = "check")]
     pub check_format: bool,
 
+    /// Whether --check-format fails for a manifest that is sorted but not formatted, `warn` prints a warning instead
+    #[arg(long, value_name = "LEVEL", value_enum, default_value_t, requires = "check")]
+    pub format_severity: Severity,
+
+    /// Lists the manifests that would be rewritten and whether sorting or formatting changes them,
+    /// without writing them, changes do not fail the run
+    #[arg(long, conflicts_with_all = ["check", "print", "show_config"])]
+    pub dry_run: bool,
+
+    /// Prints a unified diff of what --check would change
+    #[arg(long, requires = "check")]
+    pub diff: bool,
+
     /// Checks every crate in a workspace
     #[arg(short, long)]
     pub workspace: bool,
 
+    /// Checks only the `workspace.default-members`, the root package if there are none, with --workspace
+    #[arg(long)]
+    pub default_members_only: bool,
+
+    /// Checks every Cargo.toml below the given directories, skipping `target` and hidden directories
+    #[arg(short, long)]
+    pub recursive: bool,
+
     /// Keep blank lines when sorting groups of key value pairs
     #[arg(short, long)]
     pub grouped: bool,
 
-    /// List the order tables should be written out
-    /// (--order package,dependencies,features)
+    /// Writes LF line endings, same as `newline_style = "lf"`
+    #[arg(long, conflicts_with = "crlf")]
+    pub lf: bool,
+
+    /// Writes CRLF line endings, same as `newline_style = "crlf"`
+    #[arg(long)]
+    pub crlf: bool,
+
+    /// The line endings to write, `auto` keeps the ones most lines of a manifest use
+    #[arg(
+        long,
+        value_name = "STYLE",
+        alias = "line-ending",
+        value_parser = clap::builder::PossibleValuesParser::new(NewlineStyle::NAMES),
+        conflicts_with_all = ["lf", "crlf"]
+    )]
+    pub newline_style: Option<String>,
+
+    /// List the order tables should be written out, `*` stands for the unlisted tables
+    /// (--order package,*,lints)
     #[arg(short, long, value_delimiter = ',')]
     pub order: Vec<String>,
+
+    /// Prints one `<status>\t<path>` line per manifest to stdout and nothing else,
+    /// the format is stable across versions
+    #[arg(long, conflicts_with = "print")]
+    pub porcelain: bool,
+
+    /// Prints one JSON object per manifest to stdout with `json`, the other messages go to stderr
+    #[arg(long, value_name = "FMT", value_enum, default_value_t, conflicts_with_all = ["print", "porcelain", "show_config"])]
+    pub message_format: MessageFormat,
+
+    /// Prints the effective config for each manifest and where each value came from
+    #[arg(long, conflicts_with_all = ["check", "print", "porcelain"])]
+    pub show_config: bool,
+
+    /// Skips manifests matching this glob, relative to the current directory,
+    /// in addition to the `ignore` config list
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Applies the `[profile.<NAME>]` section of the config files
+    #[arg(long, value_name = "NAME", env = "CARGO_SORT_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Accepts unknown keys in the config files, same as `allow_unknown_keys = true`
+    #[arg(long)]
+    pub allow_unknown_config: bool,
+
+    /// Ignores the per user config file, for reproducible runs
+    #[arg(long)]
+    pub no_user_config: bool,
+
+    /// Prints extra information, such as the config file in use, to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Prints only the errors and warnings of each manifest, and the summary of --check
+    #[arg(short, long, conflicts_with_all = ["verbose", "print"])]
+    pub quiet: bool,
+
+    /// Writes a tomlfmt.toml with every option set to its default to the current directory
+    #[arg(long, conflicts_with_all = ["check", "print", "porcelain", "show_config"])]
+    pub init_config: bool,
+
+    /// Reads a manifest from stdin and writes it sorted to stdout, same as passing `-` as the path
+    #[arg(long, conflicts_with = "cwd", conflicts_with_all = STDIN_CONFLICTS)]
+    pub stdin: bool,
+
+    /// The path of the manifest read with --stdin, the config files are looked up from there
+    #[arg(long, value_name = "PATH")]
+    pub stdin_filepath: Option<PathBuf>,
+
+    /// Overwrites an existing tomlfmt.toml with --init-config
+    #[arg(long, requires = "init_config")]
+    pub force: bool,
+
+    /// Number of manifests to process in parallel, output is printed sorted by path
+    /// [default: the number of CPUs]
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+}
+
+impl Cli {
+    /// The number of threads processing manifests, `--print` writes the
+    /// manifests one after another.
+    fn jobs(&self) -> usize {
+        if self.print {
+            return 1;
+        }
+        self.jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    }
 }
 
-fn write_red<S: Display>(highlight: &str, msg: S) -> Result<()> {
-    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-    stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
-    write!(stderr, "{highlight}")?;
-    stderr.reset()?;
-    writeln!(stderr, "{msg}").map_err(Into::into)
+/// How the outcome of each manifest is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Colored messages for people.
+    #[default]
+    Human,
+    /// One JSON object per manifest on stdout, the messages go to stderr.
+    Json,
+    /// The messages for people, and a GitHub Actions `::error` or `::warning`
+    /// workflow command on stdout for each problem `--check` finds.
+    Github,
 }
 
-fn write_green<S: Display>(highlight: &str, msg: S) -> Result<()> {
-    let mut stdout = StandardStream::stdout(ColorChoice::Auto);
-    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
-    write!(stdout, "{highlight}")?;
-    stdout.reset()?;
-    writeln!(stdout, "{msg}").map_err(Into::into)
+/// How `--check` treats a manifest that only needs formatting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Severity {
+    /// A warning, the run still passes.
+    Warn,
+    /// An error, the run fails.
+    #[default]
+    Error,
 }
 
-fn check_toml(path: &str, cli: &Cli, config: &Config) -> Result<bool> {
+/// Returns the manifest for a path given on the command line, directories
+/// point at the `Cargo.toml` inside them.
+fn manifest_path(path: &str) -> PathBuf {
     let mut path = PathBuf::from(path);
     if path.is_dir() {
         path.push(CARGO_TOML);
     }
+    path
+}
 
-    let krate = path.components().nth_back(1).ok_or("No crate folder found")?.as_os_str();
+/// A manifest read and parsed once, for the config lookup, the workspace
+/// members and the check.
+#[derive(Debug, Clone)]
+struct Manifest {
+    path: PathBuf,
+    raw: String,
+    doc: Result<DocumentMut, ParseError>,
+}
 
-    write_green("Checking ", format!("{}...", krate.to_string_lossy()))?;
+impl Manifest {
+    fn read(path: PathBuf) -> Result<Self> {
+        let raw = read_file(&path)?;
+        let doc = parse_toml(&raw);
+        Ok(Self { path, raw, doc })
+    }
+}
+
+/// Reads the toml file at `path`, a file that is not UTF-8 is reported
+/// apart from one that is missing.
+pub(crate) fn read_file(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).map_err(|err| {
+        let err = match err.kind() {
+            io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, format!("no file found ({err})")),
+            _ => err,
+        };
+        Error::from(err).with_path(path)
+    })?;
+    let problem = match String::from_utf8(bytes) {
+        Err(err) if matches!(err.as_bytes(), [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..]) => {
+            "is not valid UTF-8, it starts with a UTF-16 byte order mark".to_owned()
+        }
+        Err(err) => format!("is not valid UTF-8, is it UTF-16? ({})", err.utf8_error()),
+        // UTF-16 without a byte order mark can be valid UTF-8, which toml
+        // would reject for the NUL bytes
+        Ok(text) if text.contains('\0') => "contains NUL bytes, is it UTF-16?".to_owned(),
+        Ok(text) => return Ok(text),
+    };
+    let msg = format!("the file exists but {problem}");
+    Err(Error::from(io::Error::new(io::ErrorKind::InvalidData, msg)).with_path(path))
+}
+
+/// The name used for a manifest in messages.
+///
+/// This is `package.name` when present, virtual manifests are labeled as the
+/// workspace root and anything else falls back to the parent directory name,
+/// or the path for a manifest right under the filesystem root.
+fn crate_label(doc: Option<&DocumentMut>, path: &Path) -> String {
+    if let Some(name) = doc.and_then(|doc| doc.get("package")?.get("name")?.as_str()) {
+        return name.to_owned();
+    }
+    // `Cargo.toml` and `./Cargo.toml` are in the current directory
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let dir = match absolute.parent().and_then(Path::file_name) {
+        Some(dir) => dir.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    };
+    if doc.is_some_and(|doc| doc.contains_key("workspace")) {
+        format!("workspace root ({dir})")
+    } else {
+        dir
+    }
+}
+
+/// The config layer holding the values explicitly passed on the command line.
+fn cli_layer(cli: &Cli) -> Table {
+    let mut table = Table::new();
+    if !cli.order.is_empty() {
+        // `--order` applies to the workspace root as well
+        table["table_order"] = value(Array::from_iter(&cli.order));
+        table["table_order_root"] = value(Array::from_iter(&cli.order));
+    }
+    if cli.grouped {
+        table["grouped"] = value(true);
+    }
+    if cli.check_format {
+        table["check_format"] = value(true);
+    }
+    if cli.workspace {
+        table["workspace"] = value(true);
+    }
+    if cli.no_format {
+        table["no_format"] = value(true);
+    }
+    if cli.lf {
+        table["newline_style"] = value(NewlineStyle::Lf.as_str());
+    }
+    if cli.crlf {
+        table["newline_style"] = value(NewlineStyle::Crlf.as_str());
+    }
+    if let Some(style) = &cli.newline_style {
+        table["newline_style"] = value(style);
+    }
+    if cli.allow_unknown_config {
+        table["allow_unknown_keys"] = value(true);
+    }
+    table
+}
 
-    let toml_raw = read_to_string(&path).map_err(|_| format!("No file found at: {}", path.display()))?;
+/// Checks or fixes the manifest at `path`, `root` is the already read
+/// manifest of the first path given.
+fn check_toml(path: &str, cli: &Cli, layers: &Layers, root: Option<&Manifest>, out: &mut Output) -> Result<Status> {
+    let path = manifest_path(path);
+    let Manifest { path, raw: toml_raw, doc } = match root {
+        Some(root) if root.path == path => root.clone(),
+        _ => Manifest::read(path)?,
+    };
 
-    let crlf = toml_raw.contains("\r\n");
+    let layers = layers.for_manifest(&path, doc.as_ref().ok(), cli_layer(cli))?;
+    for warning in layers.warnings() {
+        out.yellow("warning: ", warning);
+    }
+    if cli.verbose {
+        for source in layers.applied_overrides() {
+            out.cyan("note: ", format!("{} uses {source}", path.display()));
+        }
+    }
+    if cli.show_config {
+        out.print(format!("# {}\n{}", path.display(), layers.show()?));
+        return Ok(Status::Ok);
+    }
 
-    let mut config = config.clone();
-    if config.crlf.is_none() {
-        config.crlf = Some(crlf);
+    let krate = crate_label(doc.as_ref().ok(), &path);
+
+    out.green("Checking ", format!("{krate}..."));
+
+    let doc = doc.map_err(|err| Error::from(err).with_path(&path))?;
+    let mut config = layers.config()?;
+    if cli.dry_run && !config.no_format {
+        // a dry run lists the formatting it would apply too
+        config.check_format = true;
     }
+    let mut report = check_document(&toml_raw, doc, &config);
 
-    let mut sorted_doc = sort::sort_toml(&toml_raw, sort::MATCHER, cli.grouped, &config.table_order);
+    if cli.print {
+        out.print(report.output);
+        return Ok(Status::Ok);
+    }
 
-    // if no-format is not found apply formatting
-    let (origin_already_formatted, mut final_str) = if !cli.no_format || cli.check_format {
-        let before_fmt = sorted_doc.to_string();
-        fmt::fmt_toml(&mut sorted_doc, &config);
-        let final_str = sorted_doc.to_string();
-        (before_fmt == final_str, final_str)
+    let changed = toml_raw != report.output;
+    if !cli.check && !cli.dry_run && changed {
+        if let Err(source) = std::fs::write(&path, &report.output) {
+            // what fixing would have changed, the way `--check` reports it
+            render(&report, &krate, true, cli.format_severity, out);
+            return Err(Error::Write { path, source });
+        }
+        report.written = true;
+    }
+    let status = if cli.dry_run {
+        render_dry_run(&report, &path, changed, out)
     } else {
-        (true, sorted_doc.to_string())
+        render(&report, &krate, cli.check, cli.format_severity, out)
     };
+    if cli.diff && status != Status::Ok {
+        render_diff(&relative_to_root(&path, root), &toml_raw, &report.output, out);
+    }
+    match cli.message_format {
+        MessageFormat::Human => {}
+        MessageFormat::Json => out.json(output::json_report(&path.display().to_string(), status, &report)),
+        MessageFormat::Github => {
+            let name = relative_to_cwd(&path);
+            for command in output::github_annotations(&name.display().to_string(), status, &report, &toml_raw) {
+                out.github(command);
+            }
+        }
+    }
+    Ok(status)
+}
 
-    if config.crlf.unwrap_or(fmt::DEF_CRLF) && !final_str.contains("\r\n") {
-        final_str = final_str.replace('\n', "\r\n");
+/// Sorts and formats the manifest `raw` read from stdin as if it were the
+/// manifest at `path`, a `Cargo.toml` in the current directory without one.
+fn sort_stdin(raw: &str, path: Option<&Path>, cli: &Cli, layers: &Layers) -> Result<String> {
+    let doc = parse_toml(raw).map_err(|err| match path {
+        Some(path) => Error::from(err).with_path(path),
+        None => Error::from(err),
+    })?;
+    let layers = layers.for_manifest(path.unwrap_or(Path::new(CARGO_TOML)), Some(&doc), cli_layer(cli))?;
+    for warning in layers.warnings() {
+        output::write_yellow("warning: ", warning)?;
     }
+    Ok(check_document(raw, doc, &layers.config()?).output)
+}
 
-    if cli.print {
-        print!("{final_str}");
-        return Ok(true);
+/// Returns `path` relative to the current directory, if it is below it.
+fn relative_to_cwd(path: &Path) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let name = path.strip_prefix(&cwd).unwrap_or(path);
+    name.strip_prefix(".").unwrap_or(name).to_owned()
+}
+
+/// Returns `path` relative to the directory of the `root` manifest, the
+/// workspace root, or else relative to the current directory.
+fn relative_to_root(path: &Path, root: Option<&Manifest>) -> PathBuf {
+    let dir = root.and_then(|root| root.path.parent());
+    match dir.and_then(|dir| path.strip_prefix(dir).ok()) {
+        Some(name) => name.strip_prefix(".").unwrap_or(name).to_owned(),
+        None => relative_to_cwd(path),
     }
+}
 
-    let origin_already_sorted = toml_raw == final_str;
-    if cli.check {
-        if !origin_already_sorted {
-            write_red("error: ", format!("Dependencies for {} are not sorted", krate.to_string_lossy()))?;
+/// Adds the diff from `original` to `output` of the manifest `name`, the path
+/// used in the file header.
+fn render_diff(name: &Path, original: &str, output: &str, out: &mut Output) {
+    let (old, new) = if name.is_relative() {
+        (format!("a/{}", name.display()), format!("b/{}", name.display()))
+    } else {
+        (name.display().to_string(), name.display().to_string())
+    };
+
+    // compared without CR, so a change of line endings is not every line
+    let (original, output) = (original.replace("\r\n", "\n"), output.replace("\r\n", "\n"));
+    let hunks = unified_diff(&original, &output, 3);
+    if hunks.is_empty() {
+        out.cyan("note: ", format!("{} only differs in its line endings", name.display()));
+    } else {
+        out.diff(format!("--- {old}\n+++ {new}\n{hunks}"));
+    }
+}
+
+/// Prints the outcome of checking or fixing the manifest of `krate` and
+/// returns its status.
+fn render(report: &CheckReport, krate: &str, check: bool, format_severity: Severity, out: &mut Output) -> Status {
+    if check {
+        let sorts = !report.sorted;
+        if sorts {
+            out.red("error: ", format!("Dependencies for {krate} are not sorted"));
         }
 
-        if !origin_already_formatted {
-            write_red("error: ", format!("{CARGO_TOML} for {} is not formatted", krate.to_string_lossy()))?;
+        let mut format_problem = |msg: String| match format_severity {
+            Severity::Warn if !sorts => out.yellow("warning: ", msg),
+            _ => out.red("error: ", msg),
+        };
+        if !report.formatted {
+            format_problem(format!("{CARGO_TOML} for {krate} is not formatted"));
         }
 
-        return Ok(origin_already_sorted && origin_already_formatted);
+        if !report.newlines {
+            let style = report.newline_style.as_str().to_uppercase();
+            let lines = match report.newline_mismatches {
+                1 => "1 line".to_owned(),
+                n => format!("{n} lines"),
+            };
+            format_problem(format!("{CARGO_TOML} for {krate} does not use {style} line endings on {lines}"));
+        }
+
+        return if sorts {
+            Status::Unsorted
+        } else if !report.formatted || !report.newlines {
+            Status::Unformatted
+        } else {
+            Status::Ok
+        };
     }
 
-    if !origin_already_sorted {
-        std::fs::write(&path, &final_str)?;
-        let msg = format!("{CARGO_TOML} for {:?} has been rewritten", krate.to_string_lossy());
-        write_green("Finished: ", msg)?;
+    if report.written {
+        let msg = format!("{CARGO_TOML} for {krate:?} has been rewritten");
+        out.green("Finished: ", msg);
+        Status::Fixed
     } else {
-        let msg = format!("{CARGO_TOML} for {} is sorted already, no changes made", krate.to_string_lossy());
-        write_green("Finished: ", msg)?;
+        let msg = format!("{CARGO_TOML} for {krate} is sorted already, no changes made");
+        out.green("Finished: ", msg);
+        Status::Ok
     }
+}
 
-    Ok(true)
+/// Prints what fixing the manifest at `path` would change and returns the
+/// status `--check` gives a manifest that fixing rewrites.
+fn render_dry_run(report: &CheckReport, path: &Path, changed: bool, out: &mut Output) -> Status {
+    if !changed {
+        return Status::Ok;
+    }
+    let sorts = !report.sorted;
+    let formats = !report.formatted || !report.newlines;
+    let changes = match (sorts, formats) {
+        (true, true) => "sort and format",
+        (true, false) => "sort",
+        // line endings and the end of the file are formatting too
+        (false, _) => "format",
+    };
+    out.green("Would rewrite ", format!("{} ({changes})", path.display()));
+    if sorts { Status::Unsorted } else { Status::Unformatted }
 }
 
-fn _main() -> Result<()> {
+/// Parses the command line, a `-` as the only path reads the manifest from
+/// stdin like `--stdin`.
+fn parse_cli(args: Vec<String>) -> std::result::Result<Cli, clap::Error> {
+    let mut command = <Cli as clap::CommandFactory>::command();
+    let matches = command.try_get_matches_from_mut(args)?;
+    let mut cli = <Cli as clap::FromArgMatches>::from_arg_matches(&matches).map_err(|err| err.format(&mut command))?;
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if cli.cwd == ["-"] {
+        let conflict = STDIN_CONFLICTS.iter().find(|id| given(id));
+        if let Some(arg) = conflict.and_then(|id| command.get_arguments().find(|arg| arg.get_id() == id)) {
+            let msg = format!("the argument '-' cannot be used with '--{}'", arg.get_long().unwrap_or_default());
+            return Err(command.error(ErrorKind::ArgumentConflict, msg));
+        }
+        cli.cwd.clear();
+        cli.stdin = true;
+    } else if cli.stdin_filepath.is_some() && !cli.stdin {
+        let msg = "the argument '--stdin-filepath <PATH>' requires '--stdin' or '-'";
+        return Err(command.error(ErrorKind::MissingRequiredArgument, msg));
+    }
+    Ok(cli)
+}
+
+/// Runs the command line and returns the exit code.
+///
+/// An error of a single manifest is printed right away and the others are
+/// still processed, any such error decides the code.
+fn _main() -> Result<i32> {
     let mut args: Vec<String> = std::env::args().collect();
     // remove "sort-fix" when invoked `cargo sort-fix` sort-fix is the first arg
     // https://github.com/rust-lang/cargo/issues/7653
     if args.len() > 1 && args[1] == "sort-fix" {
         args.remove(1);
     }
-    let cli = <Cli as clap::Parser>::parse_from(args);
+    let cli = match parse_cli(args) {
+        Ok(cli) => cli,
+        // `--help` and `--version` exit with 0
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            err.print()?;
+            return Ok(ERROR_EXIT_CODE);
+        }
+    };
+    if let Some((_, table)) = cli.order.iter().enumerate().find(|(idx, table)| cli.order[..*idx].contains(table)) {
+        return Err(Error::Config(format!("`--order` lists `{table}` twice")));
+    }
 
-    let cwd = std::env::current_dir().map_err(|e| format!("no current directory found: {e}"))?;
+    let cwd = std::env::current_dir()?;
     let dir = cwd.to_string_lossy();
 
+    if cli.init_config {
+        let path = cwd.join("tomlfmt.toml");
+        if path.exists() && !cli.force {
+            return Err(Error::Config(format!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            )));
+        }
+        std::fs::write(&path, config::default_config_file()).map_err(|err| Error::from(err).with_path(&path))?;
+        return output::write_green("Created ", path.display()).map(|()| 0);
+    }
+
     let mut filtered_matches: Vec<String> = cli.cwd.clone();
     let is_posible_workspace = filtered_matches.is_empty() || filtered_matches.len() == 1;
     if filtered_matches.is_empty() {
         filtered_matches.push(dir.to_string());
     }
 
-    if cli.workspace && is_posible_workspace {
+    let mut layers = Layers::default();
+    if !cli.no_user_config
+        && let Some(path) = config::user_config_file()
+    {
+        for (path, table) in config::read_user_config(&path)? {
+            if cli.verbose {
+                output::write_cyan("note: ", format!("using user config file {}", path.display()))?;
+            }
+            layers.push(config::Source::User(path), table)?;
+        }
+    }
+    // the config of a manifest read from stdin is the one next to its path
+    let stdin_path = cli.stdin_filepath.as_deref().map(std::path::absolute).transpose()?;
+    let config_dir = stdin_path.as_deref().and_then(Path::parent).unwrap_or(&cwd);
+    if let Some((path, table)) = config::discover_config_file(config_dir)? {
+        for shadowed in config::shadowed_config_files(&path) {
+            let msg = format!("ignoring {} in favor of {}", shadowed.display(), path.display());
+            output::write_yellow("warning: ", msg)?;
+        }
+        for (path, table) in config::resolve_extends(path, table)? {
+            if cli.verbose {
+                output::write_cyan("note: ", format!("using config file {}", path.display()))?;
+            }
+            layers.push(config::Source::File(path), table)?;
+        }
+    }
+    if let Some(profile) = &cli.profile {
+        layers.select_profile(profile)?;
+    }
+    for warning in layers.warnings() {
+        output::write_yellow("warning: ", warning)?;
+    }
+
+    if cli.stdin {
+        let mut raw = String::new();
+        io::stdin().read_to_string(&mut raw)?;
+        let sorted = sort_stdin(&raw, stdin_path.as_deref(), &cli, &layers)?;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(sorted.as_bytes())?;
+        stdout.flush()?;
+        return Ok(0);
+    }
+
+    // `workspace = true` may come from the config of the root manifest
+    let root = Manifest::read(manifest_path(&filtered_matches[0])).ok();
+    let root_doc = root.as_ref().and_then(|root| root.doc.as_ref().ok());
+    if let Some((root, doc)) = root.as_ref().zip(root_doc) {
+        layers.add_manifest(&root.path, doc);
+    }
+    let root_layers = layers.for_manifest(&manifest_path(&filtered_matches[0]), root_doc, cli_layer(&cli))?;
+    let root_config = root_layers.config()?;
+    for warning in config::unknown_table_warnings(&root_config) {
+        output::write_yellow("warning: ", warning)?;
+    }
+    let workspace = root_config.workspace;
+
+    if workspace && is_posible_workspace {
         let mut file_path = PathBuf::from(&&filtered_matches[0]);
         let dir = if file_path.is_file() {
             let mut path_dir = file_path.clone();
@@ -198,78 +684,214 @@ fn _main() -> Result<()> {
             file_path.push(CARGO_TOML);
             path_dir.to_string_lossy().to_string()
         } else {
-            let m = format!("Item `{}` is not a file or directory", file_path.display());
-            return Err(m.into());
+            return Err(not_found(&file_path));
         };
 
-        let raw_toml = read_to_string(&file_path).map_err(|_| format!("no file found at: {}", file_path.display()))?;
-
-        let toml = raw_toml.parse::<DocumentMut>()?;
-        let workspace = toml.get("workspace");
-        if let Some(Item::Table(ws)) = workspace {
-            // The workspace excludes, used to filter members by
-            let excludes = workspace_items_of_kind(&dir, ws, "exclude")?;
-            let members = workspace_items_of_kind(&dir, ws, "members")?;
-            'globs: for member in &members {
-                // The `check_toml` function expects only folders that it appends `Cargo.toml` onto
-                if member.is_file() {
-                    continue;
-                }
-                for excl in &excludes {
-                    if member == excl {
-                        continue 'globs;
-                    }
-                }
-                filtered_matches.push(member.display().to_string());
+        // the root manifest, which is read already
+        let toml = match root.as_ref().map(|root| &root.doc) {
+            Some(Ok(doc)) => doc,
+            Some(Err(err)) => return Err(Error::from(err.clone()).with_path(&file_path)),
+            None => return Err(not_found(&file_path)),
+        };
+        let (members, without_manifest) = workspace_members(&dir, toml.as_table(), cli.default_members_only)?;
+        if cli.verbose {
+            for member in without_manifest {
+                output::write_cyan("note: ", format!("skipping {}, it has no {CARGO_TOML}", member.display()))?;
             }
         }
+        filtered_matches.extend(members.iter().map(|member| member.display().to_string()));
     }
 
-    let mut cwd = cwd.clone();
-    cwd.push("tomlfmt.toml");
-    let mut config = read_to_string(&cwd)
-        .or_else(|_err| {
-            cwd.pop();
-            cwd.push(".tomlfmt.toml");
-            read_to_string(&cwd)
-        })
-        .unwrap_or_default()
-        .parse::<Config>()?;
+    if cli.recursive {
+        let mut found = vec![];
+        for path in &filtered_matches {
+            if Path::new(path).is_dir() {
+                find_manifests(Path::new(path), &mut found)?;
+            } else {
+                found.push(PathBuf::from(path));
+            }
+        }
+        filtered_matches = found.iter().map(|path| path.display().to_string()).collect();
+    }
+    let filtered_matches = dedup_manifests(filtered_matches);
 
-    if !cli.order.is_empty() {
-        config.table_order = cli.order.clone();
+    let mut ignore = config::Ignore::default();
+    ignore.add(&config::base_dir(&root_layers.source_of("ignore"), &cwd), &root_config.ignore)?;
+    ignore.add(&cwd, &cli.exclude)?;
+    let mut kept = vec![];
+    for path in filtered_matches {
+        match ignore.matching(&manifest_path(&path)) {
+            Some(pattern) if cli.verbose => output::write_cyan("note: ", format!("skipping {path}, it matches `{pattern}`"))?,
+            Some(_) => {}
+            None => kept.push(path),
+        }
     }
+    let filtered_matches = kept;
 
-    let mut flag = true;
-    for sorted in filtered_matches.iter().map(|path| check_toml(path, &cli, &config)) {
-        match sorted {
-            Ok(true) => continue,
-            Ok(false) => flag = false,
-            Err(e) => {
-                write_red("error: ", e)?;
-                flag = false;
+    let json = cli.message_format == MessageFormat::Json;
+    let progress = progress::Progress::new(
+        filtered_matches.len(),
+        !cli.print && !cli.porcelain && !cli.show_config && !json && !cli.quiet,
+    );
+    let (mut unsorted, mut invalid, mut unwritten, mut failed) = (0, 0, 0, 0);
+    let (mut unformatted, mut checked) = (0, 0);
+    let mut first_error = None;
+    let mut emit = |idx: usize, out: Output, status: Result<Status>| -> Result<()> {
+        checked += 1;
+        out.emit(cli.porcelain || json || cli.quiet)?;
+        let status = status.or_else(|e| {
+            first_error.get_or_insert(exit_code(&e));
+            let path = manifest_path(&filtered_matches[idx]);
+            let line = match cli.message_format {
+                MessageFormat::Human => None,
+                MessageFormat::Json => Some(output::json_error(&path.display().to_string(), &e.to_string())),
+                MessageFormat::Github => Some(output::github_error(&relative_to_cwd(&path).display().to_string(), &e)),
+            };
+            if let Some(line) = line {
+                progress::suspend(|| println!("{line}"));
+            }
+            match e {
+                Error::Parse { .. } => invalid += 1,
+                Error::Write { .. } => unwritten += 1,
+                _ => failed += 1,
             }
+            write_red("error: ", e).map(|()| Status::Error)
+        })?;
+        if cli.porcelain {
+            let path = manifest_path(&filtered_matches[idx]);
+            progress::suspend(|| println!("{}", output::porcelain_line(status, &path.display().to_string())));
+        }
+        // a dry run only reports what would change
+        let fails = match status {
+            Status::Unsorted => !cli.dry_run,
+            Status::Unformatted => !cli.dry_run && cli.format_severity == Severity::Error,
+            _ => false,
+        };
+        unsorted += usize::from(fails);
+        unformatted += usize::from(fails && status == Status::Unformatted);
+        Ok(())
+    };
+    process_manifests(&filtered_matches, &cli, &layers, root.as_ref(), &progress, &mut emit)?;
+    progress.finish();
+
+    let fails = unsorted + invalid + unwritten + failed > 0;
+    if cli.check && !cli.porcelain && !json {
+        let counts = [
+            (unsorted - unformatted, "unsorted"),
+            (unformatted, "unformatted"),
+            (invalid, "invalid"),
+            (unwritten, "unwritten"),
+            (failed, "failed"),
+        ];
+        let summary = output::check_summary(checked, &counts);
+        if fails {
+            write_red("Checked ", summary)?;
+        } else {
+            output::write_green("Checked ", summary)?;
         }
     }
+    if !fails {
+        return Ok(0);
+    }
+    let summary = Error::Check {
+        unsorted,
+        invalid,
+        unwritten,
+        failed,
+    };
+    let code = match first_error {
+        Some(code) => code,
+        None if unformatted == unsorted => UNFORMATTED_EXIT_CODE,
+        None => exit_code(&summary),
+    };
+    if !cli.check || cli.porcelain || json {
+        write_red("error: ", summary)?;
+    }
+    Ok(code)
+}
 
-    if !flag {
-        return Err("Some Cargo.toml files are not sorted or formatted".into());
+/// Runs `check_toml` on every path, using up to [`Cli::jobs`] threads.
+///
+/// Each manifest's messages are buffered and handed to `emit` strictly in the
+/// order of `paths`, regardless of the order in which the manifests finish.
+/// `progress` ticks as soon as a manifest is picked up.
+fn process_manifests<F>(
+    paths: &[String],
+    cli: &Cli,
+    layers: &Layers,
+    root: Option<&Manifest>,
+    progress: &progress::Progress,
+    emit: &mut F,
+) -> Result<()>
+where
+    F: FnMut(usize, Output, Result<Status>) -> Result<()>,
+{
+    let check = |idx: usize, path: &str| {
+        progress.tick(idx, path);
+        let mut out = Output::default();
+        let sorted = check_toml(path, cli, layers, root, &mut out);
+        (out, sorted)
+    };
+
+    let jobs = cli.jobs().min(paths.len());
+    if jobs <= 1 {
+        for (idx, path) in paths.iter().enumerate() {
+            let (out, sorted) = check(idx, path);
+            emit(idx, out, sorted)?;
+        }
+        return Ok(());
     }
-    Ok(())
+
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (next, check) = (&next, &check);
+            scope.spawn(move || {
+                loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(idx) else { break };
+                    let (out, sorted) = check(idx, path);
+                    if tx.send((idx, out, sorted)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Results arrive in completion order, hold them back until every
+        // earlier manifest has been emitted.
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (idx, out, sorted) in rx {
+            pending.insert(idx, (out, sorted));
+            while let Some((out, sorted)) = pending.remove(&expected) {
+                emit(expected, out, sorted)?;
+                expected += 1;
+            }
+        }
+        Ok(())
+    })
 }
 
 fn array_string_members(value: &Item) -> Vec<&str> {
     value.as_array().into_iter().flatten().filter_map(|s| s.as_str()).collect()
 }
 
-fn workspace_items_of_kind(dir: &str, ws: &toml_edit::Table, kind: &str) -> Result<Vec<PathBuf>> {
+fn workspace_items_of_kind(dir: &str, ws: &Table, kind: &str) -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
     for member in ws.get(kind).map_or_else(Vec::new, array_string_members) {
         // TODO: a better test wether to glob?
         if member.contains('*') || member.contains('?') {
-            let paths_iter = glob::glob(&format!("{dir}/{member}"))?;
+            let paths_iter = glob::glob(&format!("{dir}/{member}"))
+                .map_err(|e| Error::Config(format!("invalid `workspace.{kind}` pattern `{member}`: {e}")))?;
             for path in paths_iter {
-                paths.push(path?);
+                paths.push(path.map_err(|e| {
+                    let path = e.path().to_owned();
+                    Error::from(e.into_error()).with_path(path)
+                })?);
             }
         } else {
             let mut path = PathBuf::from(dir);
@@ -280,22 +902,719 @@ fn workspace_items_of_kind(dir: &str, ws: &toml_edit::Table, kind: &str) -> Resu
     Ok(paths)
 }
 
+/// Returns the member directories of the workspace whose root manifest in
+/// `dir` is `manifest`, and apart from them the directories that match
+/// `workspace.members` but hold no `Cargo.toml`.
+///
+/// A member is excluded when it or a directory above it matches a
+/// `workspace.exclude` entry, which may be a glob. With `default_only` just
+/// the members that match `workspace.default-members` are returned, without
+/// it the root package alone or every member of a virtual workspace.
+fn workspace_members(dir: &str, manifest: &Table, default_only: bool) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let Some(Item::Table(ws)) = manifest.get("workspace") else {
+        return Ok((vec![], vec![]));
+    };
+    let mut excludes = vec![];
+    for exclude in ws.get("exclude").map_or_else(Vec::new, array_string_members) {
+        let pattern = exclude.trim_start_matches("./").trim_end_matches('/');
+        let pattern =
+            glob::Pattern::new(pattern).map_err(|e| Error::Config(format!("invalid `workspace.exclude` pattern `{exclude}`: {e}")))?;
+        excludes.push(pattern);
+    }
+    let defaults = match ws.get("default-members") {
+        Some(_) if default_only => Some(workspace_items_of_kind(dir, ws, "default-members")?),
+        None if default_only && manifest.contains_key("package") => Some(vec![]),
+        _ => None,
+    };
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..glob::MatchOptions::new()
+    };
+
+    let (mut members, mut without_manifest) = (vec![], vec![]);
+    for member in workspace_items_of_kind(dir, ws, "members")? {
+        let relative = member.strip_prefix(dir).unwrap_or(&member);
+        let excluded = relative
+            .ancestors()
+            .any(|path| excludes.iter().any(|exclude| exclude.matches_path_with(path, options)));
+        if excluded || defaults.as_ref().is_some_and(|defaults| !defaults.contains(&member)) {
+            continue;
+        }
+        // `check_toml` appends `Cargo.toml` to the directory
+        if member.join(CARGO_TOML).is_file() {
+            members.push(member);
+        } else if member.is_dir() {
+            without_manifest.push(member);
+        }
+    }
+    Ok((members, without_manifest))
+}
+
+/// Collects `dir` and every directory below it that holds a `Cargo.toml`, in
+/// path order.
+///
+/// `target` and hidden directories are skipped and symlinks are not followed.
+fn find_manifests(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|err| Error::from(err).with_path(dir))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    if entries.iter().any(|entry| entry.file_name() == CARGO_TOML) {
+        found.push(dir.to_owned());
+    }
+    for entry in entries {
+        let name = entry.file_name();
+        let skipped = name == "target" || name.to_string_lossy().starts_with('.');
+        if !skipped && entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+            find_manifests(&entry.path(), found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Drops the paths naming a manifest that an earlier path names already,
+/// e.g. a workspace member that was also given or found directly, and sorts
+/// the rest by path, the order their output is printed in.
+fn dedup_manifests(paths: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut paths = paths
+        .into_iter()
+        .filter(|path| {
+            let manifest = manifest_path(path);
+            seen.insert(std::fs::canonicalize(&manifest).unwrap_or(manifest))
+        })
+        .collect::<Vec<_>>();
+    paths.sort_by_cached_key(|path| manifest_path(path));
+    paths
+}
+
+/// The error for a path that is neither a manifest nor a directory holding
+/// one.
+fn not_found(path: &Path) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::NotFound, "no file found")).with_path(path)
+}
+
+/// The exit code for `err`, the README lists them.
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Check { .. } => 1,
+        _ => ERROR_EXIT_CODE,
+    }
+}
+
 fn main() {
-    _main().unwrap_or_else(|e| {
+    let code = _main().unwrap_or_else(|e| {
+        let code = exit_code(&e);
         write_red("error: ", e).unwrap();
-        std::process::exit(1);
+        code
     });
+    std::process::exit(code);
 }
 
-// #[test]
-// fn fuzzy_fail() {
-//     for file in std::fs::read_dir("out/default/crashes").unwrap() {
-//         let path = file.unwrap().path();
-//         println!("{}", path.display());
-//         let s = read_to_string(&path).unwrap().replace("\r", "");
-//         let mut toml = sort::sort_toml(&s, sort::MATCHER, false);
-//         fmt::fmt_toml(&mut toml, &fmt::Config::default());
-//         print!("{}", s);
-//         s.parse::<DocumentMut>().unwrap();
-//     }
-// }
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use cargo_sort_fix::Error;
+
+    use super::{Cli, Output, Status, process_manifests};
+    use crate::{
+        config::{Layers, Source},
+        output::Line,
+        test_utils::temp_dir,
+    };
+
+    /// Layers that keep the order of the tables, so the fixtures are sorted.
+    fn keep_table_order() -> Layers {
+        let mut layers = Layers::default();
+        layers
+            .push(
+                Source::Cli,
+                "table_order = []".parse::<toml_edit::DocumentMut>().unwrap().as_table().clone(),
+            )
+            .unwrap();
+        layers
+    }
+
+    fn run(paths: &[String], jobs: &str) -> Vec<(usize, Output, Result<Status, String>)> {
+        let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check", "--jobs", jobs]);
+        let mut emitted = vec![];
+        let mut emit = |idx, out, sorted: super::Result<Status>| {
+            emitted.push((idx, out, sorted.map_err(|e| e.to_string())));
+            Ok(())
+        };
+        let progress = crate::progress::Progress::new(paths.len(), false);
+        process_manifests(paths, &cli, &keep_table_order(), None, &progress, &mut emit).unwrap();
+        emitted
+    }
+
+    #[test]
+    fn parallel_output_is_deterministic() {
+        let mut paths = std::fs::read_dir("examp")
+            .unwrap()
+            .map(|entry| entry.unwrap().path().display().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.push("examp/does-not-exist.toml".to_owned());
+        let paths = std::iter::repeat_n(paths, 8).flatten().collect::<Vec<_>>();
+
+        let sequential = run(&paths, "1");
+        assert_eq!(
+            sequential.iter().map(|(idx, ..)| *idx).collect::<Vec<_>>(),
+            (0..paths.len()).collect::<Vec<_>>()
+        );
+        for _ in 0..4 {
+            assert_eq!(sequential, run(&paths, "32"));
+        }
+    }
+
+    #[test]
+    fn job_counts() {
+        let jobs = |args: &[&str]| <Cli as clap::Parser>::parse_from(["cargo-sort-fix"].iter().chain(args)).jobs();
+        assert_eq!(jobs(&["--jobs", "3"]), 3);
+        assert_eq!(jobs(&[]), std::thread::available_parallelism().map_or(1, |n| n.get()));
+        assert_eq!(jobs(&["--print", "--jobs", "3"]), 1);
+        assert!(<Cli as clap::Parser>::try_parse_from(["cargo-sort-fix", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn porcelain_statuses() {
+        let paths = ["examp/right.toml", "examp/ruma.toml", "examp/does-not-exist.toml"].map(str::to_owned);
+        let statuses = run(&paths, "1").into_iter().map(|(_, _, status)| status.ok()).collect::<Vec<_>>();
+        assert_eq!(statuses, [Some(Status::Ok), Some(Status::Unsorted), None]);
+    }
+
+    #[test]
+    fn read_only_manifests() {
+        let path = temp_dir("read-only").join("Cargo.toml");
+        let unsorted = "[package]\nname = \"ro\"\n\n[dependencies]\nb = \"1\"\na = \"1\"\n";
+        std::fs::write(&path, unsorted).unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        // root may write anyway
+        if std::fs::OpenOptions::new().append(true).open(&path).is_ok() {
+            return;
+        }
+
+        let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix"]);
+        let mut out = Output::default();
+        let err = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut out).unwrap_err();
+        assert!(matches!(&err, Error::Write { path: written, .. } if *written == path), "{err}");
+        assert_eq!(super::exit_code(&err), 3);
+        let reported = Line::Red("error: ", "Dependencies for ro are not sorted".to_owned());
+        assert!(out.lines.contains(&reported), "{out:?}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), unsorted);
+    }
+
+    #[test]
+    fn unreadable_manifests() {
+        let dir = temp_dir("encodings");
+        let check = |name: &str, contents: Option<Vec<u8>>| {
+            let path = dir.join(name);
+            if let Some(contents) = contents {
+                std::fs::write(&path, contents).unwrap();
+            }
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check"]);
+            let err = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap_err();
+            assert_eq!(super::exit_code(&err), 3);
+            err.to_string()
+        };
+        let utf16 = |bom: bool| {
+            let text = "[package]\nname = \"a\"\n".encode_utf16();
+            let bom = bom.then_some(0xFEFF);
+            bom.into_iter().chain(text).flat_map(u16::to_le_bytes).collect::<Vec<_>>()
+        };
+
+        let err = check("bom.toml", Some(utf16(true)));
+        assert!(
+            err.ends_with("bom.toml: the file exists but is not valid UTF-8, it starts with a UTF-16 byte order mark"),
+            "{err}"
+        );
+        let err = check("no-bom.toml", Some(utf16(false)));
+        assert!(
+            err.ends_with("no-bom.toml: the file exists but contains NUL bytes, is it UTF-16?"),
+            "{err}"
+        );
+        let err = check("latin1.toml", Some(b"[package]\nname = \"caf\xE9\"\n".to_vec()));
+        assert!(
+            err.contains("latin1.toml: the file exists but is not valid UTF-8, is it UTF-16? ("),
+            "{err}"
+        );
+        let err = check("missing.toml", None);
+        assert!(err.contains("missing.toml: no file found ("), "{err}");
+    }
+
+    #[test]
+    fn newline_styles() {
+        let path = temp_dir("newlines").join("Cargo.toml");
+        let lf = std::fs::read_to_string("examp/right.toml").unwrap().replace("\r\n", "\n");
+        let crlf = lf.replace('\n', "\r\n");
+        let check = |contents: &str, args: &[&str]| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix"].iter().chain(args));
+            super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap()
+        };
+
+        assert_eq!(check(&lf, &["--check"]), Status::Ok);
+        assert_eq!(check(&crlf, &["--check"]), Status::Ok);
+        assert_eq!(check(&lf, &["--check", "--lf"]), Status::Ok);
+        // line endings are formatting, checked with --check-format only
+        assert_eq!(check(&crlf, &["--check", "--lf"]), Status::Ok);
+        assert_eq!(check(&crlf, &["--check", "--check-format", "--lf"]), Status::Unformatted);
+        assert_eq!(check(&lf, &["--check", "--check-format", "--crlf"]), Status::Unformatted);
+        assert_eq!(check(&crlf, &["--check", "--check-format", "--crlf"]), Status::Ok);
+        assert_eq!(
+            check(&crlf, &["--check", "--check-format", "--newline-style", "lf"]),
+            Status::Unformatted
+        );
+        assert_eq!(check(&crlf, &["--check", "--check-format", "--line-ending", "auto"]), Status::Ok);
+        let one_lf = crlf.replacen("\r\n", "\n", 1);
+        assert_eq!(check(&one_lf, &["--check"]), Status::Ok);
+        assert_eq!(check(&one_lf, &["--check", "--check-format"]), Status::Unformatted);
+        assert_eq!(
+            check(&one_lf, &["--check", "--check-format", "--newline-style", "crlf"]),
+            Status::Unformatted
+        );
+        assert_eq!(check(&lf, &["--check", "--crlf", "--no-format"]), Status::Ok);
+
+        assert_eq!(check(&lf, &["--crlf"]), Status::Fixed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), crlf);
+        assert_eq!(check(&crlf, &["--lf"]), Status::Fixed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), lf);
+        // a file with one pasted LF line keeps CRLF, the one line is repaired
+        assert_eq!(check(&one_lf, &[]), Status::Fixed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), crlf);
+    }
+
+    #[test]
+    fn workspace_members() {
+        let dir = temp_dir("workspace-members");
+        for sub in [
+            "",
+            "crates/a/fuzz",
+            "crates/b/fuzz",
+            "crates/a",
+            "crates/b",
+            "crates/legacy-x",
+            "examples/e",
+            "other",
+        ] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("Cargo.toml"), "[package]\nname = \"x\"\n").unwrap();
+        }
+        std::fs::create_dir_all(dir.join("tools/empty")).unwrap();
+        std::fs::write(dir.join("crates/notes.txt"), "").unwrap();
+
+        let members = |manifest: &str, default_only| {
+            let doc = manifest.parse::<toml_edit::DocumentMut>().unwrap();
+            let (members, without_manifest) = super::workspace_members(dir.to_str().unwrap(), doc.as_table(), default_only).unwrap();
+            let relative = |paths: Vec<std::path::PathBuf>| {
+                paths
+                    .iter()
+                    .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+                    .collect::<Vec<_>>()
+            };
+            (relative(members), relative(without_manifest))
+        };
+
+        // `other` is excluded without being a member, `examples` for the member below it
+        let manifest = r#"[workspace]
+members = ["crates/*", "crates/*/fuzz", "examples/*", "tools/*"]
+exclude = ["crates/legacy-*", "examples", "other"]
+default-members = ["crates/a", "crates/*/fuzz"]
+"#;
+        let (found, without_manifest) = members(manifest, false);
+        assert_eq!(found, ["crates/a", "crates/b", "crates/a/fuzz", "crates/b/fuzz"]);
+        assert_eq!(without_manifest, ["tools/empty"]);
+        assert_eq!(members(manifest, true).0, ["crates/a", "crates/a/fuzz", "crates/b/fuzz"]);
+
+        // a glob below a glob, `*` does not match across directories
+        let manifest = "[workspace]\nmembers = [\"crates/*\", \"crates/*/fuzz\"]\nexclude = [\"crates/*/fuzz\", \"./crates/b/\"]\n";
+        assert_eq!(members(manifest, false).0, ["crates/a", "crates/legacy-x"]);
+        assert_eq!(members(manifest, true).0, ["crates/a", "crates/legacy-x"]);
+        let with_package = format!("[package]\nname = \"root\"\n\n{manifest}");
+        assert!(members(&with_package, true).0.is_empty());
+        assert!(members("[package]\nname = \"root\"\n", false).0.is_empty());
+    }
+
+    #[test]
+    fn recursive_discovery() {
+        let dir = temp_dir("recursive");
+        for sub in ["", "tools/b", "tools/a", "tools/a/target/debug", ".git/x", "examples/c"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("Cargo.toml"), "[package]\nname = \"x\"\n").unwrap();
+        }
+        std::fs::create_dir_all(dir.join("examples/c/src")).unwrap();
+
+        let mut found = vec![];
+        super::find_manifests(&dir, &mut found).unwrap();
+        let found = found
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["", "examples/c", "tools/a", "tools/b"]);
+
+        // a member given directly and through the workspace is checked once
+        let member = dir.join("tools/a").display().to_string();
+        let paths = vec![
+            dir.display().to_string(),
+            member.clone(),
+            format!("{}/../a", dir.join("tools/b").display()),
+            format!("{member}/Cargo.toml"),
+        ];
+        assert_eq!(super::dedup_manifests(paths), [dir.display().to_string(), member.clone()]);
+
+        // and the rest are sorted by path, whatever order they were found in
+        let paths = vec![
+            dir.join("tools/b").display().to_string(),
+            dir.join("examples/c").display().to_string(),
+            member.clone(),
+            dir.display().to_string(),
+        ];
+        let sorted = super::dedup_manifests(paths)
+            .iter()
+            .map(|path| Path::new(path).strip_prefix(&dir).unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, ["", "examples/c", "tools/a", "tools/b"]);
+    }
+
+    #[test]
+    fn diffs() {
+        let path = temp_dir("diff").join("Cargo.toml");
+        let check = |contents: &str, args: &[&str]| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check", "--diff"].iter().chain(args));
+            let mut out = Output::default();
+            let status = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut out).unwrap();
+            (
+                status,
+                out.lines
+                    .into_iter()
+                    .filter(|line| matches!(line, Line::Diff(_) | Line::Cyan(..)))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let name = path.display();
+
+        let unsorted = "[package]\nname = \"d\"\n\n[dependencies]\nb = \"1\"\na = \"1\"\n";
+        let diff =
+            format!("--- {name}\n+++ {name}\n@@ -2,5 +2,5 @@\n name = \"d\"\n \n [dependencies]\n+a = \"1\"\n b = \"1\"\n-a = \"1\"\n");
+        assert_eq!(check(unsorted, &[]), (Status::Unsorted, vec![Line::Diff(diff)]));
+
+        let sorted = "[package]\nname = \"d\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        assert_eq!(check(sorted, &[]), (Status::Ok, vec![]));
+        let unformatted = sorted.replace("a = \"1\"", "a = { version = \"1\",features=[] }");
+        let diff = format!(
+            "--- {name}\n+++ {name}\n@@ -2,5 +2,5 @@\n name = \"d\"\n \n [dependencies]\n-{}\n+{}\n b = \"1\"\n",
+            "a = { version = \"1\",features=[] }", "a = { version = \"1\", features = [] }"
+        );
+        assert_eq!(
+            check(&unformatted, &["--check-format"]),
+            (Status::Unformatted, vec![Line::Diff(diff)])
+        );
+
+        let note = Line::Cyan("note: ", format!("{name} only differs in its line endings"));
+        assert_eq!(check(&sorted.replace('\n', "\r\n"), &["--lf"]), (Status::Ok, vec![]));
+        assert_eq!(
+            check(&sorted.replace('\n', "\r\n"), &["--check-format", "--lf"]),
+            (Status::Unformatted, vec![note])
+        );
+
+        // a member's header is relative to the workspace root
+        let dir = path.parent().unwrap();
+        std::fs::write(&path, "[workspace]\nmembers = [\"crates/a\"]\n").unwrap();
+        let root = super::Manifest::read(path.clone()).unwrap();
+        std::fs::create_dir_all(dir.join("crates/a")).unwrap();
+        let member = dir.join("crates/a/Cargo.toml");
+        std::fs::write(&member, unsorted).unwrap();
+        let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check", "--diff"]);
+        let mut out = Output::default();
+        super::check_toml(member.to_str().unwrap(), &cli, &keep_table_order(), Some(&root), &mut out).unwrap();
+        let header = out.lines.iter().find_map(|line| match line {
+            Line::Diff(diff) => diff.lines().next(),
+            _ => None,
+        });
+        assert_eq!(header, Some("--- a/crates/a/Cargo.toml"));
+    }
+
+    #[test]
+    fn format_severities() {
+        let path = temp_dir("format-severity").join("Cargo.toml");
+        let check = |contents: &str, args: &[&str]| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check"].iter().chain(args));
+            let mut out = Output::default();
+            let status = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut out).unwrap();
+            let problems = out
+                .lines
+                .into_iter()
+                .filter(|line| matches!(line, Line::Red(..) | Line::Yellow(..)));
+            (status, problems.collect::<Vec<_>>())
+        };
+        let not_sorted = Line::Red("error: ", "Dependencies for d are not sorted".to_owned());
+        let not_formatted = "Cargo.toml for d is not formatted".to_owned();
+        let (error, warning) = (
+            Line::Red("error: ", not_formatted.clone()),
+            Line::Yellow("warning: ", not_formatted),
+        );
+
+        let sorted = "[package]\nname = \"d\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        let unformatted = sorted.replace("a = \"1\"", "a = {version = \"1\"}");
+        // plain --check only fails what sorting moves
+        assert_eq!(check(&unformatted, &[]), (Status::Ok, vec![]));
+        assert_eq!(check(sorted.trim_end(), &[]), (Status::Ok, vec![]));
+        assert_eq!(check(sorted, &["--crlf"]), (Status::Ok, vec![]));
+
+        assert_eq!(check(&unformatted, &["--check-format"]), (Status::Unformatted, vec![error.clone()]));
+        // a missing newline at the end is not a sorting problem
+        assert_eq!(
+            check(sorted.trim_end(), &["--check-format"]),
+            (Status::Unformatted, vec![error.clone()])
+        );
+        assert_eq!(check(sorted.trim_end(), &["--no-format"]), (Status::Ok, vec![]));
+        assert_eq!(
+            check(&unformatted, &["--check-format", "--format-severity", "warn"]),
+            (Status::Unformatted, vec![warning])
+        );
+        assert_eq!(
+            check(sorted, &["--check-format", "--format-severity", "warn"]),
+            (Status::Ok, vec![])
+        );
+        let crlf = Line::Red("error: ", "Cargo.toml for d does not use CRLF line endings on 6 lines".to_owned());
+        assert_eq!(check(sorted, &["--check-format", "--crlf"]), (Status::Unformatted, vec![crlf]));
+
+        // sorting still fails the run, with the formatting that comes with it
+        let unsorted = "[package]\nname = \"d\"\n\n[dependencies]\nb = \"1\"\na = {version = \"1\"}\n";
+        assert_eq!(check(unsorted, &[]), (Status::Unsorted, vec![not_sorted.clone()]));
+        assert_eq!(
+            check(unsorted, &["--check-format", "--format-severity", "warn"]),
+            (Status::Unsorted, vec![not_sorted, error])
+        );
+
+        let cli = <Cli as clap::Parser>::try_parse_from(["cargo-sort-fix", "--format-severity", "warn"]);
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn quoted_keys() {
+        let path = temp_dir("quoted-keys").join("Cargo.toml");
+        let check = |contents: &str, args: &[&str]| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check"].iter().chain(args));
+            super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap()
+        };
+        let quoted = "[package]\nname = \"q\"\n\n[dependencies]\n\"serde_json\" = \"1\"\ntokio = \"1\"\n";
+        assert_eq!(check(quoted, &[]), Status::Ok);
+        assert_eq!(check(quoted, &["--check-format"]), Status::Ok);
+
+        // trailing whitespace and no newline at the end
+        let styled = quoted.replace("\"serde_json\" = \"1\"", "'serde_json'   =   \"1\"   ");
+        assert_eq!(check(styled.trim_end(), &[]), Status::Ok);
+        assert_eq!(check(styled.trim_end(), &["--check-format"]), Status::Unformatted);
+        let unsorted = "[package]\nname = \"q\"\n\n[dependencies]\ntokio = \"1\"\n\"serde_json\" = \"1\"\n";
+        assert_eq!(check(unsorted, &[]), Status::Unsorted);
+    }
+
+    #[test]
+    fn dotted_keys() {
+        let path = temp_dir("dotted-keys").join("Cargo.toml");
+        let check = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check"]);
+            super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap()
+        };
+        let dependencies = "[package]\nname = \"a\"\n\n[dependencies]\nb.workspace = true\na.workspace = true\n";
+        assert_eq!(check(dependencies), Status::Unsorted);
+        assert_eq!(
+            check(&dependencies.replace("b.workspace = true\na", "a.workspace = true\nb")),
+            Status::Ok
+        );
+        let lints = "[package]\nname = \"a\"\n\n[lints]\nrust.x = \"warn\"\nclippy.y = \"warn\"\n";
+        assert_eq!(check(lints), Status::Unsorted);
+        assert_eq!(
+            check(&lints.replace("rust.x = \"warn\"\nclippy.y", "clippy.y = \"warn\"\nrust.x")),
+            Status::Ok
+        );
+    }
+
+    #[test]
+    fn dry_runs() {
+        let path = temp_dir("dry-run").join("Cargo.toml");
+        let dry_run = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--dry-run"]);
+            let mut out = Output::default();
+            let status = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut out).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+            let listed = out
+                .lines
+                .into_iter()
+                .filter(|line| matches!(line, Line::Green("Would rewrite ", _)));
+            (status, listed.collect::<Vec<_>>())
+        };
+        let would = |changes| vec![Line::Green("Would rewrite ", format!("{} ({changes})", path.display()))];
+
+        let sorted = "[package]\nname = \"d\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        assert_eq!(dry_run(sorted), (Status::Ok, vec![]));
+        let unsorted = "[package]\nname = \"d\"\n\n[dependencies]\nb = \"1\"\na = \"1\"\n";
+        assert_eq!(dry_run(unsorted), (Status::Unsorted, would("sort")));
+        let unformatted = sorted.replace("a = \"1\"", "a = {version = \"1\"}");
+        assert_eq!(dry_run(&unformatted), (Status::Unformatted, would("format")));
+        let both = unsorted.replace("a = \"1\"", "a = {version = \"1\"}");
+        assert_eq!(dry_run(&both), (Status::Unsorted, would("sort and format")));
+
+        // `--check` fails for the changes a dry run only lists
+        let cli = <Cli as clap::Parser>::try_parse_from(["cargo-sort-fix", "--dry-run", "--check"]);
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn stdin_dash() {
+        let parse = |args: &[&str]| super::parse_cli(["cargo-sort-fix"].iter().chain(args).map(|&arg| arg.to_owned()).collect());
+        let cli = parse(&["-"]).unwrap();
+        assert!(cli.stdin && cli.cwd.is_empty());
+        let cli = parse(&["-", "--stdin-filepath", "-"]).unwrap();
+        assert!(cli.stdin && cli.stdin_filepath.as_deref() == Some(Path::new("-")));
+        let cli = parse(&["--order", "-", "."]).unwrap();
+        assert!(!cli.stdin && cli.order == ["-"]);
+        assert!(!parse(&["a", "-"]).unwrap().stdin);
+
+        let err = parse(&["-", "--check"]).unwrap_err().to_string();
+        assert!(err.contains("the argument '-' cannot be used with '--check'"), "{err}");
+        let err = parse(&["--stdin-filepath", "Cargo.toml"]).unwrap_err().to_string();
+        assert!(err.contains("requires '--stdin' or '-'"), "{err}");
+    }
+
+    #[test]
+    fn stdin() {
+        let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--stdin"]);
+        let unsorted = "[package]\r\nname = \"a\"\r\n\r\n[dependencies]\r\nb = \"1\"\r\na = \"1\"\r\n";
+        let sorted = super::sort_stdin(unsorted, None, &cli, &keep_table_order()).unwrap();
+        assert_eq!(
+            sorted,
+            "[package]\r\nname = \"a\"\r\n\r\n[dependencies]\r\na = \"1\"\r\nb = \"1\"\r\n"
+        );
+
+        // the config applies as if the manifest was at the given path
+        let mut layers = keep_table_order();
+        let grouped = "grouped = true".parse::<toml_edit::DocumentMut>().unwrap();
+        layers
+            .push(Source::File("tomlfmt.toml".into()), grouped.as_table().clone())
+            .unwrap();
+        let input = "[dependencies]\nc = \"1\"\n\nb = \"1\"\na = \"1\"\n";
+        let path = Path::new("crates/a/Cargo.toml");
+        let sorted = super::sort_stdin(input, Some(path), &cli, &layers).unwrap();
+        assert_eq!(sorted, "[dependencies]\nc = \"1\"\n\na = \"1\"\nb = \"1\"\n");
+
+        let err = super::sort_stdin("[dependencies\n", Some(path), &cli, &layers).unwrap_err();
+        assert!(matches!(&err, Error::Parse { path: Some(p), .. } if p == path), "{err}");
+        let err = super::sort_stdin("[dependencies\n", None, &cli, &layers).unwrap_err();
+        assert!(matches!(err, Error::Parse { path: None, .. }), "{err}");
+
+        for args in [&["--stdin", "--check"][..], &["--stdin", "."], &["--stdin-filepath", "Cargo.toml"]] {
+            let cli = super::parse_cli(["cargo-sort-fix"].iter().chain(args).map(|&arg| arg.to_owned()).collect());
+            assert!(cli.is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let path = temp_dir("parse-error").join("Cargo.toml");
+        std::fs::write(&path, "[package]\nname = \"a\"\n[dependencies\n").unwrap();
+        let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix", "--check"]);
+        let err = super::check_toml(path.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap_err();
+        let prefix = format!("{}: TOML parse error at line 3, column 14", path.display());
+        assert!(err.to_string().starts_with(&prefix), "{err}");
+        assert!(matches!(err, Error::Parse { path: Some(_), .. }));
+        assert_eq!(super::exit_code(&err), 3);
+
+        // a badly merged manifest is left as it is, in fix mode too
+        let merged = "[package]\nname = \"a\"\n\n[dependencies]\ntokio = \"1\"\ntokio = { version = \"1\", features = [\"full\"] }\n";
+        std::fs::write(&path, merged).unwrap();
+        let fix = <Cli as clap::Parser>::parse_from(["cargo-sort-fix"]);
+        let err = super::check_toml(path.to_str().unwrap(), &fix, &keep_table_order(), None, &mut Output::default()).unwrap_err();
+        let prefix = format!(
+            "{}: `tokio` of `[dependencies]` on line 6 is already defined on line 5\n",
+            path.display()
+        );
+        assert!(err.to_string().starts_with(&prefix), "{err}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), merged);
+        std::fs::write(&path, "[package]\nname = \"a\"\n[dependencies\n").unwrap();
+
+        let missing = path.with_file_name("missing.toml");
+        let err = super::check_toml(missing.to_str().unwrap(), &cli, &keep_table_order(), None, &mut Output::default()).unwrap_err();
+        assert!(matches!(&err, Error::Io { path: Some(path), .. } if *path == missing), "{err}");
+        assert_eq!(super::exit_code(&err), 3);
+        let check = Error::Check {
+            unsorted: 2,
+            invalid: 0,
+            unwritten: 0,
+            failed: 0,
+        };
+        assert_eq!(super::exit_code(&check), 1);
+
+        // the other manifests are still checked
+        let paths = [path.display().to_string(), "examp/right.toml".to_owned()];
+        let statuses = run(&paths, "1").into_iter().map(|(_, _, status)| status.ok()).collect::<Vec<_>>();
+        assert_eq!(statuses, [None, Some(Status::Ok)]);
+        assert_eq!(super::exit_code(&Error::Config(String::new())), 3);
+    }
+
+    #[test]
+    fn labels() {
+        let label = |toml: &str, path: &str| super::crate_label(toml.parse().ok().as_ref(), Path::new(path));
+        assert_eq!(label("[package]\nname = \"myorg-http\"\n", "crates/http/Cargo.toml"), "myorg-http");
+        assert_eq!(label("[workspace]\nmembers = []\n", "repo/Cargo.toml"), "workspace root (repo)");
+        assert_eq!(label("[dependencies]\n", "crates/http/Cargo.toml"), "http");
+
+        // too few components for a parent directory name
+        let cwd = std::env::current_dir().unwrap();
+        let cwd = cwd.file_name().unwrap().to_str().unwrap();
+        assert_eq!(label("[dependencies]\n", "Cargo.toml"), cwd);
+        assert_eq!(label("[dependencies]\n", "./Cargo.toml"), cwd);
+        assert_eq!(label("[workspace]\n", "/Cargo.toml"), "workspace root (/Cargo.toml)");
+        assert_eq!(label("[package]\nname = \"a\"\n", "/Cargo.toml"), "a");
+        assert_eq!(label("", "/Cargo.toml"), "/Cargo.toml");
+    }
+
+    #[test]
+    fn cli_overrides_config() {
+        let file = "grouped = true\ncheck_format = true\nworkspace = true\nno_format = true\ntable_order = [\"dependencies\"]\n";
+        let mut base = Layers::default();
+        base.push(
+            Source::File("tomlfmt.toml".into()),
+            file.parse::<toml_edit::DocumentMut>().unwrap().as_table().clone(),
+        )
+        .unwrap();
+        let config = |args: &[&str]| {
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix"].iter().chain(args));
+            base.for_manifest(Path::new("Cargo.toml"), None, super::cli_layer(&cli))
+                .unwrap()
+                .config()
+                .unwrap()
+        };
+
+        let from_file = config(&[]);
+        assert!(from_file.grouped && from_file.check_format && from_file.workspace && from_file.no_format);
+        assert_eq!(from_file.table_order, ["dependencies"]);
+
+        let from_cli = config(&["--order", "package,features"]);
+        assert_eq!(from_cli.table_order, ["package", "features"]);
+        assert_eq!(from_cli.table_order_for(true), ["package", "features"]);
+
+        let defaults = Layers::default();
+        let config = |args: &[&str]| {
+            let cli = <Cli as clap::Parser>::parse_from(["cargo-sort-fix"].iter().chain(args));
+            defaults
+                .for_manifest(Path::new("Cargo.toml"), None, super::cli_layer(&cli))
+                .unwrap()
+                .config()
+                .unwrap()
+        };
+        assert!(!config(&[]).grouped && config(&["--grouped"]).grouped);
+        assert!(!config(&[]).check_format && config(&["--check", "--check-format"]).check_format);
+        assert!(!config(&[]).workspace && config(&["--workspace"]).workspace);
+        assert!(!config(&[]).no_format && config(&["--no-format"]).no_format);
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    fmt::{Display, Write as _},
+    io::Write,
+};
+
+use cargo_sort_fix::{CheckReport, Error, NewlineStyle, diff};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+use crate::{CARGO_TOML, Result, progress};
+
+pub(crate) fn write_red<S: Display>(highlight: &str, msg: S) -> Result<()> {
+    progress::suspend(|| {
+        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+        write!(stderr, "{highlight}")?;
+        stderr.reset()?;
+        writeln!(stderr, "{msg}").map_err(Into::into)
+    })
+}
+
+pub(crate) fn write_green<S: Display>(highlight: &str, msg: S) -> Result<()> {
+    progress::suspend(|| {
+        let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+        write!(stdout, "{highlight}")?;
+        stdout.reset()?;
+        writeln!(stdout, "{msg}").map_err(Into::into)
+    })
+}
+
+pub(crate) fn write_yellow<S: Display>(highlight: &str, msg: S) -> Result<()> {
+    progress::suspend(|| {
+        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
+        write!(stderr, "{highlight}")?;
+        stderr.reset()?;
+        writeln!(stderr, "{msg}").map_err(Into::into)
+    })
+}
+
+pub(crate) fn write_cyan<S: Display>(highlight: &str, msg: S) -> Result<()> {
+    progress::suspend(|| {
+        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)))?;
+        write!(stderr, "{highlight}")?;
+        stderr.reset()?;
+        writeln!(stderr, "{msg}").map_err(Into::into)
+    })
+}
+
+/// Writes a unified diff to stdout, removed lines in red and added ones in
+/// green.
+pub(crate) fn write_diff(diff: &str) -> Result<()> {
+    progress::suspend(|| {
+        let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+        for line in diff.split_inclusive('\n') {
+            let mut spec = ColorSpec::new();
+            if line.starts_with("---") || line.starts_with("+++") {
+                spec.set_bold(true);
+            } else if line.starts_with("@@") {
+                spec.set_fg(Some(Color::Cyan));
+            } else if line.starts_with('-') {
+                spec.set_fg(Some(Color::Red));
+            } else if line.starts_with('+') {
+                spec.set_fg(Some(Color::Green));
+            }
+            if spec == ColorSpec::new() {
+                write!(stdout, "{line}")?;
+                continue;
+            }
+            stdout.set_color(&spec)?;
+            write!(stdout, "{}", line.trim_end_matches('\n'))?;
+            stdout.reset()?;
+            writeln!(stdout)?;
+        }
+        stdout.flush().map_err(Into::into)
+    })
+}
+
+/// The outcome of processing one manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// Already sorted (and formatted when that is checked), or printed.
+    Ok,
+    /// The manifest is not sorted.
+    Unsorted,
+    /// The manifest is sorted but not formatted.
+    Unformatted,
+    /// The manifest has been rewritten.
+    Fixed,
+    /// The manifest could not be processed.
+    Error,
+}
+
+impl Status {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Unsorted => "unsorted",
+            Status::Unformatted => "unformatted",
+            Status::Fixed => "fixed",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// Renders the summary `--check` ends with, after `Checked `: the number of
+/// manifests and those of `counts` that are not zero.
+pub(crate) fn check_summary(checked: usize, counts: &[(usize, &str)]) -> String {
+    let mut summary = match checked {
+        1 => "1 manifest".to_owned(),
+        n => format!("{n} manifests"),
+    };
+    let mut sep = ": ";
+    for (count, what) in counts.iter().filter(|(count, _)| *count > 0) {
+        write!(summary, "{sep}{count} {what}").unwrap();
+        sep = ", ";
+    }
+    summary
+}
+
+/// Renders the `--porcelain` line for a manifest.
+///
+/// This format is a compatibility surface: `<status>\t<path>` with one of the
+/// [`Status`] strings, it must not change between versions.
+pub(crate) fn porcelain_line(status: Status, path: &str) -> String {
+    format!("{}\t{path}", status.as_str())
+}
+
+/// Renders the `--message-format json` line for a manifest that was checked.
+pub(crate) fn json_report(path: &str, status: Status, report: &CheckReport) -> String {
+    let mut json = format!(
+        "{{\"path\":{},\"status\":\"{}\",\"sorted\":{},\"formatted\":{},\"newlines\":{},\"unsorted_tables\":[",
+        json_string(path),
+        status.as_str(),
+        report.sorted,
+        report.formatted,
+        report.newlines
+    );
+    let tables = report.unsorted_tables.iter().map(|table| json_string(table));
+    json.push_str(&tables.collect::<Vec<_>>().join(","));
+    json.push_str("],\"findings\":[");
+    let findings = report.misplaced_keys.iter().map(|misplaced| {
+        format!(
+            "{{\"table\":{},\"key\":{},\"expected_before\":{}}}",
+            json_string(&misplaced.table),
+            json_string(&misplaced.key),
+            json_string(&misplaced.expected_before)
+        )
+    });
+    json.push_str(&findings.collect::<Vec<_>>().join(","));
+    json.push_str("]}");
+    json
+}
+
+/// Renders the `--message-format json` line for a manifest that could not be
+/// checked.
+pub(crate) fn json_error(path: &str, error: &str) -> String {
+    format!(
+        "{{\"path\":{},\"status\":\"{}\",\"error\":{}}}",
+        json_string(path),
+        Status::Error.as_str(),
+        json_string(error)
+    )
+}
+
+/// Renders the `--message-format github` workflow commands for a manifest
+/// that was checked, an `::error` for each problem of sorting and a
+/// `::warning` at the first line formatting changes.
+///
+/// `input` is the manifest as it was read, the lines point into it.
+pub(crate) fn github_annotations(path: &str, status: Status, report: &CheckReport, input: &str) -> Vec<String> {
+    let mut commands = vec![];
+    if !matches!(status, Status::Unsorted | Status::Unformatted) {
+        return commands;
+    }
+    for misplaced in &report.misplaced_keys {
+        let msg = format!(
+            "`{}` of `[{}]` is out of order, it belongs before `{}`",
+            misplaced.key, misplaced.table, misplaced.expected_before
+        );
+        commands.push(github_command("error", path, misplaced.line, &msg));
+    }
+    // the tables and arrays that moved without a key moving inside them
+    let tables = report
+        .unsorted_tables
+        .iter()
+        .filter(|table| report.misplaced_keys.iter().all(|m| &m.table != *table));
+    for table in tables {
+        commands.push(github_command("error", path, None, &format!("`{table}` is not sorted")));
+    }
+
+    if !report.formatted {
+        let (input, output) = (input.replace("\r\n", "\n"), report.output.replace("\r\n", "\n"));
+        let line = diff(&input, &output).first().map(|hunk| hunk.original.start + 1);
+        commands.push(github_command("warning", path, line, &format!("{CARGO_TOML} is not formatted")));
+    }
+    if !report.newlines {
+        let lines = match report.newline_mismatches {
+            1 => "1 line".to_owned(),
+            n => format!("{n} lines"),
+        };
+        let style = report.newline_style.as_str().to_uppercase();
+        let msg = format!("{CARGO_TOML} does not use {style} line endings on {lines}");
+        let crlf = report.newline_style == NewlineStyle::Crlf;
+        let line = input
+            .split_inclusive('\n')
+            .position(|line| line.ends_with('\n') && line.ends_with("\r\n") != crlf);
+        commands.push(github_command("warning", path, line.map(|idx| idx + 1), &msg));
+    }
+    commands
+}
+
+/// Renders the `--message-format github` workflow command for a manifest
+/// that could not be checked, at the line of a parse error.
+pub(crate) fn github_error(path: &str, error: &Error) -> String {
+    // the command names the file, the message leaves it out
+    let (line, msg) = match error {
+        Error::Parse { source, .. } => {
+            // the line is in the command, a repeated key names both lines
+            let msg = match source.to_string().lines().next() {
+                Some(first) if source.duplicate().is_some() || source.duplicate_key().is_some() => first.to_owned(),
+                _ => source.message().to_owned(),
+            };
+            (source.line(), msg)
+        }
+        Error::Io { source, .. } => (None, source.to_string()),
+        Error::Write { source, .. } => (None, format!("failed to write: {source}")),
+        error => (None, error.to_string()),
+    };
+    github_command("error", path, line, &msg)
+}
+
+/// Renders the workflow command `::<level> file=<path>,line=<line>::<msg>`
+/// with the characters GitHub reads as syntax escaped.
+fn github_command(level: &str, path: &str, line: Option<usize>, msg: &str) -> String {
+    let escape_data = |text: &str| text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    let escape_property = |text: &str| escape_data(text).replace(':', "%3A").replace(',', "%2C");
+    let mut command = format!("::{level} file={}", escape_property(path));
+    if let Some(line) = line {
+        write!(command, ",line={line}").unwrap();
+    }
+    write!(command, "::{}", escape_data(msg)).unwrap();
+    command
+}
+
+/// Quotes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A single message produced while processing a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Line {
+    /// A green highlighted message on stdout.
+    Green(&'static str, String),
+    /// A red highlighted message on stderr.
+    Red(&'static str, String),
+    /// A yellow highlighted warning on stderr.
+    Yellow(&'static str, String),
+    /// A cyan highlighted note on stderr.
+    Cyan(&'static str, String),
+    /// Raw text written to stdout as is.
+    Print(String),
+    /// A unified diff written to stdout, colored line by line.
+    Diff(String),
+    /// A `--message-format json` line on stdout, written even with
+    /// `errors_only`.
+    Json(String),
+    /// A `--message-format github` workflow command on stdout, written even
+    /// with `errors_only`.
+    Github(String),
+}
+
+/// The messages for one manifest, buffered so they can be written out as a
+/// block in discovery order no matter when the manifest finished processing.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Output {
+    pub(crate) lines: Vec<Line>,
+}
+
+impl Output {
+    pub(crate) fn green<S: Display>(&mut self, highlight: &'static str, msg: S) {
+        self.lines.push(Line::Green(highlight, msg.to_string()));
+    }
+
+    pub(crate) fn red<S: Display>(&mut self, highlight: &'static str, msg: S) {
+        self.lines.push(Line::Red(highlight, msg.to_string()));
+    }
+
+    pub(crate) fn yellow<S: Display>(&mut self, highlight: &'static str, msg: S) {
+        self.lines.push(Line::Yellow(highlight, msg.to_string()));
+    }
+
+    pub(crate) fn cyan<S: Display>(&mut self, highlight: &'static str, msg: S) {
+        self.lines.push(Line::Cyan(highlight, msg.to_string()));
+    }
+
+    pub(crate) fn print<S: Display>(&mut self, text: S) {
+        self.lines.push(Line::Print(text.to_string()));
+    }
+
+    pub(crate) fn diff(&mut self, diff: String) {
+        self.lines.push(Line::Diff(diff));
+    }
+
+    pub(crate) fn json(&mut self, line: String) {
+        self.lines.push(Line::Json(line));
+    }
+
+    pub(crate) fn github(&mut self, command: String) {
+        self.lines.push(Line::Github(command));
+    }
+
+    /// Writes all buffered messages to the terminal, with `errors_only` just
+    /// the red and yellow messages on stderr and the JSON lines are written.
+    pub(crate) fn emit(&self, errors_only: bool) -> Result<()> {
+        for line in &self.lines {
+            match line {
+                Line::Red(highlight, msg) => write_red(highlight, msg)?,
+                Line::Yellow(highlight, msg) => write_yellow(highlight, msg)?,
+                Line::Json(line) | Line::Github(line) => progress::suspend(|| {
+                    let mut stdout = std::io::stdout().lock();
+                    writeln!(stdout, "{line}")?;
+                    stdout.flush()
+                })?,
+                _ if errors_only => {}
+                Line::Green(highlight, msg) => write_green(highlight, msg)?,
+                Line::Cyan(highlight, msg) => write_cyan(highlight, msg)?,
+                Line::Print(text) => progress::suspend(|| {
+                    let mut stdout = std::io::stdout().lock();
+                    write!(stdout, "{text}")?;
+                    stdout.flush()
+                })?,
+                Line::Diff(diff) => write_diff(diff)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cargo_sort_fix::{Config, Error, check_manifest};
+
+    use super::{Status, check_summary, github_annotations, github_error, json_error, json_report, porcelain_line};
+
+    #[test]
+    fn summary() {
+        assert_eq!(check_summary(1, &[(0, "unsorted")]), "1 manifest");
+        assert_eq!(
+            check_summary(183, &[(3, "unsorted"), (0, "invalid"), (1, "unformatted")]),
+            "183 manifests: 3 unsorted, 1 unformatted"
+        );
+    }
+
+    #[test]
+    fn porcelain_format() {
+        let path = "crates/foo/Cargo.toml";
+        assert_eq!(porcelain_line(Status::Ok, path), "ok\tcrates/foo/Cargo.toml");
+        assert_eq!(porcelain_line(Status::Unsorted, path), "unsorted\tcrates/foo/Cargo.toml");
+        assert_eq!(porcelain_line(Status::Unformatted, path), "unformatted\tcrates/foo/Cargo.toml");
+        assert_eq!(porcelain_line(Status::Fixed, path), "fixed\tcrates/foo/Cargo.toml");
+        assert_eq!(porcelain_line(Status::Error, path), "error\tcrates/foo/Cargo.toml");
+    }
+
+    #[test]
+    fn github_commands() {
+        let config = Config {
+            table_order: vec![],
+            check_format: true,
+            ..Config::default()
+        };
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\ntokio = \"1\"\nserde = {version=\"1\"}\n";
+        let report = check_manifest(input, &config).unwrap();
+        assert_eq!(
+            github_annotations("crates/a/Cargo.toml", Status::Unsorted, &report, input),
+            [
+                "::error file=crates/a/Cargo.toml,line=6::`serde` of `[dependencies]` is out of order, it belongs before `tokio`",
+                "::warning file=crates/a/Cargo.toml,line=5::Cargo.toml is not formatted",
+            ]
+        );
+        assert!(github_annotations("Cargo.toml", Status::Fixed, &report, input).is_empty());
+        // formatting is annotated with `check_format` only
+        let unchecked = Config {
+            check_format: false,
+            ..config.clone()
+        };
+        let report = check_manifest(input, &unchecked).unwrap();
+        assert_eq!(github_annotations("Cargo.toml", Status::Unsorted, &report, input).len(), 1);
+
+        // an array moved without a key, and the first line with the other ending
+        let input = "[workspace]\r\nmembers = [\"b\", \"a\"]\r\n\r\n[dependencies]\na = \"1\"\r\n";
+        let report = check_manifest(input, &config).unwrap();
+        assert_eq!(
+            github_annotations("a,b:c/Cargo.toml", Status::Unsorted, &report, input),
+            [
+                "::error file=a%2Cb%3Ac/Cargo.toml::`workspace.members` is not sorted",
+                "::warning file=a%2Cb%3Ac/Cargo.toml,line=4::Cargo.toml does not use CRLF line endings on 1 line",
+            ]
+        );
+        assert_eq!(
+            github_error("Cargo.toml", &Error::Config("100% broken\nat line 1".to_owned())),
+            "::error file=Cargo.toml::100%25 broken%0Aat line 1"
+        );
+        let input = "[package]\nname = \"a\"\n[dependencies\n";
+        let error = check_manifest(input, &Config::default())
+            .unwrap_err()
+            .with_path("/abs/crates/a/Cargo.toml");
+        let command = github_error("crates/a/Cargo.toml", &error);
+        assert_eq!(
+            command,
+            "::error file=crates/a/Cargo.toml,line=3::invalid table header%0Aexpected `.`, `]`"
+        );
+        let input = "[package]\nname = \"a\"\nname = \"b\"\n";
+        let error = check_manifest(input, &Config::default()).unwrap_err();
+        assert_eq!(
+            github_error("Cargo.toml", &error),
+            "::error file=Cargo.toml,line=3::`name` of `[package]` on line 3 is already defined on line 2"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\ntokio = \"1\"\nserde = \"1\"\n";
+        let report = check_manifest(input, &Config::default()).unwrap();
+        assert_eq!(
+            json_report("crates/a/Cargo.toml", Status::Unsorted, &report),
+            r#"{"path":"crates/a/Cargo.toml","status":"unsorted","sorted":false,"formatted":true,"newlines":true,"unsorted_tables":["dependencies"],"findings":[{"table":"dependencies","key":"serde","expected_before":"tokio"}]}"#
+        );
+        let report = check_manifest(&report.output, &Config::default()).unwrap();
+        assert_eq!(
+            json_report("a\\Cargo.toml", Status::Ok, &report),
+            r#"{"path":"a\\Cargo.toml","status":"ok","sorted":true,"formatted":true,"newlines":true,"unsorted_tables":[],"findings":[]}"#
+        );
+        assert_eq!(
+            json_error("Cargo.toml", "line \"1\"\n\tat\u{1}"),
+            r#"{"path":"Cargo.toml","status":"error","error":"line \"1\"\n\tat\u0001"}"#
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::Mutex,
+};
+
+/// The progress line currently drawn on stderr, if any.
+///
+/// It has to be erased before anything else is written to the terminal and is
+/// drawn again afterwards so it always stays the last line.
+static CURRENT: Mutex<Option<String>> = Mutex::new(None);
+
+/// A `[37/300] crates/foo` style counter drawn on stderr while manifests are
+/// being processed.
+///
+/// The counter is only drawn when stderr is a terminal so redirected logs never
+/// contain it.
+#[derive(Debug)]
+pub(crate) struct Progress {
+    total: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    /// Creates a counter for `total` manifests, `enabled` is further restricted
+    /// to a terminal stderr and more than one manifest.
+    pub(crate) fn new(total: usize, enabled: bool) -> Self {
+        Self {
+            total,
+            enabled: enabled && total > 1 && std::io::stderr().is_terminal(),
+        }
+    }
+
+    /// Draws the counter for the manifest at `idx` (zero based).
+    pub(crate) fn tick(&self, idx: usize, label: &str) {
+        if !self.enabled {
+            return;
+        }
+        let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+        erase(current.is_some());
+        let line = progress_line(idx + 1, self.total, label);
+        draw(&line);
+        *current = Some(line);
+    }
+
+    /// Erases the counter once all manifests have been processed.
+    pub(crate) fn finish(&self) {
+        let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+        erase(current.take().is_some());
+    }
+}
+
+/// Runs `f` with the progress line erased, drawing it again afterwards.
+///
+/// All terminal output has to go through this so the counter never ends up in
+/// the middle of a message.
+pub(crate) fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    erase(current.is_some());
+    let res = f();
+    if let Some(line) = current.as_deref() {
+        draw(line);
+    }
+    res
+}
+
+fn erase(visible: bool) {
+    if visible {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
+
+fn draw(line: &str) {
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(stderr, "{line}");
+    let _ = stderr.flush();
+}
+
+fn progress_line(current: usize, total: usize, label: &str) -> String {
+    format!("[{current}/{total}] {label}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::progress_line;
+
+    #[test]
+    fn counter_format() {
+        assert_eq!(progress_line(37, 300, "crates/foo"), "[37/300] crates/foo");
+    }
+}
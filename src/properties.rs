@@ -0,0 +1,466 @@
+//! Property tests: sorting and formatting never change what Cargo reads from
+//! a manifest, only the order of tables and keys and the whitespace.
+//!
+//! The manifests are generated by [`proptest`] strategies, which shrink a
+//! failing manifest to a small one, or are the example manifests with their
+//! keys shuffled. Both input and output are reduced to a [`Semantic`] tree
+//! that drops all decor and order, and must be equal.
+
+use std::{collections::BTreeMap, fmt::Write, fs};
+
+use proptest::{
+    prelude::*,
+    sample::{select, subsequence},
+    strategy::ValueTree,
+    test_runner::TestRunner,
+};
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+use crate::{
+    check::check_manifest,
+    fmt::{Config, NewlineStyle},
+    sort::{MATCHER, UnknownTables},
+};
+
+/// What Cargo sees of a toml document.
+#[derive(Debug, PartialEq)]
+enum Semantic {
+    Table(BTreeMap<String, Semantic>),
+    Array(Vec<Semantic>),
+    Scalar(String),
+}
+
+impl Semantic {
+    fn of(input: &str) -> Self {
+        let doc = input.parse::<DocumentMut>().unwrap_or_else(|err| panic!("{err}\n{input}"));
+        Self::table(doc.as_table(), "")
+    }
+
+    fn table(table: &dyn TableLike, path: &str) -> Self {
+        let entries = table.iter().filter(|(_, item)| !item.is_none());
+        Semantic::Table(
+            entries
+                .map(|(key, item)| {
+                    let path = if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") };
+                    (key.to_owned(), Self::item(item, &path))
+                })
+                .collect(),
+        )
+    }
+
+    fn item(item: &Item, path: &str) -> Self {
+        if let Some(table) = item.as_table_like() {
+            return Self::table(table, path);
+        }
+        match item {
+            Item::ArrayOfTables(tables) => Semantic::Array(tables.iter().map(|table| Self::table(table, path)).collect()),
+            Item::Value(value) => Self::value(value, path),
+            _ => unreachable!("tables are handled above"),
+        }
+    }
+
+    fn value(value: &Value, path: &str) -> Self {
+        match value {
+            Value::InlineTable(table) => Self::table(table, path),
+            Value::Array(array) => {
+                let mut items: Vec<_> = array.iter().map(|v| Self::value(v, path)).collect();
+                // the sorted arrays are sets as far as Cargo is concerned
+                if MATCHER.heading_key.iter().any(|(heading, key)| path == format!("{heading}.{key}")) {
+                    items.sort_by_key(|item| format!("{item:?}"));
+                }
+                Semantic::Array(items)
+            }
+            Value::String(s) => Semantic::Scalar(format!("{:?}", s.value())),
+            Value::Integer(i) => Semantic::Scalar(i.value().to_string()),
+            Value::Float(f) => Semantic::Scalar(f.value().to_string()),
+            Value::Boolean(b) => Semantic::Scalar(b.value().to_string()),
+            Value::Datetime(d) => Semantic::Scalar(d.value().to_string()),
+        }
+    }
+}
+
+const NAMES: &[&str] = &[
+    "anyhow",
+    "bytes",
+    "clap",
+    "futures",
+    "glob",
+    "libc",
+    "log",
+    "nix",
+    "rand",
+    "serde",
+    "serde_json",
+    "tokio",
+    "toml_edit",
+    "tun",
+];
+
+const TABLES: &[&str] = &[
+    "package",
+    "workspace",
+    "workspace.dependencies",
+    "features",
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "target.'cfg(unix)'.dependencies",
+    "lints.rust",
+    "[bin]",
+    "[example]",
+    "workspace.package",
+    "profile.release.package.tun",
+    "workspace.metadata.playground",
+];
+
+/// `true` once in `n` cases on average.
+fn one_in(n: u32) -> prop::bool::Weighted {
+    prop::bool::weighted(1.0 / f64::from(n))
+}
+
+prop_compose! {
+    /// Random but valid manifests, with the constructs that sorting and
+    /// formatting have got wrong before.
+    fn manifest()(
+        cargo_features in one_in(4),
+        tables in subsequence(TABLES, 1..=TABLES.len())
+            .prop_shuffle()
+            .prop_flat_map(|tables| tables.into_iter().map(table).collect::<Vec<_>>()),
+        end in one_in(5),
+        crlf in one_in(4),
+    ) -> String {
+        let mut out = String::new();
+        if cargo_features {
+            out.push_str("cargo-features = [\"edition2024\"]\n\n");
+        }
+        out.extend(tables);
+        if end {
+            out.push_str("# the end\n");
+        }
+        if crlf { out.replace('\n', "\r\n") } else { out }
+    }
+}
+
+fn table(table: &'static str) -> BoxedStrategy<String> {
+    match table {
+        "package" => package().boxed(),
+        "workspace" => workspace().boxed(),
+        "features" => features().boxed(),
+        "lints.rust" => lints().boxed(),
+        "workspace.package" | "profile.release.package.tun" | "workspace.metadata.playground" => implicit_parents(table).boxed(),
+        "[bin]" | "[example]" => array_of_tables(table).boxed(),
+        deps => dependencies(deps).boxed(),
+    }
+}
+
+fn header(header: &str) -> impl Strategy<Value = String> + use<> {
+    let header = header.to_owned();
+    comment().prop_map(move |comment| format!("{comment}[{header}]\n"))
+}
+
+prop_compose! {
+    /// Maybe a full line comment.
+    fn comment()(
+        comment in prop::option::weighted(0.25, select(vec!["# a comment", "#no space", "# [dependencies]", "# key = \"value\""])),
+    ) -> String {
+        comment.map(|comment| format!("{comment}\n")).unwrap_or_default()
+    }
+}
+
+prop_compose! {
+    /// Maybe a trailing comment, then the end of the line and maybe a blank
+    /// one.
+    fn end_line()(trailing in one_in(5), blank in one_in(4)) -> String {
+        let trailing = if trailing { " # trailing" } else { "" };
+        let blank = if blank { "\n" } else { "" };
+        format!("{trailing}\n{blank}")
+    }
+}
+
+/// A `key = value` line, maybe with comments.
+fn entry(key: impl Into<String>, value: impl Strategy<Value = String>) -> impl Strategy<Value = String> {
+    let key = key.into();
+    let eq = select(vec![" = ", "=", "  =  "]);
+    (comment(), eq, value, end_line()).prop_map(move |(comment, eq, value, end)| format!("{comment}{key}{eq}{value}{end}"))
+}
+
+prop_compose! {
+    fn string()(
+        s in select(vec!["1", "0.2.3", "a b", "with \\\"quote\\\"", "tab\\t", "ünï", "#not a comment"]),
+        literal in one_in(4),
+    ) -> String {
+        if literal && !s.contains('\\') { format!("'{s}'") } else { format!("\"{s}\"") }
+    }
+}
+
+prop_compose! {
+    fn array(choices: &'static [&'static str])(multiline in one_in(3))(array in array_on(choices, multiline)) -> String {
+        array
+    }
+}
+
+prop_compose! {
+    /// A subset of `choices` as an array, `multiline` puts each item and its
+    /// comment on a line of its own.
+    fn array_on(choices: &'static [&'static str], multiline: bool)(
+        items in subsequence(choices, 0..=choices.len()).prop_shuffle(),
+        comments in prop::collection::vec(one_in(4), choices.len()),
+        sep in select(vec![", ", ","]),
+    ) -> String {
+        let items = items.iter().map(|item| format!("\"{item}\""));
+        if multiline {
+            let mut array = "[\n".to_owned();
+            for (item, comment) in items.zip(comments) {
+                let comment = if comment { " # why" } else { "" };
+                writeln!(array, "    {item},{comment}").unwrap();
+            }
+            array + "]"
+        } else {
+            format!("[{}]", items.collect::<Vec<_>>().join(sep))
+        }
+    }
+}
+
+fn package() -> impl Strategy<Value = String> {
+    let keys = Just(vec!["name", "version", "edition", "authors", "metadata"]).prop_shuffle();
+    let entries = keys.prop_flat_map(|keys| {
+        keys.into_iter()
+            .map(|key| match key {
+                "authors" => entry(key, array(&["a <a@b.c>", "d"])).boxed(),
+                "edition" => entry(key, Just("\"2024\"".to_owned())).boxed(),
+                "metadata" => (comment(), end_line())
+                    .prop_map(|(comment, end)| format!("{comment}metadata.docs.rs.all-features = true{end}"))
+                    .boxed(),
+                _ => entry(key, string()).boxed(),
+            })
+            .collect::<Vec<_>>()
+    });
+    (header("package"), entries).prop_map(|(header, entries)| header + &entries.concat())
+}
+
+prop_compose! {
+    fn workspace()(
+        header in header("workspace"),
+        members in prop::option::weighted(2.0 / 3.0, entry("members", array(&["b", "a", "crates/*", "c/d"]))),
+        exclude in prop::option::weighted(2.0 / 3.0, entry("exclude", array(&["b", "a", "crates/*", "c/d"]))),
+    ) -> String {
+        let (members, exclude) = (members.unwrap_or_default(), exclude.unwrap_or_default());
+        format!("{header}{members}{exclude}resolver = \"2\"\n\n")
+    }
+}
+
+fn features() -> impl Strategy<Value = String> {
+    let names = subsequence(vec!["default", "std", "async", "full"], 1..=4).prop_shuffle();
+    let entries = names.prop_flat_map(|names| {
+        let value = || array(&["std", "dep:tokio", "serde?/derive", "tokio/rt"]);
+        names.into_iter().map(|name| entry(name, value())).collect::<Vec<_>>()
+    });
+    (header("features"), entries).prop_map(|(header, entries)| header + &entries.concat())
+}
+
+fn lints() -> impl Strategy<Value = String> {
+    header("lints.rust").prop_map(|header| header + "unsafe_code = \"forbid\"\nmissing_docs = { level = \"warn\", priority = -1 }\n\n")
+}
+
+/// A nested header whose parents may have no header of their own.
+fn implicit_parents(table: &'static str) -> impl Strategy<Value = String> {
+    let parent = match table.rsplit_once('.') {
+        // the top level tables have a generator of their own
+        Some((parent, _)) if parent.contains('.') => prop::option::weighted(1.0 / 3.0, header(parent)).boxed(),
+        _ => Just(None).boxed(),
+    };
+    (parent, header(table)).prop_map(|(parent, header)| {
+        let parent = parent.map(|parent| parent + "\n").unwrap_or_default();
+        format!("{parent}{header}opt-level = 3\n\n")
+    })
+}
+
+prop_compose! {
+    fn array_of_tables(table: &'static str)(count in 1..=3_usize)(
+        tables in NAMES[..count].iter().map(|&name| (header(table), Just(name), any::<bool>())).collect::<Vec<_>>(),
+    ) -> String {
+        let mut out = String::new();
+        for (header, name, path) in tables {
+            writeln!(out, "{header}name = \"{name}\"").unwrap();
+            if path {
+                writeln!(out, "path = \"src/{name}.rs\"").unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn dependencies(table: &'static str) -> impl Strategy<Value = String> {
+    let names = Just(NAMES.to_vec()).prop_shuffle();
+    let entries = (names, 0..NAMES.len(), 0..3_usize).prop_flat_map(move |(names, keys, tables)| {
+        let (keys, names) = names.split_at(keys);
+        let keys = keys.iter().map(|&name| dependency(name)).collect::<Vec<_>>();
+        let tables = names
+            .iter()
+            .take(tables)
+            .map(|&name| dependency_table(table, name))
+            .collect::<Vec<_>>();
+        (keys, tables)
+    });
+    (header(table), entries).prop_map(|(header, (keys, tables))| header + &keys.concat() + &tables.concat())
+}
+
+/// A dependency written as a key of its table.
+fn dependency(name: &'static str) -> impl Strategy<Value = String> {
+    let value = prop_oneof![
+        string(),
+        array_on(&["derive", "std"], false).prop_map(|features| format!("{{ version = \"1\", features = {features} }}")),
+        Just(format!("{{path=\"../{name}\",optional=true}}")),
+        Just(format!("{{ git = \"https://example.com/{name}\", branch = \"main\" }}")),
+    ];
+    let dotted = (comment(), prop::option::of(end_line()), end_line()).prop_map(move |(comment, optional, end)| {
+        let optional = optional.map(|end| format!("{end}{name}.optional = true")).unwrap_or_default();
+        format!("{comment}{name}.workspace = true{optional}{end}")
+    });
+    prop_oneof![4 => entry(format!("\"{name}\""), value), 1 => dotted]
+}
+
+prop_compose! {
+    /// A dependency with a header of its own.
+    fn dependency_table(table: &'static str, name: &'static str)(
+        header in header(&format!("{table}.{name}")),
+        features in prop::option::of(array(&["b", "a"])),
+    ) -> String {
+        let features = features.map(|features| format!("features = {features}\n")).unwrap_or_default();
+        format!("{header}version = \"1\"\n{features}\n")
+    }
+}
+
+prop_compose! {
+    /// A config among the ones that change the sorting.
+    fn config()(
+        grouped in any::<bool>(),
+        key_value_newlines in any::<bool>(),
+        keep_table_order in one_in(3),
+        unknown_tables in select(vec![UnknownTables::Position, UnknownTables::Keep, UnknownTables::Alphabetize, UnknownTables::End]),
+        newline_style in select(vec![NewlineStyle::Auto, NewlineStyle::Lf, NewlineStyle::Crlf]),
+    ) -> Config {
+        Config {
+            grouped,
+            key_value_newlines: grouped || key_value_newlines,
+            table_order: if keep_table_order { vec![] } else { Config::default().table_order },
+            unknown_tables,
+            newline_style,
+            ..Config::default()
+        }
+    }
+}
+
+#[test]
+fn semantic_ignores_only_order_and_decor() {
+    let input = "[package]\nname = \"a\"\nversion = \"1\"\n\n[dependencies]\nb = { path = \"b\" }\nc.workspace = true\n";
+    let reordered =
+        "[dependencies]\nc = { workspace = true } # moved\n[dependencies.b]\npath = 'b'\n\n[package]\nversion=\"1\"\nname=\"a\"\n";
+    assert_eq!(Semantic::of(input), Semantic::of(reordered));
+    assert_ne!(Semantic::of(input), Semantic::of(&input.replace("path", "version")));
+    assert_ne!(Semantic::of(input), Semantic::of(&input.replace("c.workspace = true\n", "")));
+
+    let workspace = "[workspace]\nmembers = [\"a\", \"b\"]\nexclude = [\"c\"]\n[features]\nstd = [\"a\", \"b\"]\n";
+    assert_eq!(
+        Semantic::of(workspace),
+        Semantic::of(&workspace.replace("[\"a\", \"b\"]\ne", "[\"b\", \"a\"]\ne"))
+    );
+    assert_ne!(
+        Semantic::of(workspace),
+        Semantic::of(&workspace.replace("std = [\"a\", \"b\"]", "std = [\"b\", \"a\"]"))
+    );
+}
+
+/// The dotted paths of the tables written with a header of their own, the
+/// implicit parents of nested headers are left out.
+fn headers(input: &str) -> Vec<String> {
+    fn visit(table: &Table, path: &str, found: &mut Vec<String>) {
+        for (key, item) in table.iter() {
+            let path = if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") };
+            let tables: Vec<&Table> = match item {
+                Item::Table(table) => vec![table],
+                Item::ArrayOfTables(tables) => tables.iter().collect(),
+                _ => vec![],
+            };
+            for table in tables {
+                if !table.is_implicit() && !table.is_dotted() {
+                    found.push(path.clone());
+                }
+                visit(table, &path, found);
+            }
+        }
+    }
+    let doc = input.parse::<DocumentMut>().unwrap_or_else(|err| panic!("{err}\n{input}"));
+    let mut found = vec![];
+    visit(doc.as_table(), "", &mut found);
+    found.sort();
+    found
+}
+
+/// Returns `true` if the output differs from `input` at all.
+#[track_caller]
+fn assert_preserved(input: &str, config: &Config) -> bool {
+    let output = check_manifest(input, config).unwrap().output;
+    assert!(
+        Semantic::of(input) == Semantic::of(&output),
+        "changed the manifest\n{input}\n---\n{output}\n{config:?}"
+    );
+    assert!(
+        headers(input) == headers(&output),
+        "changed the headers\n{input}\n---\n{output}\n{config:?}"
+    );
+    output != input
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(500))]
+
+    #[test]
+    fn generated_manifests_keep_their_meaning(input in manifest(), config in config()) {
+        assert_preserved(&input, &config);
+    }
+}
+
+#[test]
+fn generated_manifests_are_mostly_unsorted() {
+    // most manifests are not sorted, or the property says little
+    let mut runner = TestRunner::deterministic();
+    let cases = 500;
+    let changed = (0..cases).filter(|_| {
+        let (input, config) = (manifest(), config()).new_tree(&mut runner).unwrap().current();
+        assert_preserved(&input, &config)
+    });
+    assert!(changed.count() * 10 > cases * 9);
+}
+
+/// Shuffles the keys of every table the way a careless edit would.
+fn shuffle_keys(table: &mut Table, seed: u64) {
+    // `sort_values_by` also walks the dotted keys, a rank per key name
+    // covers those
+    let rank = |key: &str| {
+        key.bytes()
+            .fold(seed, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3))
+    };
+    table.sort_values_by(|a, _, b, _| rank(a.get()).cmp(&rank(b.get())));
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(table) => shuffle_keys(table, seed),
+            Item::ArrayOfTables(tables) => tables.iter_mut().for_each(|table| shuffle_keys(table, seed)),
+            _ => {}
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(20))]
+
+    #[test]
+    fn mutated_examples_keep_their_meaning(seed in any::<u64>(), config in config()) {
+        for entry in fs::read_dir("examp").unwrap() {
+            let mut doc = fs::read_to_string(entry.unwrap().path()).unwrap().parse::<DocumentMut>().unwrap();
+            shuffle_keys(doc.as_table_mut(), seed);
+            assert_preserved(&doc.to_string(), &config);
+        }
+    }
+}
@@ -1,11 +1,25 @@
-use std::{cmp::Ordering, collections::BTreeMap, iter::FromIterator};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter},
+    iter::FromIterator,
+    ops::Range,
+};
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{Array, ArrayOfTables, Decor, DocumentMut, ImDocument, Item, Key, RawString, Table, TableLike, TomlError, Value};
 
-use toml_edit::{Array, Decor, DocumentMut, Item, RawString, Table, Value};
+use crate::fmt::TableOverrides;
 
 /// Leading string for combining keys such as
 /// `[target.'cfg(target_os="linux")'.dependencies]` in Cargo.toml files.
 const TARGET: &str = "target";
 
+/// The table of a Cargo manifest whose arrays `feature_values` sorts.
+const FEATURES: &str = "features";
+/// The entry of a table order that stands for every table it does not list.
+const ORDER_WILDCARD: &str = "*";
+
 /// Stores the paths of target tables in a BTreeMap, the data structure looks like:
 /// ```plain
 /// target_tables: {
@@ -23,28 +37,369 @@ const TARGET: &str = "target";
 /// ```
 type TargetTablePaths = BTreeMap<String, Vec<Vec<String>>>;
 
-/// Each `Matcher` field when matched to a heading or key token
-/// will be matched with `.contains()`.
-#[derive(Debug)]
-pub(crate) struct Matcher<'a> {
+/// Selects the tables and arrays that are sorted.
+///
+/// The fields name whole tables, a `heading` matches the top level table of
+/// that name and `[target.<cfg>.<heading>]`, never a table of the same name
+/// nested elsewhere such as `[package.metadata.tool.dependencies]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Matcher<'a> {
     /// Toml headings with braces `[heading]`.
     pub heading: &'a [&'a str],
-    /// Toml heading with braces `[heading]` and the key
-    /// of the array to sort.
+    /// Toml heading with braces `[heading]` and the dotted key of the array
+    /// or table below it to sort, a `*` segment matches every table there.
     pub heading_key: &'a [(&'a str, &'a str)],
+    /// Per table settings keyed by dotted path, these can turn sorting on or
+    /// off for a table and override `group`.
+    pub tables: Option<&'a TableOverrides>,
+    /// Where the tables missing from the ordering go.
+    pub unknown_tables: UnknownTables,
+    /// Full line comments start a group of keys that are sorted on their own,
+    /// the comment stays at the start of the group.
+    pub group_comments: bool,
+    /// The arrays of strings in `[features]` are sorted, the feature names
+    /// are only sorted when `heading` lists the table.
+    pub feature_values: bool,
+    /// The arrays of tables whose entries are sorted by their `name`.
+    pub array_of_tables: &'a [String],
+}
+
+/// Where tables missing from `table_order` are placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownTables {
+    /// A sorted table such as `[lints]` keeps its position in the input while
+    /// the listed tables take the positions from the top, one for each of
+    /// their subtables too, the other tables go after the listed ones in
+    /// input order. This is where they always went, sorting the output again
+    /// can move the sorted ones once more.
+    #[default]
+    Position,
+    /// Each table stays after the table it followed in the input.
+    Keep,
+    /// After the listed tables, sorted by name.
+    Alphabetize,
+    /// After the listed tables, in input order.
+    End,
+}
+
+impl UnknownTables {
+    /// The config file spelling of every policy.
+    pub const NAMES: &[&str] = &["position", "keep", "alphabetize", "end"];
+
+    /// Returns the config file spelling of the policy.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UnknownTables::Position => "position",
+            UnknownTables::Keep => "keep",
+            UnknownTables::Alphabetize => "alphabetize",
+            UnknownTables::End => "end",
+        }
+    }
+
+    /// Adds the `unlisted` tables of `input`, in input order, to `ordering`,
+    /// except the ones `sorted` is `true` for with [`UnknownTables::Position`].
+    ///
+    /// A `*` entry of `ordering` is replaced by all of them, in input order
+    /// or sorted by name with [`UnknownTables::Alphabetize`].
+    fn place(self, ordering: &mut Vec<String>, mut unlisted: Vec<String>, input: &[String], sorted: impl Fn(&str) -> bool) {
+        if let Some(slot) = ordering.iter().position(|o| o == ORDER_WILDCARD) {
+            if self == UnknownTables::Alphabetize {
+                unlisted.sort();
+            }
+            ordering.splice(slot..=slot, unlisted);
+            return;
+        }
+        match self {
+            // the sorted ones are left out, they keep their positions and
+            // `sort_by_ordering` gives the listed ones the positions from the top
+            UnknownTables::Position => ordering.extend(unlisted.into_iter().filter(|name| !sorted(name))),
+            UnknownTables::End => ordering.extend(unlisted),
+            UnknownTables::Alphabetize => {
+                unlisted.sort();
+                ordering.extend(unlisted);
+            }
+            UnknownTables::Keep => {
+                let mut prev: Option<&String> = None;
+                for key in input {
+                    if unlisted.contains(key) {
+                        let pos = prev.and_then(|p| ordering.iter().position(|o| o == p)).map_or(0, |pos| pos + 1);
+                        ordering.insert(pos, key.clone());
+                    }
+                    if ordering.contains(key) {
+                        prev = Some(key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The error for input that is not valid toml.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    error: Box<TomlError>,
+    line: Option<usize>,
+    duplicate: Option<Duplicate>,
+}
+
+/// A table header repeating a table or key defined before it, or a key
+/// repeated in its table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Duplicate {
+    path: String,
+    /// The dotted key repeated in the table at `path`, `None` for a header.
+    key: Option<String>,
+    first_line: usize,
+    line: usize,
+}
+
+impl ParseError {
+    pub(crate) fn new(input: &str, error: TomlError) -> Self {
+        let duplicate = if error.message().contains("duplicate key") {
+            find_duplicate(input, &error).or_else(|| find_duplicate_key(input, &error))
+        } else {
+            None
+        };
+        let line = error.span().map(|span| input[..span.start].matches('\n').count() + 1);
+        Self {
+            error: Box::new(error),
+            line,
+            duplicate,
+        }
+    }
+
+    /// Returns the message of the parser, without the location.
+    pub fn message(&self) -> &str {
+        self.error.message()
+    }
+
+    /// Returns the byte range of the input the error points at.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.error.span()
+    }
+
+    /// Returns the line of the input the error points at, counted from 1.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// Returns the dotted path of a table header that defines a table or key
+    /// a second time, with the line of the first definition and that of the
+    /// header, counted from 1.
+    pub fn duplicate(&self) -> Option<(&str, usize, usize)> {
+        self.duplicate
+            .as_ref()
+            .filter(|dup| dup.key.is_none())
+            .map(|dup| (dup.path.as_str(), dup.first_line, dup.line))
+    }
+
+    /// Returns the dotted path of a table and the key that is set in it a
+    /// second time, with the line of the first definition and that of the
+    /// second one, counted from 1. The path is empty for the root table.
+    pub fn duplicate_key(&self) -> Option<(&str, &str, usize, usize)> {
+        let dup = self.duplicate.as_ref()?;
+        Some((dup.path.as_str(), dup.key.as_deref()?, dup.first_line, dup.line))
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.duplicate {
+            Some(Duplicate {
+                path,
+                key: None,
+                first_line,
+                line,
+            }) => writeln!(f, "`[{path}]` on line {line} is already defined on line {first_line}")?,
+            Some(Duplicate {
+                path,
+                key: Some(key),
+                first_line,
+                line,
+            }) => {
+                let table = if path.is_empty() {
+                    String::new()
+                } else {
+                    format!(" of `[{path}]`")
+                };
+                writeln!(f, "`{key}`{table} on line {line} is already defined on line {first_line}")?;
+            }
+            None => {}
+        }
+        self.error.fmt(f)
+    }
+}
+
+/// Parses `input`, a table header that defines a table again is reported
+/// with the lines of both definitions.
+pub(crate) fn parse(input: &str) -> Result<DocumentMut, ParseError> {
+    input.parse::<DocumentMut>().map_err(|err| ParseError::new(input, err))
+}
+
+/// Joins `keys` into a dotted path that is valid toml, the keys that are not
+/// bare are quoted, e.g. `target."cfg(unix)".dependencies`.
+pub(crate) fn key_path<'k>(keys: impl IntoIterator<Item = &'k str>) -> String {
+    let keys = keys.into_iter().map(|key| Key::new(key).display_repr().into_owned());
+    keys.collect::<Vec<_>>().join(".")
+}
+
+/// Finds where the table or key repeated by the header `error` points at was
+/// first defined.
+fn find_duplicate(input: &str, error: &TomlError) -> Option<Duplicate> {
+    let start = input[..error.span()?.start].rfind('\n').map_or(0, |idx| idx + 1);
+    let end = input[start..].find('\n').map_or(input.len(), |idx| start + idx);
+    let header = input[start..end].trim_start();
+    if !header.starts_with('[') || header.starts_with("[[") {
+        return None;
+    }
+    // the header parses on its own, which splits its dotted keys, and so does
+    // everything before it
+    let header = header.parse::<DocumentMut>().ok()?;
+    let before = ImDocument::parse(&input[..start]).ok()?;
+    let (mut keys, mut span) = (vec![], None);
+    let mut walk = header.as_table();
+    let mut table: &dyn TableLike = before.as_table();
+    while let Some((name, Item::Table(inner))) = walk.iter().next() {
+        keys.push(name);
+        walk = inner;
+        let (key, item) = table.get_key_value(name)?;
+        span = key.span();
+        match item {
+            Item::Table(next) => table = next,
+            Item::Value(Value::InlineTable(next)) => table = next,
+            _ => break,
+        }
+    }
+    let line = |offset: usize| input[..offset].matches('\n').count() + 1;
+    Some(Duplicate {
+        path: key_path(keys),
+        key: None,
+        first_line: line(span?.start),
+        line: line(start),
+    })
+}
+
+/// Finds where the key that `error` points at was first set in the table
+/// of the last header before it.
+fn find_duplicate_key(input: &str, error: &TomlError) -> Option<Duplicate> {
+    let at = error.span()?.start;
+    let start = input[..at].rfind('\n').map_or(0, |idx| idx + 1);
+    let (repr, _) = input[at..].split_once('=')?;
+    let keys = Key::parse(repr.trim()).ok()?;
+    // a key repeated inside an inline table is on the line we cut off here
+    let before = ImDocument::parse(&input[..start]).ok()?;
+
+    // the lines before the duplicate belong to the header with the highest position
+    fn last_header<'t>(table: &'t Table, path: &mut Vec<&'t str>, last: &mut (usize, Vec<&'t str>, &'t Table)) {
+        for (name, item) in table.iter() {
+            let tables: Vec<&Table> = match item {
+                Item::Table(table) => vec![table],
+                Item::ArrayOfTables(array) => array.iter().collect(),
+                _ => continue,
+            };
+            path.push(name);
+            for table in tables {
+                if let Some(position) = table.position().filter(|&p| p >= last.0 && !table.is_dotted()) {
+                    *last = (position, path.clone(), table);
+                }
+                last_header(table, path, last);
+            }
+            path.pop();
+        }
+    }
+    let mut last = (0, vec![], before.as_table());
+    last_header(before.as_table(), &mut vec![], &mut last);
+
+    let (_, path, header) = last;
+    let mut table: &dyn TableLike = header;
+    let (parents, name) = keys.split_at(keys.len() - 1);
+    for key in parents {
+        table = match table.get(key.get())? {
+            Item::Table(next) => next,
+            Item::Value(Value::InlineTable(next)) => next,
+            _ => return None,
+        };
+    }
+    let (first, _) = table.get_key_value(name[0].get())?;
+    let line = |offset: usize| input[..offset].matches('\n').count() + 1;
+    Some(Duplicate {
+        path: key_path(path),
+        key: Some(repr.trim().to_owned()),
+        first_line: line(first.span()?.start),
+        line: line(start),
+    })
+}
+
+impl Error for ParseError {}
+
+impl Matcher<'_> {
+    /// Whether the table at the dotted `path` is sorted, `default` is used when
+    /// no override exists.
+    fn sorts(&self, path: &str, default: bool) -> bool {
+        self.tables.and_then(|t| t.get(path)?.sort).unwrap_or(default)
+    }
+
+    /// Where the table at the dotted `path` is split into groups sorted on
+    /// their own, `default` is used for blank lines when no override exists.
+    fn groups(&self, path: &str, default: bool) -> Groups {
+        Groups {
+            blank_lines: self.tables.and_then(|t| t.get(path)?.grouped).unwrap_or(default),
+            comments: self.group_comments,
+        }
+    }
+}
+
+/// Where grouped sorting starts a new group of keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Groups {
+    /// At a blank line.
+    blank_lines: bool,
+    /// At a full line comment.
+    comments: bool,
+}
+
+impl Groups {
+    const NONE: Groups = Groups {
+        blank_lines: false,
+        comments: false,
+    };
+
+    /// Whether the keys are sorted in groups at all.
+    fn any(self) -> bool {
+        self.blank_lines || self.comments
+    }
 }
 
-pub(crate) const MATCHER: Matcher<'_> = Matcher {
-    heading: &["dependencies", "dev-dependencies", "build-dependencies"],
+/// The tables and arrays of a Cargo manifest that are sorted by default.
+pub const MATCHER: Matcher<'_> = Matcher {
+    heading: &["dependencies", "dev-dependencies", "build-dependencies", "lints"],
     heading_key: &[
         ("workspace", "members"),
         ("workspace", "exclude"),
         ("workspace", "dependencies"),
         ("workspace", "dev-dependencies"),
         ("workspace", "build-dependencies"),
+        ("workspace", "lints"),
+        ("workspace", "lints.*"),
+        ("workspace", "metadata"),
+        ("lints", "*"),
     ],
+    tables: None,
+    unknown_tables: UnknownTables::Position,
+    group_comments: false,
+    feature_values: false,
+    array_of_tables: &[],
 };
 
+/// Returns the tables and arrays of [`MATCHER`] as `sort_tables` entries.
+pub(crate) fn default_sort_tables() -> Vec<String> {
+    let heading = MATCHER.heading.iter().map(|&heading| heading.to_owned());
+    heading
+        .chain(MATCHER.heading_key.iter().map(|(heading, key)| format!("{heading}.{key}")))
+        .collect()
+}
+
 /// A state machine to track collection of headings.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Heading {
@@ -56,56 +411,64 @@ enum Heading {
     Complete(Vec<String>),
 }
 
-/// Returns a sorted toml `DocumentMut`.
-pub(crate) fn sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering: &[String]) -> DocumentMut {
+/// Returns a sorted toml `DocumentMut`, or the error if `input` is not valid
+/// toml.
+pub(crate) fn sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering: &[String]) -> Result<DocumentMut, ParseError> {
+    let mut toml = parse(input)?;
+    sort_document(&mut toml, matcher, group, ordering);
+    Ok(toml)
+}
+
+/// Sorts the already parsed `toml` in place, the way [`sort_toml`] sorts its
+/// input.
+pub(crate) fn sort_document(toml: &mut DocumentMut, matcher: Matcher<'_>, group: bool, ordering: &[String]) {
     let mut ordering = ordering.to_owned();
-    let mut toml = input.parse::<DocumentMut>().unwrap();
+    let file_header = take_file_header(toml.as_table_mut());
+    if matcher.feature_values
+        && let Some(features) = toml.get_mut(FEATURES).and_then(Item::as_table_like_mut)
+    {
+        features.iter_mut().filter_map(|(_, item)| item.as_array_mut()).for_each(sort_array);
+    }
     // This takes care of `[workspace] members = [...]`
     for (heading, key) in matcher.heading_key {
-        // Since this `&mut toml[&heading]` is like
-        // `SomeMap.entry(key).or_insert(Item::None)` we only want to do it if we
-        // know the heading is there already
-        if toml.as_table().contains_key(heading) {
-            if let Item::Table(table) = &mut toml[heading] {
-                if table.contains_key(key) {
-                    match &mut table[key] {
-                        Item::Value(Value::Array(arr)) => {
-                            sort_array(arr);
-                        }
-                        Item::Table(table) => {
-                            sort_table(table, group);
-                        }
-                        _ => {}
-                    }
+        for segments in heading_key_paths(toml.as_table(), heading, key) {
+            let path = segments.join(".");
+            if !matcher.sorts(&path, true) {
+                continue;
+            }
+            match item_at_mut(toml.as_table_mut(), &segments) {
+                Some(Item::Value(Value::Array(arr))) => {
+                    sort_array(arr);
+                }
+                Some(Item::Table(table)) => {
+                    sort_table(table, matcher.groups(&path, group));
                 }
+                _ => {}
             }
         }
     }
 
     let mut first_table = None;
     let mut heading_order: BTreeMap<_, Vec<Heading>> = BTreeMap::new();
+    let mut input_order = vec![];
+    let mut target_kinds = vec![];
     for (idx, (head, item)) in toml.as_table_mut().iter_mut().enumerate() {
+        if (item.is_table() || item.is_array_of_tables()) && head.get() != TARGET {
+            input_order.push(head.get().to_owned());
+        }
         let mut target_tables: TargetTablePaths = BTreeMap::new();
         let item_key = head.get();
-        if item_key == TARGET {
-            if let Some(table) = item.as_table() {
-                for &key in matcher.heading {
-                    let mut path = vec![item_key];
-                    let mut deps_tables = vec![];
-                    nested_tables_with_key(table, &mut path, key, &mut deps_tables);
-                    let deps_tables = deps_tables
-                        .iter()
-                        .map(|p| p.iter().map(|&s| s.to_owned()).collect::<Vec<_>>())
-                        .collect::<Vec<_>>();
-                    target_tables.entry(key.to_owned()).or_default().extend(deps_tables);
-                }
+        if item_key == TARGET
+            && let Some(table) = item.as_table()
+        {
+            for &key in matcher.heading {
+                target_tables_with_key(table, key, target_tables.entry(key.to_owned()).or_default());
             }
+            target_kinds = target_positions(table, &target_tables);
         }
 
-        if !matcher.heading.contains(&item_key) && target_tables.is_empty() {
-            if !ordering.contains(&head.to_owned()) && !ordering.is_empty() {
-                ordering.push(head.to_owned());
-            }
+        let sorted = matcher.sorts(item_key, matcher.heading.contains(&item_key) || !target_tables.is_empty());
+        if !sorted && target_tables.is_empty() {
             continue;
         }
         match item {
@@ -124,7 +487,9 @@ pub(crate) fn sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering
 
                 gather_headings(table, headings, 1);
                 headings.sort();
-                sort_table(table, group);
+                if sorted {
+                    sort_table(table, matcher.groups(item_key, group));
+                }
                 sort_nested_table(table, &target_tables);
             }
             Item::None => continue,
@@ -132,52 +497,578 @@ pub(crate) fn sort_toml(input: &str, matcher: Matcher<'_>, group: bool, ordering
         }
     }
 
+    // `[target.<cfg>.dependencies]` is ordered as part of `dependencies`, so
+    // each kind of target table counts as a table where its first one is
+    for (kind, position) in target_kinds {
+        if input_order.contains(&kind) {
+            continue;
+        }
+        let root = toml.as_table();
+        let after = input_order
+            .iter()
+            .position(|name| root_position(root, name).is_some_and(|p| p > position));
+        input_order.insert(after.unwrap_or(input_order.len()), kind);
+    }
+    let unlisted = if ordering.is_empty() {
+        vec![]
+    } else {
+        input_order.iter().filter(|name| !ordering.contains(name)).cloned().collect()
+    };
+
+    let sorted = |name: &str| matcher.sorts(name, matcher.heading.contains(&name));
+    matcher.unknown_tables.place(&mut ordering, unlisted, &input_order, sorted);
     if ordering.is_empty() {
-        sort_lexicographical(first_table, &heading_order, &mut toml);
+        sort_lexicographical(first_table, &heading_order, toml);
     } else {
-        sort_by_ordering(&ordering, &heading_order, &mut toml);
+        sort_by_ordering(&ordering, &heading_order, toml);
+    }
+    for name in matcher.array_of_tables {
+        if let Some(Item::ArrayOfTables(array)) = toml.get_mut(name) {
+            sort_array_of_tables(array);
+        }
+    }
+    if let Some(header) = file_header {
+        header.restore(toml.as_table_mut());
+    }
+}
+
+/// Sorts the entries of `array` by their `name`, the entries without one go
+/// last in input order.
+///
+/// Each entry keeps its keys and the comments above its header, and the
+/// entries take the positions the array had, one blank line apart.
+fn sort_array_of_tables(array: &mut ArrayOfTables) {
+    let name = |table: &Table| table.get("name").and_then(Item::as_str).map(str::to_owned);
+    let mut positions = array.iter().filter_map(Table::position).collect::<Vec<_>>();
+    positions.sort_unstable();
+    let mut entries = array.iter().cloned().collect::<Vec<_>>();
+    let blank_before = entries
+        .first()
+        .is_some_and(|first| starts_with_blank_line(first.decor().prefix().and_then(RawString::as_str).unwrap_or_default()));
+    // stable, so entries of the same name keep their order
+    entries.sort_by_cached_key(|table| {
+        let name = name(table);
+        (name.is_none(), name)
+    });
+
+    array.clear();
+    let slots = positions.len() == entries.len();
+    for (idx, mut entry) in entries.into_iter().enumerate() {
+        let mut rest = entry.decor().prefix().and_then(RawString::as_str).unwrap_or_default();
+        while starts_with_blank_line(rest) {
+            rest = &rest[rest.find('\n').expect("a blank line ends in a newline") + 1..];
+        }
+        let sep = if idx > 0 || blank_before { "\n" } else { "" };
+        let prefix = format!("{sep}{rest}");
+        entry.decor_mut().set_prefix(prefix);
+        if slots {
+            entry.set_position(positions[idx]);
+        }
+        array.push(entry);
+    }
+}
+
+/// The comments at the top of a manifest that belong to the file rather than
+/// to the table header below them.
+struct FileHeader {
+    comments: String,
+    /// Whether a blank line separated the comments from the first table.
+    blank_after: bool,
+}
+
+impl FileHeader {
+    /// Puts the comments back in front of the table that is printed first now.
+    fn restore(self, root: &mut Table) {
+        let Some(decor) = first_header_decor(root) else { return };
+        let rest = decor.prefix().and_then(RawString::as_str).unwrap_or_default();
+        let sep = if self.blank_after && !starts_with_blank_line(rest) {
+            "\n"
+        } else {
+            ""
+        };
+        decor.set_prefix(format!("{}{sep}{rest}", self.comments));
+    }
+}
+
+/// Takes the comments at the top of the file off the table printed first, so
+/// they stay at the top whichever table is sorted first.
+///
+/// These are the comments up to the last blank line before the first table
+/// header and a `#!` first line, the comments right above the header stay
+/// with that table.
+fn take_file_header(root: &mut Table) -> Option<FileHeader> {
+    // the keys of the root table are printed first and never move
+    if root
+        .iter()
+        .any(|(_, item)| item.is_value() || item.as_table().is_some_and(Table::is_dotted))
+    {
+        return None;
+    }
+    let decor = first_header_decor(root)?;
+    let prefix = decor.prefix().and_then(RawString::as_str)?.to_owned();
+    let mut end = 0;
+    let mut offset = 0;
+    let mut comment = false;
+    for line in prefix.split_inclusive('\n') {
+        if offset == 0 && line.starts_with("#!") {
+            end = line.len();
+        }
+        if line.trim().is_empty() && comment {
+            end = offset;
+        }
+        comment |= line.trim_start().starts_with('#');
+        offset += line.len();
+    }
+    if end == 0 {
+        return None;
+    }
+    let (comments, rest) = prefix.split_at(end);
+    let header = FileHeader {
+        comments: comments.to_owned(),
+        blank_after: starts_with_blank_line(rest),
+    };
+    decor.set_prefix(rest);
+    Some(header)
+}
+
+/// Whether the first line of `text` holds nothing but whitespace.
+fn starts_with_blank_line(text: &str) -> bool {
+    text.split_inclusive('\n')
+        .next()
+        .is_some_and(|line| line.ends_with('\n') && line.trim().is_empty())
+}
+
+/// Returns the decor of the table header printed first, the one with the
+/// lowest position.
+fn first_header_decor(root: &mut Table) -> Option<&mut Decor> {
+    fn lowest(table: &Table, min: &mut Option<usize>) {
+        for (_, item) in table.iter() {
+            let tables: Vec<&Table> = match item {
+                Item::Table(table) => vec![table],
+                Item::ArrayOfTables(array) => array.iter().collect(),
+                _ => continue,
+            };
+            for table in tables {
+                if is_visible(table)
+                    && let Some(position) = table.position()
+                {
+                    *min = Some(min.map_or(position, |min| min.min(position)));
+                }
+                lowest(table, min);
+            }
+        }
+    }
+    fn at(table: &mut Table, position: usize) -> Option<&mut Decor> {
+        for (_, item) in table.iter_mut() {
+            let tables: Vec<&mut Table> = match item {
+                Item::Table(table) => vec![table],
+                Item::ArrayOfTables(array) => array.iter_mut().collect(),
+                _ => continue,
+            };
+            for table in tables {
+                if is_visible(table) && table.position() == Some(position) {
+                    return Some(table.decor_mut());
+                }
+                if let Some(decor) = at(table, position) {
+                    return Some(decor);
+                }
+            }
+        }
+        None
+    }
+    let mut min = None;
+    lowest(root, &mut min);
+    at(root, min?)
+}
+
+/// Returns `true` if [`sort_toml`] would leave `toml` as it is, found by
+/// walking the sorted tables once instead of sorting a copy.
+///
+/// Layouts whose sorted form is not cheap to predict, `target` tables, an
+/// empty `ordering`, sorted tables missing from it with [`UnknownTables::Position`],
+/// arrays of tables sorted by name or headers nested deeper than
+/// `[dependencies.name]`, return `false`, in which case only sorting tells.
+pub(crate) fn is_sorted(toml: &DocumentMut, matcher: Matcher<'_>, group: bool, ordering: &[String]) -> bool {
+    let root = toml.as_table();
+    if ordering.is_empty() || root.contains_key(TARGET) || root.position().unwrap_or_default() != 0 {
+        return false;
+    }
+    if matcher
+        .array_of_tables
+        .iter()
+        .any(|name| root.get(name).is_some_and(Item::is_array_of_tables))
+    {
+        return false;
+    }
+    if matcher.feature_values
+        && let Some(features) = root.get(FEATURES).and_then(Item::as_table_like)
+        && !features.iter().filter_map(|(_, item)| item.as_array()).all(array_sorted)
+    {
+        return false;
+    }
+
+    // The tables sorted through `heading_key` and whether that puts their
+    // subtables in key order, grouped sorting keeps the order of sorted groups
+    let mut sorted_tables = vec![];
+    for (heading, key) in matcher.heading_key {
+        for segments in heading_key_paths(root, heading, key) {
+            let path = segments.join(".");
+            if !matcher.sorts(&path, true) {
+                continue;
+            }
+            let (parent, name) = segments.split_at(segments.len() - 1);
+            match table_at(root, parent).and_then(|t| t.get(&name[0])) {
+                Some(Item::Value(Value::Array(arr))) if !array_sorted(arr) => return false,
+                Some(Item::Table(table)) => {
+                    let groups = matcher.groups(&path, group);
+                    if !keys_sorted(table, groups) {
+                        return false;
+                    }
+                    sorted_tables.push((segments, !groups.any()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut input_order = vec![];
+    let mut unlisted = vec![];
+    let mut blocks = BTreeMap::new();
+    let mut nested_names = vec![];
+    for (key, item) in root.iter() {
+        if item.is_table() || item.is_array_of_tables() {
+            input_order.push(key.to_owned());
+            if !ordering.iter().any(|o| o == key) {
+                unlisted.push(key.to_owned());
+            }
+        }
+        if !matcher.sorts(key, matcher.heading.contains(&key)) {
+            continue;
+        }
+        if let Item::Table(table) = item {
+            if table.is_dotted() || !keys_sorted(table, matcher.groups(key, group)) {
+                return false;
+            }
+            let mut headers = vec![];
+            if is_visible(table) {
+                headers.push(vec![key.to_owned()]);
+            }
+            if !block_headers(table, &mut vec![key.to_owned()], &mut headers, &mut nested_names) {
+                return false;
+            }
+            // the order `sort_by_ordering` gives them
+            headers.sort();
+            headers.sort_by_key(|h| h.join("."));
+            blocks.insert(key, headers);
+        }
+    }
+
+    let sorted = |name: &str| matcher.sorts(name, matcher.heading.contains(&name));
+    if matcher.unknown_tables == UnknownTables::Position
+        && !ordering.iter().any(|o| o == ORDER_WILDCARD)
+        && unlisted.iter().any(|name| sorted(name))
+    {
+        return false;
+    }
+    let mut ordering = ordering.to_owned();
+    matcher.unknown_tables.place(&mut ordering, unlisted, &input_order, sorted);
+    let mut seen = ordering.clone();
+    seen.sort();
+    seen.dedup();
+    // a name shared by a nested table is positioned twice
+    if seen.len() != ordering.len()
+        || nested_names
+            .iter()
+            .any(|name| seen.binary_search_by(|o| o.as_str().cmp(name)).is_ok())
+    {
+        return false;
+    }
+
+    let mut expected = vec![];
+    for heading in &ordering {
+        if let Some(headers) = blocks.get(heading.as_str()) {
+            expected.extend(headers.iter().cloned());
+            continue;
+        }
+        let mut path = vec![heading.clone()];
+        match root.get(heading) {
+            Some(Item::Table(table)) => positioned_headers(table, &mut path, false, &sorted_tables, &mut expected),
+            Some(Item::ArrayOfTables(tables)) => {
+                for table in tables {
+                    positioned_headers(table, &mut path, false, &sorted_tables, &mut expected);
+                }
+            }
+            _ => {}
+        }
     }
 
-    toml
+    expected == printed_headers(toml)
 }
 
-fn nested_tables_with_key<'a>(table: &'a Table, path: &mut Vec<&'a str>, key_name: &str, result: &mut Vec<Vec<&'a str>>) {
+/// Whether `table` has a header when printed.
+fn is_visible(table: &Table) -> bool {
+    !table.is_dotted() && (!table.is_implicit() || !table.get_values().is_empty())
+}
+
+/// Whether sorting `table` keeps its keys in place, in the `groups` it is
+/// split into.
+fn keys_sorted(table: &Table, groups: Groups) -> bool {
+    if groups.any() {
+        // the whole group is sorted, tables included
+        let mut prev: Option<&str> = None;
+        for (key, item) in table.iter() {
+            let k = table.key(key).expect("the key exists");
+            if !starts_group(k, item, groups) && prev.is_some_and(|prev| prev >= key) {
+                return false;
+            }
+            prev = Some(key);
+        }
+        return true;
+    }
+    // subtables are printed by position so only the values have to be in order
+    let values = table
+        .iter()
+        .filter(|(_, item)| item.is_value() || item.as_table().is_some_and(Table::is_dotted))
+        .collect::<Vec<_>>();
+    values.windows(2).all(|w| w[0].0 < w[1].0)
+        && values
+            .iter()
+            .filter_map(|(_, item)| item.as_table())
+            .all(|t| keys_sorted(t, Groups::NONE))
+}
+
+/// Collects the headers of the tables below the sorted `table` at `path`,
+/// they are positioned by their dotted path.
+///
+/// Returns `false` for a header that keeps the position it had, one with
+/// only subtables or nested deeper.
+fn block_headers<'a>(table: &'a Table, path: &mut Vec<String>, headers: &mut Vec<Vec<String>>, names: &mut Vec<&'a str>) -> bool {
     for (key, item) in table.iter() {
-        path.push(key);
-        if let Item::Table(inner) = item {
-            if key == key_name && inner.position().is_some() {
-                result.push(path.clone());
+        match item {
+            Item::Table(sub) => {
+                names.push(key);
+                path.push(key.to_owned());
+                let positioned = if sub.is_dotted() {
+                    block_headers(sub, path, &mut vec![], names)
+                } else if path.len() > 2 || !(sub.is_empty() || sub.iter().any(|(_, item)| item.is_value())) {
+                    false
+                } else {
+                    if is_visible(sub) {
+                        headers.push(path.clone());
+                    }
+                    block_headers(sub, path, headers, names)
+                };
+                path.pop();
+                if !positioned {
+                    return false;
+                }
+            }
+            Item::ArrayOfTables(_) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Collects the headers of `table` at `path` and every table below it, in the
+/// order `walk_tables_set_position` positions them.
+///
+/// The children of the tables in `sorted_tables` that are sorted by key come
+/// in key order.
+fn positioned_headers(
+    table: &Table,
+    path: &mut Vec<String>,
+    sorted: bool,
+    sorted_tables: &[(Vec<String>, bool)],
+    headers: &mut Vec<Vec<String>>,
+) {
+    if is_visible(table) {
+        headers.push(path.clone());
+    }
+    let sorted = sorted || sorted_tables.iter().any(|(p, by_key)| *by_key && p == path);
+    let mut children = table.iter().collect::<Vec<_>>();
+    if sorted {
+        children.sort_by_key(|(key, _)| *key);
+    }
+    for (key, item) in children {
+        path.push(key.to_owned());
+        match item {
+            Item::Table(sub) => positioned_headers(sub, path, sorted && sub.is_dotted(), sorted_tables, headers),
+            Item::ArrayOfTables(tables) => {
+                for sub in tables {
+                    positioned_headers(sub, path, false, sorted_tables, headers);
+                }
             }
-            nested_tables_with_key(inner, path, key_name, result);
+            _ => {}
         }
         path.pop();
     }
 }
 
-fn sort_array(arr: &mut Array) {
-    let mut all_strings = true;
-    let trailing = arr.trailing().clone();
-    let trailing_comma = arr.trailing_comma();
+/// Returns the headers of `toml` in the order they are printed.
+fn printed_headers(toml: &DocumentMut) -> Vec<Vec<String>> {
+    fn visit(table: &Table, path: &mut Vec<String>, last: &mut usize, found: &mut Vec<(usize, Vec<String>, bool)>) {
+        if !table.is_dotted() {
+            *last = table.position().unwrap_or(*last);
+            found.push((*last, path.clone(), is_visible(table) && !path.is_empty()));
+        }
+        for (key, item) in table.iter() {
+            path.push(key.to_owned());
+            match item {
+                Item::Table(sub) => visit(sub, path, last, found),
+                Item::ArrayOfTables(tables) => tables.iter().for_each(|sub| visit(sub, path, last, found)),
+                _ => {}
+            }
+            path.pop();
+        }
+    }
+    let mut found = vec![];
+    visit(toml.as_table(), &mut vec![], &mut 0, &mut found);
+    found.sort_by_key(|(position, ..)| *position);
+    found
+        .into_iter()
+        .filter(|(.., visible)| *visible)
+        .map(|(_, path, _)| path)
+        .collect()
+}
 
-    let mut arr_copy = arr.iter().cloned().collect::<Vec<_>>();
-    arr_copy.sort_by(|a, b| match (a, b) {
-        (Value::String(a), Value::String(b)) => a.value().cmp(b.value()),
-        _ => {
-            all_strings = false;
-            Ordering::Equal
+/// Returns the paths of the items the `heading_key` entry of `heading` and
+/// the dotted `key` matches, a `*` segment stands for every table there.
+fn heading_key_paths(root: &Table, heading: &str, key: &str) -> Vec<Vec<String>> {
+    let mut paths = vec![vec![heading.to_owned()]];
+    for segment in key.split('.') {
+        let mut next = vec![];
+        for path in paths {
+            let Some(table) = table_at(root, &path) else { continue };
+            for (name, item) in table.iter() {
+                if name == segment || (segment == "*" && item.is_table()) {
+                    let mut path = path.clone();
+                    path.push(name.to_owned());
+                    next.push(path);
+                }
+            }
         }
-    });
-    if all_strings {
-        *arr = Array::from_iter(arr_copy);
+        paths = next;
+    }
+    paths
+}
+
+/// Returns the table at `path` below `root`, inline tables are not followed.
+fn table_at<'a>(root: &'a Table, path: &[String]) -> Option<&'a Table> {
+    path.iter().try_fold(root, |table, key| table.get(key)?.as_table())
+}
+
+fn item_at_mut<'a>(root: &'a mut Table, path: &[String]) -> Option<&'a mut Item> {
+    let (last, parent) = path.split_last()?;
+    let mut table = root;
+    for key in parent {
+        table = table.get_mut(key)?.as_table_mut()?;
+    }
+    table.get_mut(last)
+}
+
+/// Collects the paths of the `[target.<cfg>.<key_name>]` tables below the
+/// `target` table, a table of that name nested deeper is not a dependency
+/// table and is left alone.
+fn target_tables_with_key(target: &Table, key_name: &str, result: &mut Vec<Vec<String>>) {
+    for (cfg, item) in target.iter() {
+        if let Some(Item::Table(inner)) = item.as_table().and_then(|t| t.get(key_name))
+            && inner.position().is_some()
+        {
+            result.push(vec![TARGET.to_owned(), cfg.to_owned(), key_name.to_owned()]);
+        }
+    }
+}
+
+/// Returns each kind of `[target.<cfg>.<kind>]` table in `target_tables`
+/// with the position of its first table, in the order of those positions.
+fn target_positions(target: &Table, target_tables: &TargetTablePaths) -> Vec<(String, usize)> {
+    let mut kinds = target_tables
+        .iter()
+        .filter_map(|(kind, paths)| {
+            let first = paths.iter().filter_map(|path| table_at(target, &path[1..])?.position()).min()?;
+            Some((kind.clone(), first))
+        })
+        .collect::<Vec<_>>();
+    kinds.sort_by_key(|&(_, position)| position);
+    kinds
+}
+
+/// Returns the position of the top level table `name`, of its first table
+/// for an array of tables.
+fn root_position(root: &Table, name: &str) -> Option<usize> {
+    match root.get(name)? {
+        Item::Table(table) => table.position(),
+        Item::ArrayOfTables(arr) => arr.get(0)?.position(),
+        _ => None,
     }
+}
+
+/// Whether [`sort_array`] leaves `arr` as it is.
+fn array_sorted(arr: &Array) -> bool {
+    let mut sorted = arr.clone();
+    sort_array(&mut sorted);
+    sorted.to_string() == arr.to_string()
+}
 
+/// Sorts an array of strings, an array holding anything else is left alone.
+///
+/// A comment after an element, on the same line, moves with the element and
+/// so does one on the lines before it. The whitespace before the elements
+/// keeps its place, so the array stays on one line or on several.
+fn sort_array(arr: &mut Array) {
+    if !arr.iter().all(Value::is_str) {
+        return;
+    }
+    let len = arr.len();
+    let decor = |value: &Value| value.decor().prefix().and_then(RawString::as_str).unwrap_or_default().to_owned();
+    // the comment ending the line of an element is in the prefix of the next
+    // one, or for the last in the trailing whitespace of the array
+    let split_line = |text: &str| match text.find('\n') {
+        Some(end) if text[..end].contains('#') => (text[..end].to_owned(), text[end..].to_owned()),
+        _ => (String::new(), text.to_owned()),
+    };
+    let mut leads = vec![];
+    let mut tails = vec![];
+    for (idx, value) in arr.iter().enumerate() {
+        let prefix = decor(value);
+        let (tail, lead) = if idx == 0 { (String::new(), prefix) } else { split_line(&prefix) };
+        if idx > 0 {
+            tails.push(tail);
+        }
+        leads.push(lead);
+    }
+    let trailing = arr.trailing().as_str().unwrap_or_default().to_owned();
+    let (last_tail, trailing) = split_line(&trailing);
+    tails.push(last_tail);
+
+    let mut order = (0..len).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| arr.get(a).and_then(Value::as_str).cmp(&arr.get(b).and_then(Value::as_str)));
+    if order.iter().enumerate().all(|(pos, &idx)| pos == idx) {
+        return;
+    }
+
+    let mut values = order
+        .iter()
+        .map(|&idx| arr.get(idx).expect("in bounds").clone())
+        .collect::<Vec<_>>();
+    for (pos, value) in values.iter_mut().enumerate() {
+        let idx = order[pos];
+        // a comment on the lines before the element moves with it
+        let lead = if leads[idx].contains('#') { &leads[idx] } else { &leads[pos] };
+        let tail_before = if pos == 0 { "" } else { &tails[order[pos - 1]] };
+        value.decor_mut().set_prefix(format!("{tail_before}{lead}"));
+    }
+    let trailing = format!("{}{trailing}", tails[order[len - 1]]);
+    let trailing_comma = arr.trailing_comma();
+    *arr = Array::from_iter(values);
     arr.set_trailing(trailing);
     arr.set_trailing_comma(trailing_comma);
 }
 
-fn sort_table(table: &mut Table, group: bool) {
-    if group {
-        sort_by_group(table);
+fn sort_table(table: &mut Table, groups: Groups) {
+    if groups.any() {
+        sort_by_group(table, groups);
     } else {
         table.sort_values();
     }
@@ -205,7 +1096,9 @@ fn sort_table_by_path(table: &mut Table, path: &[String]) {
 }
 
 fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
-    if table.is_empty() && !table.is_implicit() {
+    // a table with a header is complete before its subtables are gathered,
+    // whichever of its keys and subtables the input has first
+    if !table.is_implicit() || table.iter().any(|(_, item)| item.is_value()) {
         let next = match keys.pop().unwrap() {
             Heading::Next(segs) => Heading::Complete(segs),
             comp => comp,
@@ -214,17 +1107,7 @@ fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
     }
     for (head, item) in table.iter() {
         match item {
-            Item::Value(_) => {
-                if keys.last().is_some_and(|h| matches!(h, Heading::Complete(_))) {
-                    continue;
-                }
-                let next = match keys.pop().unwrap() {
-                    Heading::Next(segs) => Heading::Complete(segs),
-                    _complete => unreachable!("the above if check prevents this"),
-                };
-                keys.push(next);
-                continue;
-            }
+            Item::Value(_) => {}
             Item::Table(table) => {
                 let next = match keys.pop().unwrap() {
                     Heading::Next(mut segs) => {
@@ -252,61 +1135,77 @@ fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
     }
 }
 
-fn sort_by_group(table: &mut Table) {
-    let table_clone = table.clone();
-    table.clear();
+/// Returns the decor holding the lines before the key `k`, for a dotted
+/// table that of its first key.
+fn line_decor<'a>(k: &'a Key, v: &'a Item) -> &'a Decor {
+    match v.as_table().filter(|t| t.is_dotted()).and_then(|t| t.key(t.iter().next()?.0)) {
+        Some(first_in_dotted) => first_in_dotted.leaf_decor(),
+        None => k.leaf_decor(),
+    }
+}
+
+/// Replaces the prefix of the [`line_decor`] of `k`.
+fn set_line_prefix(k: &mut Key, v: &mut Item, prefix: String) {
+    if let Some(table) = v.as_table_mut().filter(|t| t.is_dotted())
+        && let Some((mut first_in_dotted, _)) = table.iter_mut().next()
+    {
+        first_in_dotted.leaf_decor_mut().set_prefix(prefix);
+    } else {
+        k.leaf_decor_mut().set_prefix(prefix);
+    }
+}
 
-    let mut groups = BTreeMap::new();
-    let mut group_decor = BTreeMap::default();
+fn line_prefix<'a>(k: &'a Key, v: &'a Item) -> &'a str {
+    line_decor(k, v).prefix().and_then(RawString::as_str).unwrap_or("")
+}
 
-    let mut curr = 0;
-    for (idx, (k, _)) in table_clone.iter().enumerate() {
-        let (k, v) = table_clone.get_key_value(k).unwrap();
+/// Whether the lines before the key `k` start a new group, a blank line or
+/// with `groups.comments` a full line comment.
+fn starts_group(k: &Key, v: &Item, groups: Groups) -> bool {
+    let prefix = line_prefix(k, v);
+    let blank_lines = prefix.lines().filter(|l| !l.starts_with('#')).count();
+    (groups.blank_lines && blank_lines > 0) || (groups.comments && prefix.lines().any(|l| l.trim_start().starts_with('#')))
+}
 
-        // If the item is a dotted table, grab the decor of the first item of the table
-        // instead.
-        let decor = if let Some(first_in_dotted) = v.as_table().filter(|t| t.is_dotted()).and_then(|t| t.key(t.iter().next()?.0)) {
-            first_in_dotted.leaf_decor()
-        } else {
-            k.leaf_decor()
-        };
+/// Sorts the keys of each group of `table`, see [`starts_group`].
+///
+/// The entries are taken out once, sorted per group and put back in one
+/// pass. The lines before the first key of a group stay at the start of the
+/// group, the comments of the other keys stay with their key.
+fn sort_by_group(table: &mut Table, groups: Groups) {
+    let keys: Vec<Key> = table.iter().map(|(k, _)| table.key(k).expect("the key exists").clone()).collect();
+    let items: Vec<Item> = table.iter_mut().map(|(_, v)| std::mem::take(v)).collect();
+    table.clear();
 
-        let blank_lines = decor
-            .prefix()
-            .and_then(RawString::as_str)
-            .unwrap_or("")
-            .lines()
-            .filter(|l| !l.starts_with('#'))
-            .count();
-
-        if blank_lines > 0 {
-            let decor = k.leaf_decor().clone();
-            let k = k.clone().with_leaf_decor(Decor::default());
-
-            groups.entry(idx).or_insert_with(|| vec![(k, v)]);
-            group_decor.insert(idx, decor);
-            curr = idx;
-        } else {
-            groups.entry(curr).or_default().push((k.clone(), v));
+    let mut sorted: Vec<(String, Vec<(Key, Item)>)> = vec![];
+    for (mut k, mut v) in keys.into_iter().zip(items) {
+        if sorted.is_empty() || starts_group(&k, &v, groups) {
+            let separator = line_prefix(&k, &v).to_owned();
+            set_line_prefix(&mut k, &mut v, String::new());
+            sorted.push((separator, vec![]));
         }
+        sorted.last_mut().expect("a group was pushed").1.push((k, v));
     }
 
-    for (idx, mut group) in groups {
+    for (separator, mut group) in sorted {
         group.sort_by(|a, b| a.0.cmp(&b.0));
-        let group_decor = group_decor.remove(&idx);
-
-        for (idx, (mut k, v)) in group.into_iter().enumerate() {
-            if idx == 0 {
-                if let Some(group_decor) = group_decor.clone() {
-                    k = k.with_leaf_decor(group_decor);
-                }
-            }
-
-            table.insert_formatted(&k, v.clone());
+        // the decor of a key in a `[header]` is written inside the brackets
+        let head = group
+            .iter_mut()
+            .find(|(_, v)| v.is_value() || v.as_table().is_some_and(Table::is_dotted));
+        if let Some((k, v)) = head
+            && !separator.is_empty()
+        {
+            let prefix = separator + line_prefix(k, v);
+            set_line_prefix(k, v, prefix);
+        }
+        for (k, v) in group {
+            table.insert_formatted(&k, v);
         }
     }
 }
 
+#[allow(clippy::collapsible_if, clippy::iter_kv_map)]
 fn sort_lexicographical(first_table: Option<usize>, heading_order: &BTreeMap<(usize, String), Vec<Heading>>, toml: &mut DocumentMut) {
     // Since the root table is always index 0 we add one
     let first_table_idx = first_table.unwrap_or_default() + 1;
@@ -349,10 +1248,13 @@ fn sort_by_ordering(ordering: &[String], heading_order: &BTreeMap<(usize, String
         /// - Traverse left (backward) to the start (including `heading`)
         /// - Traverse right (forward) to the end (after `heading`)
         ///
-        /// Then join both parts into a dot-separated string, for example:
+        /// Then join both parts, for example:
         /// `[target.'cfg(windows)'.dependencies.windows-sys]` will be
         /// `[dependencies.'cfg(windows)'.target.windows-sys]`
-        fn join_segs_around_heading(segs: &[String], heading: &str) -> Option<String> {
+        ///
+        /// The segments are kept apart, so targets compare by their cfg
+        /// string even when it holds a `.`.
+        fn join_segs_around_heading(segs: &[String], heading: &str) -> Option<Vec<String>> {
             if let Some(pos) = segs.iter().position(|seg| seg == heading) {
                 let mut left: Vec<_> = segs[..=pos].iter().rev().cloned().collect();
                 let right: Vec<_> = if pos + 1 < segs.len() {
@@ -361,7 +1263,7 @@ fn sort_by_ordering(ordering: &[String], heading_order: &BTreeMap<(usize, String
                     Vec::new()
                 };
                 left.extend(right);
-                return Some(left.join("."));
+                return Some(left);
             }
             None
         }
@@ -371,7 +1273,7 @@ fn sort_by_ordering(ordering: &[String], heading_order: &BTreeMap<(usize, String
                 .iter()
                 .filter_map(|h| {
                     if let Heading::Complete(segs) = h {
-                        return join_segs_around_heading(segs, heading);
+                        return join_segs_around_heading(segs, heading).map(|segs| segs.join("."));
                     }
                     None
                 })
@@ -379,12 +1281,10 @@ fn sort_by_ordering(ordering: &[String], heading_order: &BTreeMap<(usize, String
                 .unwrap_or_default()
         }
 
-        matches.sort_by(|((_, a_key), a_headings), ((_, b_key), b_headings)| {
-            let a1_longest = extract_heading_segments(a_headings, heading);
-            let b1_longest = extract_heading_segments(b_headings, heading);
-            let ord = a1_longest.cmp(&b1_longest);
-            if ord == Ordering::Equal { a_key.cmp(b_key) } else { ord }
-        });
+        // the keys are built once, a table has as many headings as it has
+        // `[heading.name]` subtables, the `[target.<cfg>.heading]` tables
+        // follow the others
+        matches.sort_by_cached_key(|((_, key), headings)| (key == TARGET, extract_heading_segments(headings, heading), key.clone()));
 
         if !matches.is_empty() {
             for &((_, key), to_sort_headings) in &matches {
@@ -402,15 +1302,15 @@ fn sort_by_ordering(ordering: &[String], heading_order: &BTreeMap<(usize, String
                         false
                     })
                     .collect::<Vec<_>>();
-                to_sort_headings.sort_by_key(|h| {
+                to_sort_headings.sort_by_cached_key(|h| {
                     if let Heading::Complete(segs) = h {
                         if key == TARGET {
                             join_segs_around_heading(segs, heading).unwrap_or_default()
                         } else {
-                            segs.to_vec().join(".")
+                            vec![segs.join(".")]
                         }
                     } else {
-                        String::new()
+                        vec![]
                     }
                 });
                 for h in to_sort_headings {
@@ -463,16 +1363,27 @@ fn walk_tables_set_position(table: &mut Table, idx: &mut usize) {
 
 #[cfg(test)]
 mod test {
-    use std::fs;
+    use std::{collections::BTreeMap, fs};
 
-    use super::MATCHER;
-    use crate::test_utils::assert_eq;
+    use toml_edit::{Decor, DocumentMut, Item, Table};
+
+    use super::{Groups, MATCHER, Matcher, UnknownTables, starts_group};
+    use crate::{
+        fmt::{DEF_TABLE_ORDER, TableOverride, TableOverrides},
+        test_utils::assert_eq,
+    };
+
+    /// The groups of `--grouped`.
+    const GROUPED: Groups = Groups {
+        blank_lines: true,
+        comments: false,
+    };
 
     #[test]
     fn toml_edit_check() {
         let input = fs::read_to_string("examp/workspace.toml").unwrap();
         let expected = fs::read_to_string("examp/workspace.sorted.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, false, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, false, &[]).unwrap();
         assert_eq(expected, sorted);
     }
 
@@ -480,18 +1391,224 @@ mod test {
     fn toml_combined_key_check() {
         let input = fs::read_to_string("examp/tun.toml").unwrap();
         let expected = fs::read_to_string("examp/tun.sorted.toml").unwrap();
-        let o = crate::fmt::DEF_TABLE_ORDER;
+        let o = DEF_TABLE_ORDER;
         let o = o.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
-        let sorted = super::sort_toml(&input, MATCHER, false, &o);
+        let sorted = super::sort_toml(&input, MATCHER, false, &o).unwrap();
+
+        assert_eq(expected, sorted);
+    }
+
+    #[test]
+    fn target_tables() {
+        let input = fs::read_to_string("fixtures/valid/targets.toml").unwrap();
+        let expected = fs::read_to_string("fixtures/valid/targets.sorted.toml").unwrap();
+        let order = DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+        let sorted = super::sort_toml(&input, MATCHER, false, &order).unwrap();
+        assert_eq(&expected, &sorted);
+        assert_eq(&expected, super::sort_toml(&expected, MATCHER, false, &order).unwrap());
+
+        // a kind that only has target tables is placed like a table of its own
+        let input = "[package]\n\n[target.'cfg(unix)'.dev-dependencies]\na = \"1\"\n\n[lib]\n\n[dependencies]\nb = \"1\"\n";
+        let order = ["package".to_owned(), "dependencies".to_owned()];
+        for unknown_tables in [
+            UnknownTables::Position,
+            UnknownTables::End,
+            UnknownTables::Keep,
+            UnknownTables::Alphabetize,
+        ] {
+            let matcher = Matcher { unknown_tables, ..MATCHER };
+            let once = super::sort_toml(input, matcher, false, &order).unwrap().to_string();
+            let headers = once.lines().filter(|line| line.starts_with('[')).collect::<Vec<_>>();
+            let expected = match unknown_tables {
+                UnknownTables::Keep => ["[package]", "[target.'cfg(unix)'.dev-dependencies]", "[lib]", "[dependencies]"],
+                UnknownTables::Alphabetize => ["[package]", "[dependencies]", "[target.'cfg(unix)'.dev-dependencies]", "[lib]"],
+                UnknownTables::Position | UnknownTables::End => {
+                    ["[package]", "[dependencies]", "[target.'cfg(unix)'.dev-dependencies]", "[lib]"]
+                }
+            };
+            assert_eq!(headers, expected, "{unknown_tables:?}");
+            // the target tables keep their new position, which `[lib]` took
+            if unknown_tables != UnknownTables::Position {
+                assert_eq(&once, super::sort_toml(&once, matcher, false, &order).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn implicit_parents() {
+        let input = fs::read_to_string("examp/implicit.toml").unwrap();
+        let expected = fs::read_to_string("examp/implicit.sorted.toml").unwrap();
+        let order = DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+        let sorted = super::sort_toml(&input, MATCHER, false, &order).unwrap();
+        assert_eq(&expected, &sorted);
+        let reparsed = expected.parse::<DocumentMut>().unwrap();
+        let parents: &[&[&str]] = &[
+            &["workspace"],
+            &["workspace", "metadata"],
+            &["dependencies"],
+            &["dev-dependencies"],
+            &["profile", "release", "package"],
+        ];
+        for doc in [&sorted, &reparsed] {
+            for path in parents {
+                let table = path.iter().fold(doc.as_item(), |item, key| &item[key]);
+                assert!(table.as_table().unwrap().is_implicit(), "{path:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn exact_table_paths() {
+        let input = r#"[package]
+name = "a"
+
+[package.metadata.tool.dependencies]
+z = 1
+a = 2
+
+[dependencies]
+z = "1"
+a = "1"
 
+[target.'cfg(unix)'.dependencies]
+z = "1"
+a = "1"
+
+[target.'cfg(unix)'.dev-dependencies.x.dependencies]
+z = 1
+a = 2
+
+[target.'cfg(unix)'.tool.dependencies]
+z = 1
+a = 2
+"#;
+        let expected = input
+            .replace("[dependencies]\nz = \"1\"\na = \"1\"", "[dependencies]\na = \"1\"\nz = \"1\"")
+            .replace("'.dependencies]\nz = \"1\"\na = \"1\"", "'.dependencies]\na = \"1\"\nz = \"1\"");
+        let sorted = super::sort_toml(input, MATCHER, false, &[]).unwrap();
         assert_eq(expected, sorted);
     }
 
+    #[test]
+    fn workspace_tables() {
+        let input = r#"[package]
+name = "a"
+
+[dependencies]
+z = "1"
+a = "1"
+
+[workspace]
+members = ["a", "b"]
+
+[workspace.dependencies]
+z = "1"
+a = "1"
+
+y = "1"
+b = "1"
+
+[workspace.lints.clippy]
+pedantic = { level = "warn", priority = -1 }
+needless_pass_by_value = "allow"
+
+[workspace.lints.rust]
+unsafe_code = "forbid"
+missing_docs = "warn"
+
+[workspace.metadata]
+z = 1
+a = 2
+"#;
+        let swap = |text: &str, first: &str, second: &str| text.replace(&format!("{first}\n{second}"), &format!("{second}\n{first}"));
+        let grouped = swap(input, "z = \"1\"", "a = \"1\"").replace("y = \"1\"\nb = \"1\"", "b = \"1\"\ny = \"1\"");
+        let grouped = swap(&grouped, "unsafe_code = \"forbid\"", "missing_docs = \"warn\"");
+        let grouped = swap(
+            &grouped,
+            "pedantic = { level = \"warn\", priority = -1 }",
+            "needless_pass_by_value = \"allow\"",
+        );
+        let grouped = swap(&grouped, "z = 1", "a = 2");
+        assert_eq(&grouped, super::sort_toml(input, MATCHER, true, &[]).unwrap());
+
+        let sorted = super::sort_toml(input, MATCHER, false, &[]).unwrap().to_string();
+        assert!(
+            sorted.contains("[workspace.dependencies]\na = \"1\"\nb = \"1\"\n\ny = \"1\"\nz = \"1\"\n"),
+            "{sorted}"
+        );
+        assert_eq(&sorted, super::sort_toml(&sorted, MATCHER, false, &[]).unwrap());
+
+        let ordering = ["package", "dependencies", "workspace"].map(str::to_owned);
+        let doc = super::parse(input).unwrap();
+        assert!(!super::is_sorted(&doc, MATCHER, true, &ordering));
+        let doc = super::parse(&grouped).unwrap();
+        assert!(super::is_sorted(&doc, MATCHER, true, &ordering));
+    }
+
+    #[test]
+    fn lints_tables() {
+        let input = r#"[package]
+name = "a"
+
+[lints.rust]
+unsafe_code = "forbid"
+missing_docs = "warn"
+
+[lints.clippy]
+pedantic = { level = "warn", priority = -1 }
+needless_pass_by_value = "allow"
+all = { priority = -2, level = "deny" }
+
+[lints.mytool]
+b = 1
+
+[dependencies]
+a = "1"
+"#;
+        let expected = r#"[package]
+name = "a"
+
+[dependencies]
+a = "1"
+
+[lints.clippy]
+all = { priority = -2, level = "deny" }
+needless_pass_by_value = "allow"
+pedantic = { level = "warn", priority = -1 }
+
+[lints.mytool]
+b = 1
+
+[lints.rust]
+missing_docs = "warn"
+unsafe_code = "forbid"
+"#;
+        let ordering = ["package", "dependencies", "lints"].map(str::to_owned);
+        let sorted = super::sort_toml(input, MATCHER, false, &ordering).unwrap();
+        assert_eq(expected, &sorted);
+        let doc = super::parse(expected).unwrap();
+        assert!(super::is_sorted(&doc, MATCHER, false, &ordering));
+
+        // `*` puts the lints where the unlisted tables go
+        let ordering = ["package", "*", "dependencies"].map(str::to_owned);
+        let sorted = super::sort_toml(input, MATCHER, false, &ordering).unwrap().to_string();
+        assert!(
+            sorted.find("[lints.rust]").unwrap() < sorted.find("[dependencies]").unwrap(),
+            "{sorted}"
+        );
+
+        let dotted = "[lints]\nrust.unsafe_code = \"forbid\"\nclippy.all = \"warn\"\nrust.missing_docs = \"warn\"\n";
+        assert_eq(
+            "[lints]\nclippy.all = \"warn\"\nrust.missing_docs = \"warn\"\nrust.unsafe_code = \"forbid\"\n",
+            super::sort_toml(dotted, MATCHER, false, &[]).unwrap(),
+        );
+    }
+
     #[test]
     fn toml_workspace_deps_edit_check() {
         let input = fs::read_to_string("examp/workspace_deps.toml").unwrap();
         let expected = fs::read_to_string("examp/workspace_deps.sorted.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, false, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, false, &[]).unwrap();
         assert_eq(expected, sorted);
     }
 
@@ -499,14 +1616,14 @@ mod test {
     fn grouped_check() {
         let input = fs::read_to_string("examp/ruma.toml").unwrap();
         let expected = fs::read_to_string("examp/ruma.sorted.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(expected, sorted);
     }
 
     #[test]
     fn sort_correct() {
         let input = fs::read_to_string("examp/right.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(input, sorted);
     }
 
@@ -514,14 +1631,14 @@ mod test {
     fn sort_comments() {
         let input = fs::read_to_string("examp/comments.toml").unwrap();
         let expected = fs::read_to_string("examp/comments.sorted.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(expected, sorted);
     }
 
     #[test]
     fn sort_tables() {
         let input = fs::read_to_string("examp/fend.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_ne!(input, sorted.to_string());
         // println!("{}", sorted.to_string());
     }
@@ -529,18 +1646,18 @@ mod test {
     #[test]
     fn sort_devfirst() {
         let input = fs::read_to_string("examp/reorder.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(input, sorted);
 
         let input = fs::read_to_string("examp/noreorder.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(input, sorted);
     }
 
     #[test]
     fn issue_104() {
         let input = fs::read_to_string("regressions/104.toml").unwrap();
-        let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+        let sorted = super::sort_toml(&input, MATCHER, true, &[]).unwrap();
         assert_eq(input, sorted);
     }
 
@@ -558,7 +1675,676 @@ mod test {
                 "build-dependencies".to_owned(),
                 "dev-dependencies".to_owned(),
             ],
-        );
+        )
+        .unwrap();
         assert_ne!(input, sorted.to_string());
     }
+
+    #[test]
+    fn table_overrides() {
+        let input = r#"[dependencies]
+b = "1"
+a = "1"
+
+[features]
+z = []
+y = []
+
+[workspace]
+members = ["b", "a"]
+"#;
+        let config = r#"
+[tables.features]
+sort = true
+[tables.dependencies]
+sort = false
+[tables."workspace.members"]
+sort = false
+"#
+        .parse::<crate::fmt::Config>()
+        .unwrap();
+        let matcher = Matcher {
+            tables: Some(&config.tables),
+            ..MATCHER
+        };
+        let sorted = super::sort_toml(input, matcher, false, &[]).unwrap();
+        assert_eq(
+            r#"[dependencies]
+b = "1"
+a = "1"
+
+[features]
+y = []
+z = []
+
+[workspace]
+members = ["b", "a"]
+"#,
+            sorted,
+        );
+    }
+
+    #[test]
+    fn unknown_tables() {
+        let input = r#"[lints]
+x = 1
+
+[package]
+name = "x"
+
+[badges]
+y = 1
+
+[features]
+f = []
+
+[dependencies]
+a = "1"
+"#;
+        let order = ["package", "dependencies", "features"].map(str::to_owned);
+        let headers = |unknown_tables| {
+            let matcher = Matcher { unknown_tables, ..MATCHER };
+            let sorted = super::sort_toml(input, matcher, false, &order).unwrap().to_string();
+            sorted.lines().filter(|l| l.starts_with('[')).map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        assert_eq!(MATCHER.unknown_tables, UnknownTables::Position);
+        // `[lints]` is sorted so it keeps its position, `[badges]` is not
+        assert_eq!(
+            headers(UnknownTables::Position),
+            ["[package]", "[lints]", "[dependencies]", "[features]", "[badges]"]
+        );
+        assert_eq!(
+            headers(UnknownTables::End),
+            ["[package]", "[dependencies]", "[features]", "[lints]", "[badges]"]
+        );
+        assert_eq!(
+            headers(UnknownTables::Alphabetize),
+            ["[package]", "[dependencies]", "[features]", "[badges]", "[lints]"]
+        );
+        assert_eq!(
+            headers(UnknownTables::Keep),
+            ["[lints]", "[package]", "[badges]", "[dependencies]", "[features]"]
+        );
+    }
+
+    #[test]
+    fn unknown_tables_default() {
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\na = \"1\"\n\n[lints]\nrust = {}\n\n[features]\nf = []\n\n\
+                     [dev-dependencies]\nb = \"1\"\n";
+        let order = ["package", "features", "lints"].map(str::to_owned);
+        let sorted = super::sort_toml(input, MATCHER, false, &order).unwrap().to_string();
+        let headers = sorted.lines().filter(|l| l.starts_with('[')).collect::<Vec<_>>();
+        let before = |a: &str, b: &str| {
+            let at = |header: &str| headers.iter().position(|h| *h == header).unwrap();
+            assert!(at(a) < at(b), "{a} is not before {b}: {headers:?}");
+        };
+
+        // the listed tables keep the order they are listed in
+        before("[package]", "[features]");
+        before("[features]", "[lints]");
+        // the unlisted ones keep their positions, where they always went
+        before("[features]", "[dependencies]");
+        before("[dependencies]", "[lints]");
+        before("[lints]", "[dev-dependencies]");
+    }
+
+    #[test]
+    fn order_wildcard() {
+        let input = "[lints]\nx = 1\n\n[package]\nname = \"x\"\n\n[badges]\ny = 1\n\n[features]\nf = []\n\n[dependencies]\na = \"1\"\n";
+        let headers = |order: &[&str], unknown_tables| {
+            let order = order.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+            let matcher = Matcher { unknown_tables, ..MATCHER };
+            let sorted = super::sort_toml(input, matcher, false, &order).unwrap().to_string();
+            assert_eq(&sorted, super::sort_toml(&sorted, matcher, false, &order).unwrap());
+            assert!(super::is_sorted(&sorted.parse().unwrap(), matcher, false, &order), "{order:?}");
+            sorted.lines().filter(|l| l.starts_with('[')).map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        // the unlisted tables keep their input order in the slot, whatever the policy
+        for unknown_tables in [UnknownTables::End, UnknownTables::Keep] {
+            assert_eq!(
+                headers(&["*", "package", "dependencies"], unknown_tables),
+                ["[lints]", "[badges]", "[features]", "[package]", "[dependencies]"]
+            );
+            assert_eq!(
+                headers(&["package", "*", "lints"], unknown_tables),
+                ["[package]", "[badges]", "[features]", "[dependencies]", "[lints]"]
+            );
+            assert_eq!(
+                headers(&["package", "dependencies", "*"], unknown_tables),
+                ["[package]", "[dependencies]", "[lints]", "[badges]", "[features]"]
+            );
+        }
+        assert_eq!(
+            headers(&["package", "*", "badges"], UnknownTables::Alphabetize),
+            ["[package]", "[dependencies]", "[features]", "[lints]", "[badges]"]
+        );
+        // nothing left for the slot
+        assert_eq!(
+            headers(&["features", "*", "package", "badges", "lints", "dependencies"], UnknownTables::End),
+            ["[features]", "[package]", "[badges]", "[lints]", "[dependencies]"]
+        );
+        assert_eq!(
+            headers(&["*"], UnknownTables::Alphabetize),
+            ["[badges]", "[dependencies]", "[features]", "[lints]", "[package]"]
+        );
+    }
+
+    #[test]
+    fn array_of_tables() {
+        let input = fs::read_to_string("examp/examples.toml").unwrap();
+        let names = ["example".to_owned()];
+        let matcher = Matcher {
+            array_of_tables: &names,
+            ..MATCHER
+        };
+        let order = DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+        let sorted = super::sort_toml(&input, matcher, false, &order).unwrap();
+        assert_eq(fs::read_to_string("examp/examples.sorted.toml").unwrap(), &sorted);
+        assert_eq(
+            sorted.to_string(),
+            super::sort_toml(&sorted.to_string(), matcher, false, &order).unwrap(),
+        );
+        assert!(!super::is_sorted(&sorted, matcher, false, &order));
+
+        // without the option the entries stay in input order
+        let unsorted = super::sort_toml(&input, MATCHER, false, &order).unwrap().to_string();
+        let paths = |toml: &str| {
+            toml.lines()
+                .filter(|line| line.starts_with("path = \"examples"))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&unsorted), paths(&input));
+    }
+
+    #[test]
+    fn file_header() {
+        let order = ["package", "dependencies"].map(str::to_owned);
+        let sort = |input: &str| {
+            let sorted = super::sort_toml(input, MATCHER, false, &order).unwrap().to_string();
+            assert_eq(&sorted, super::sort_toml(&sorted, MATCHER, false, &order).unwrap());
+            sorted
+        };
+
+        // the block before a blank line stays at the top
+        let input = "# SPDX-License-Identifier: MIT\n# Copyright\n\n[dependencies]\na = \"1\"\n\n[package]\nname = \"x\"\n";
+        assert_eq(
+            "# SPDX-License-Identifier: MIT\n# Copyright\n\n[package]\nname = \"x\"\n\n[dependencies]\na = \"1\"\n",
+            sort(input),
+        );
+        let input = "# SPDX-License-Identifier: MIT\n\n[dependencies]\na = \"1\"\n\n# the package\n[package]\nname = \"x\"\n";
+        assert_eq(
+            "# SPDX-License-Identifier: MIT\n\n# the package\n[package]\nname = \"x\"\n\n[dependencies]\na = \"1\"\n",
+            sort(input),
+        );
+
+        // a comment right above the header moves with its table
+        let input = "# SPDX-License-Identifier: MIT\n\n# the deps\n[dependencies]\na = \"1\"\n\n[package]\nname = \"x\"\n";
+        assert_eq(
+            "# SPDX-License-Identifier: MIT\n\n[package]\nname = \"x\"\n\n# the deps\n[dependencies]\na = \"1\"\n",
+            sort(input),
+        );
+        let input = "# the deps\n[dependencies]\na = \"1\"\n[package]\nname = \"x\"\n";
+        assert_eq("[package]\nname = \"x\"\n# the deps\n[dependencies]\na = \"1\"\n", sort(input));
+
+        // a `#!` line is part of the file header even without a blank line
+        let input = "#!/usr/bin/env cargo\n# SPDX-License-Identifier: MIT\n\n[dependencies]\na = \"1\"\n[package]\nname = \"x\"\n";
+        assert_eq(
+            "#!/usr/bin/env cargo\n# SPDX-License-Identifier: MIT\n\n[package]\nname = \"x\"\n\n[dependencies]\na = \"1\"\n",
+            sort(input),
+        );
+        let input = "#!/usr/bin/env cargo\n[dependencies]\na = \"1\"\n[package]\nname = \"x\"\n";
+        assert_eq(
+            "#!/usr/bin/env cargo\n[package]\nname = \"x\"\n[dependencies]\na = \"1\"\n",
+            sort(input),
+        );
+
+        // sorted files are left as they are
+        for input in [
+            "# SPDX-License-Identifier: MIT\n\n[package]\nname = \"x\"\n",
+            "#!/usr/bin/env cargo\n[package]\nname = \"x\"\n",
+            "# the package\n[package]\nname = \"x\"\n",
+        ] {
+            assert_eq(input, sort(input));
+        }
+    }
+
+    #[test]
+    fn parse_error() {
+        let input = "[package]\nname = \"a\"\n[dependencies\nb = \"1\"\n";
+        let err = super::sort_toml(input, MATCHER, false, &[]).unwrap_err();
+        assert_eq!(err.message(), "invalid table header\nexpected `.`, `]`");
+        assert_eq!(err.span(), Some(34..35));
+        assert_eq!(err.line(), Some(3));
+        assert!(err.to_string().starts_with("TOML parse error at line 3, column 14"), "{err}");
+    }
+
+    #[test]
+    fn duplicate_tables() {
+        let input = fs::read_to_string("fixtures/invalid/duplicate-dependencies.toml").unwrap();
+        let err = super::sort_toml(&input, MATCHER, true, &[]).unwrap_err();
+        assert_eq!(err.duplicate(), Some(("dependencies", 4, 10)));
+        assert!(
+            err.to_string()
+                .starts_with("`[dependencies]` on line 10 is already defined on line 4\n"),
+            "{err}"
+        );
+
+        let duplicate = |input: &str| {
+            super::parse(input)
+                .unwrap_err()
+                .duplicate()
+                .map(|(path, first, line)| (path.to_owned(), first, line))
+        };
+        let key = "[dependencies]\na = \"1\"\n\n[dependencies.a]\nversion = \"1\"\n";
+        assert_eq!(duplicate(key), Some(("dependencies.a".to_owned(), 2, 4)));
+        let array = "[[bin]]\nname = \"a\"\n[bin]\n";
+        assert_eq!(duplicate(array), Some(("bin".to_owned(), 1, 3)));
+        assert_eq!(duplicate("[a]\nb = 1\nb = 2\n"), None);
+
+        let duplicate_key = |input: &str| {
+            let err = super::parse(input).unwrap_err();
+            let found = err
+                .duplicate_key()
+                .map(|(path, key, first, line)| (path.to_owned(), key.to_owned(), first, line));
+            (found, err.to_string().lines().next().unwrap().to_owned())
+        };
+        let merged =
+            "[package]\nname = \"a\"\n\n[dependencies]\ntokio = \"1\"\nserde = \"1\"\ntokio = { version = \"1\", features = [\"full\"] }\n";
+        assert_eq!(
+            duplicate_key(merged),
+            (
+                Some(("dependencies".to_owned(), "tokio".to_owned(), 5, 7)),
+                "`tokio` of `[dependencies]` on line 7 is already defined on line 5".to_owned()
+            )
+        );
+        let target = "[target.'cfg(unix)'.dependencies]\nlibc.version = \"1\"\n\n[[bin]]\nname = \"b\"\nname = \"c\"\n";
+        assert_eq!(duplicate_key(target).0, Some(("bin".to_owned(), "name".to_owned(), 5, 6)));
+        let dotted = "[target.'cfg(unix)'.dependencies]\nlibc.version = \"1\"\n\"libc\".version = \"2\"\n";
+        assert_eq!(
+            duplicate_key(dotted).0,
+            Some(("target.\"cfg(unix)\".dependencies".to_owned(), "\"libc\".version".to_owned(), 2, 3))
+        );
+        assert_eq!(
+            duplicate_key("dupe = false\ndupe = true\n"),
+            (
+                Some((String::new(), "dupe".to_owned(), 1, 2)),
+                "`dupe` on line 2 is already defined on line 1".to_owned()
+            )
+        );
+        assert_eq!(duplicate_key("a = { b = 1, b = 2 }\n").0, None);
+        assert_eq!(duplicate_key("[a]\nb = 1\n[a.b]\n").0, None);
+
+        for entry in fs::read_dir("fixtures/invalid").unwrap() {
+            let path = entry.unwrap().path();
+            if !path.file_name().unwrap().to_str().unwrap().starts_with("duplicate-") {
+                continue;
+            }
+            let input = fs::read_to_string(&path).unwrap();
+            assert!(super::sort_toml(&input, MATCHER, false, &[]).is_err(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn is_sorted_agrees_with_sorting() {
+        let orders: [&[&str]; 3] = [
+            DEF_TABLE_ORDER,
+            &["package", "dependencies"],
+            &["dependencies", "workspace", "package"],
+        ];
+        let (mut checked, mut predicted) = (0, 0);
+        for entry in fs::read_dir("examp").unwrap() {
+            let input = fs::read_to_string(entry.unwrap().path()).unwrap();
+            for group in [false, true] {
+                for order in orders {
+                    for unknown_tables in [UnknownTables::Keep, UnknownTables::Alphabetize, UnknownTables::End] {
+                        let order = order.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+                        let matcher = Matcher { unknown_tables, ..MATCHER };
+                        // the sorted form is what `--check` usually sees
+                        let sorted = super::sort_toml(&input, matcher, group, &order).unwrap().to_string();
+                        for text in [&input, &sorted] {
+                            checked += 1;
+                            let doc = text.parse::<DocumentMut>().unwrap();
+                            if super::is_sorted(&doc, matcher, group, &order) {
+                                predicted += 1;
+                                assert_eq(super::sort_toml(text, matcher, group, &order).unwrap(), doc);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(predicted * 2 > checked, "only {predicted} of {checked} predicted sorted");
+    }
+
+    /// A manifest with a table or array for every option of [`Matcher`],
+    /// without `target` tables which [`is_sorted`](super::is_sorted) leaves
+    /// to sorting.
+    const OPTIONS: &str = r#"[package]
+name = "a"
+
+[lints.rust]
+unsafe_code = "forbid"
+missing_docs = "warn"
+
+[[bin]]
+name = "b"
+
+[dependencies]
+# networking
+tokio = "1"
+hyper = "1"
+# serialization
+serde = "1"
+anyhow = "1"
+
+[[example]]
+name = "z"
+
+[[example]]
+name = "a"
+
+[features]
+std = ["serde/std", "alloc"]
+alloc = []
+
+[package.metadata.docs.rs]
+all-features = true
+features = ["z", "a"]
+
+[lints.clippy]
+pedantic = "warn"
+all = "warn"
+"#;
+
+    #[test]
+    fn is_sorted_agrees_with_sorting_options() {
+        let overrides: TableOverrides = [
+            (
+                "dependencies",
+                TableOverride {
+                    sort: Some(false),
+                    ..TableOverride::default()
+                },
+            ),
+            (
+                "features",
+                TableOverride {
+                    sort: Some(true),
+                    ..TableOverride::default()
+                },
+            ),
+            (
+                "lints.rust",
+                TableOverride {
+                    grouped: Some(true),
+                    ..TableOverride::default()
+                },
+            ),
+        ]
+        .into_iter()
+        .map(|(path, settings)| (path.to_owned(), settings))
+        .collect();
+        let arrays = ["example".to_owned(), "bin".to_owned()];
+        let matchers = [
+            Matcher {
+                group_comments: true,
+                ..MATCHER
+            },
+            Matcher {
+                feature_values: true,
+                ..MATCHER
+            },
+            Matcher {
+                array_of_tables: &arrays,
+                ..MATCHER
+            },
+            Matcher {
+                heading: &["dependencies", "features"],
+                heading_key: &[("package", "metadata.*"), ("workspace", "dependencies")],
+                ..MATCHER
+            },
+            Matcher {
+                heading: &[],
+                heading_key: &[],
+                ..MATCHER
+            },
+            Matcher {
+                tables: Some(&overrides),
+                ..MATCHER
+            },
+            Matcher {
+                tables: Some(&overrides),
+                group_comments: true,
+                feature_values: true,
+                ..MATCHER
+            },
+        ];
+        let order = DEF_TABLE_ORDER.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+        let mut inputs = vec![OPTIONS.to_owned()];
+        for dir in ["examp", "fixtures/valid"] {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "toml") {
+                    inputs.push(fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+        let (mut checked, mut predicted) = (0, 0);
+        let mut options_predicted = vec![0; matchers.len()];
+        for input in &inputs {
+            for (idx, matcher) in matchers.into_iter().enumerate() {
+                for unknown_tables in [
+                    UnknownTables::Position,
+                    UnknownTables::Keep,
+                    UnknownTables::Alphabetize,
+                    UnknownTables::End,
+                ] {
+                    let matcher = Matcher { unknown_tables, ..matcher };
+                    for group in [false, true] {
+                        let sorted = super::sort_toml(input, matcher, group, &order).unwrap().to_string();
+                        for text in [input, &sorted] {
+                            checked += 1;
+                            let doc = text.parse::<DocumentMut>().unwrap();
+                            if super::is_sorted(&doc, matcher, group, &order) {
+                                predicted += 1;
+                                options_predicted[idx] += usize::from(*input == OPTIONS);
+                                assert_eq(super::sort_toml(text, matcher, group, &order).unwrap(), doc);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(predicted * 2 > checked, "only {predicted} of {checked} predicted sorted");
+        // each option takes the fast path, but for arrays of tables sorted by name which it leaves to sorting
+        let arrays_idx = 2;
+        assert!(
+            (0..matchers.len()).all(|idx| (options_predicted[idx] > 0) != (idx == arrays_idx)),
+            "{options_predicted:?}"
+        );
+    }
+
+    /// The grouped sorting before it was rebuilt in one pass, it replaced
+    /// the comments of the key sorted first in a group with the lines before
+    /// the group.
+    fn old_sort_by_group(table: &mut Table) {
+        let table_clone = table.clone();
+        table.clear();
+
+        let mut groups = BTreeMap::new();
+        let mut group_decor = BTreeMap::default();
+
+        let mut curr = 0;
+        for (idx, (k, _)) in table_clone.iter().enumerate() {
+            let (k, v) = table_clone.get_key_value(k).unwrap();
+
+            if starts_group(k, v, GROUPED) {
+                let decor = k.leaf_decor().clone();
+                let k = k.clone().with_leaf_decor(Decor::default());
+
+                groups.entry(idx).or_insert_with(|| vec![(k, v)]);
+                group_decor.insert(idx, decor);
+                curr = idx;
+            } else {
+                groups.entry(curr).or_default().push((k.clone(), v));
+            }
+        }
+
+        for (idx, mut group) in groups {
+            group.sort_by(|a, b| a.0.cmp(&b.0));
+            let group_decor = group_decor.remove(&idx);
+            // the decor of a key in a `[header]` is written inside the brackets
+            let head = group
+                .iter()
+                .position(|(_, v)| v.is_value() || v.as_table().is_some_and(Table::is_dotted))
+                .unwrap_or_default();
+
+            for (idx, (mut k, v)) in group.into_iter().enumerate() {
+                if idx == head
+                    && let Some(group_decor) = group_decor.clone()
+                {
+                    k = k.with_leaf_decor(group_decor);
+                }
+
+                table.insert_formatted(&k, v.clone());
+            }
+        }
+    }
+
+    fn grouped_tables(table: &mut Table, sort: fn(&mut Table)) {
+        sort(table);
+        for (_, item) in table.iter_mut() {
+            if let Item::Table(table) = item {
+                grouped_tables(table, sort);
+            }
+        }
+    }
+
+    #[test]
+    fn grouped_matches_old_sorting() {
+        let mut differ = vec![];
+        for entry in fs::read_dir("examp").unwrap().chain(fs::read_dir("fixtures/valid").unwrap()) {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+            let input = fs::read_to_string(&path).unwrap().parse::<DocumentMut>().unwrap();
+            let (mut old, mut new) = (input.clone(), input);
+            grouped_tables(old.as_table_mut(), old_sort_by_group);
+            grouped_tables(new.as_table_mut(), |table| super::sort_by_group(table, GROUPED));
+            if old.to_string() != new.to_string() {
+                differ.push(path.display().to_string());
+            }
+        }
+        assert!(differ.is_empty(), "{differ:?}");
+    }
+
+    #[test]
+    fn comment_groups() {
+        let input = r#"[dependencies]
+# async runtime
+tokio = "1"
+async-std = "1" # trailing
+# serialization
+serde_json = "1"
+serde = "1"
+
+b = "1"
+a = "1"
+"#;
+        let matcher = Matcher {
+            group_comments: true,
+            ..MATCHER
+        };
+        let grouped = r#"[dependencies]
+# async runtime
+async-std = "1" # trailing
+tokio = "1"
+# serialization
+serde = "1"
+serde_json = "1"
+
+a = "1"
+b = "1"
+"#;
+        assert_eq(grouped, super::sort_toml(input, matcher, true, &[]).unwrap());
+        // without `--grouped` the blank line stays with its key
+        let sorted = r#"[dependencies]
+# async runtime
+async-std = "1" # trailing
+tokio = "1"
+# serialization
+a = "1"
+
+b = "1"
+serde = "1"
+serde_json = "1"
+"#;
+        assert_eq(sorted, super::sort_toml(input, matcher, false, &[]).unwrap());
+
+        let ordering = ["dependencies".to_owned()];
+        assert!(super::is_sorted(&super::parse(grouped).unwrap(), matcher, true, &ordering));
+        assert!(!super::is_sorted(&super::parse(grouped).unwrap(), MATCHER, true, &ordering));
+    }
+
+    #[test]
+    fn feature_values() {
+        let input = r#"[features]
+default = ["std", "dep:serde", "alloc"]
+std = [
+    "serde/std", # keep std
+    # the derive macros
+    "dep:serde_derive",
+    "alloc",
+]
+alloc = []
+
+[package.metadata]
+flags = ["b", "a"]
+"#;
+        let matcher = Matcher {
+            feature_values: true,
+            ..MATCHER
+        };
+        let sorted = r#"[features]
+default = ["alloc", "dep:serde", "std"]
+std = [
+    "alloc",
+    # the derive macros
+    "dep:serde_derive",
+    "serde/std", # keep std
+]
+alloc = []
+
+[package.metadata]
+flags = ["b", "a"]
+"#;
+        assert_eq(sorted, super::sort_toml(input, matcher, false, &[]).unwrap());
+        assert_eq(input, super::sort_toml(input, MATCHER, false, &[]).unwrap());
+
+        let ordering = ["features".to_owned(), "package".to_owned()];
+        assert!(super::is_sorted(&super::parse(sorted).unwrap(), matcher, false, &ordering));
+        assert!(!super::is_sorted(&super::parse(input).unwrap(), matcher, false, &ordering));
+        assert!(super::is_sorted(&super::parse(input).unwrap(), MATCHER, false, &ordering));
+    }
+
+    #[test]
+    fn grouped_comments_stay_with_their_key() {
+        let input = "[dependencies]\n\n# group\nc = \"3\"\n# b\nb = \"2\"\n\ne = \"5\"\n# d\nd = \"4\"\n";
+        let mut old = input.parse::<DocumentMut>().unwrap();
+        grouped_tables(old.as_table_mut(), old_sort_by_group);
+        assert_eq(
+            old.to_string(),
+            "[dependencies]\n\n# group\nb = \"2\"\nc = \"3\"\n\nd = \"4\"\ne = \"5\"\n",
+        );
+
+        let sorted = super::sort_toml(input, MATCHER, true, &[]).unwrap();
+        assert_eq(
+            sorted.to_string(),
+            "[dependencies]\n\n# group\n# b\nb = \"2\"\nc = \"3\"\n\n# d\nd = \"4\"\ne = \"5\"\n",
+        );
+    }
 }
@@ -1,3 +1,6 @@
+// Shared by the library and the binary tests, each uses only some helpers.
+#![allow(dead_code)]
+
 pub(crate) fn assert_eq<L: ToString, R: ToString>(left: L, right: R) {
     let left = left.to_string();
     let right = right.to_string();
@@ -8,3 +11,11 @@ pub(crate) fn assert_eq<L: ToString, R: ToString>(left: L, right: R) {
     #[cfg(not(windows))]
     similar_asserts::assert_eq!(left, right);
 }
+
+/// Creates a fresh empty directory for a test.
+pub(crate) fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("cargo-sort-ng-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,85 @@
+//! The sorting and formatting of `cargo sort-fix` for a web page, built with
+//! `wasm-pack build --target web`.
+
+use cargo_sort_fix::{Config, ConfigBuilder, Error, check_manifest};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Why [`sort`] failed, thrown as an exception on the JavaScript side.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortError {
+    /// `"config"` if the config was not valid, `"toml"` if the input was not.
+    pub kind: String,
+
+    /// The error as the command line prints it.
+    pub message: String,
+
+    /// The byte offset in the input where a toml error starts.
+    pub start: Option<usize>,
+
+    /// The byte offset in the input where a toml error ends.
+    pub end: Option<usize>,
+}
+
+/// Sorts and formats the manifest `input`.
+///
+/// `config` is a JSON object with the keys of `tomlfmt.toml`, an empty string
+/// uses the defaults.
+///
+/// ## Errors
+/// If `config` is not a valid config or `input` is not valid toml.
+#[wasm_bindgen]
+pub fn sort(input: &str, config: &str) -> Result<String, SortError> {
+    let config = if config.trim().is_empty() {
+        Config::default()
+    } else {
+        serde_json::from_str::<Config>(config)
+            .map_err(|err| err.to_string())
+            .and_then(|config| ConfigBuilder::from(config).build().map_err(|err| err.to_string()))
+            .map_err(|message| SortError {
+                kind: "config".to_owned(),
+                message,
+                start: None,
+                end: None,
+            })?
+    };
+    let report = check_manifest(input, &config).map_err(|err| {
+        let span = match &err {
+            Error::Parse { source, .. } => source.span(),
+            _ => None,
+        };
+        SortError {
+            kind: "toml".to_owned(),
+            message: err.to_string(),
+            start: span.as_ref().map(|span| span.start),
+            end: span.map(|span| span.end),
+        }
+    })?;
+    Ok(report.output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::sort;
+
+    #[test]
+    fn sorts() {
+        let input = "[package]\nname = \"a\"\n\n[dependencies]\nb = \"1\"\na = \"1\"\n";
+        let sorted = "[package]\nname = \"a\"\n\n[dependencies]\na = \"1\"\nb = \"1\"\n";
+        assert_eq!(sort(input, "").unwrap(), sorted);
+        assert_eq!(sort(input, r#"{ "indent_count": 2 }"#).unwrap(), sorted);
+    }
+
+    #[test]
+    fn errors() {
+        let err = sort("[package]\n", r#"{ "indnet_count": 2 }"#).unwrap_err();
+        assert_eq!((err.kind.as_str(), err.start, err.end), ("config", None, None));
+        assert!(err.message.contains("indnet_count"), "{}", err.message);
+        let err = sort("[package]\n", r#"{ "grouped": true, "key_value_newlines": false }"#).unwrap_err();
+        assert_eq!(err.kind, "config");
+
+        let err = sort("[package]\nname = \n", "").unwrap_err();
+        assert_eq!(err.kind, "toml");
+        assert_eq!((err.start, err.end), (Some(17), Some(18)));
+    }
+}